@@ -0,0 +1,68 @@
+//! Filters a 1080p screenshot with the single-threaded reference and with the parallel
+//! SIMD path the CPU renderers use: `cargo bench --bench filter`.
+
+use std::hint::black_box;
+
+use ban_shadow::filter::{self, PixelFormat};
+use ban_shadow::lut::{Interpolation, Lut3d, LutStage};
+use ban_shadow::params::FilterParams;
+use ban_shadow::render;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use half::f16;
+use image::imageops::{self, FilterType};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+/// `before.png` scaled to 1080p, in each format the capture can deliver.
+fn frame(format: PixelFormat) -> Vec<u8> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/before.png");
+    let image = image::open(path).expect("before.png").to_rgba8();
+    let rgba = imageops::resize(&image, WIDTH, HEIGHT, FilterType::Triangle).into_raw();
+    match format {
+        PixelFormat::Rgba8 => rgba,
+        PixelFormat::Bgra8 => rgba
+            .chunks_exact(4)
+            .flat_map(|px| [px[2], px[1], px[0], px[3]])
+            .collect(),
+        PixelFormat::Rgba16F => rgba
+            .iter()
+            .flat_map(|&c| f16::from_f32(f32::from(c) / 255.0).to_le_bytes())
+            .collect(),
+    }
+}
+
+fn filter_1080p(c: &mut Criterion) {
+    let params = FilterParams::default();
+    let lut = LutStage {
+        lut: Lut3d::identity(33),
+        interpolation: Interpolation::Tetrahedral,
+        replaces_curve: false,
+    };
+    for (name, format, lut) in [
+        ("bgra8", PixelFormat::Bgra8, None),
+        ("bgra8_lut", PixelFormat::Bgra8, Some(&lut)),
+        ("rgba16f", PixelFormat::Rgba16F, None),
+    ] {
+        let source = frame(format);
+        let mut buffer = source.clone();
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(u64::from(WIDTH * HEIGHT)));
+        group.bench_function("reference", |b| {
+            b.iter(|| {
+                buffer.copy_from_slice(&source);
+                filter::apply_graded(black_box(&mut buffer), format, &params, lut).unwrap();
+            })
+        });
+        group.bench_function("parallel", |b| {
+            b.iter(|| {
+                buffer.copy_from_slice(&source);
+                render::apply_parallel(black_box(&mut buffer), format, &params, lut).unwrap();
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, filter_1080p);
+criterion_main!(benches);
@@ -1,54 +1,108 @@
-use std::{ffi::CString, sync::Arc, thread, time::Duration};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use half::f16;
 use raw_window_handle::HasWindowHandle;
 use windows::Win32::{
-    Foundation::{HMODULE, HWND},
+    Foundation::{HMODULE, HWND, POINT, RECT},
     Graphics::{
         Direct3D::{
-            D3D_DRIVER_TYPE_HARDWARE, D3D_FEATURE_LEVEL, D3D_FEATURE_LEVEL_11_0,
-            D3D_FEATURE_LEVEL_11_1, D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+            D3D_DRIVER_TYPE, D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP, D3D_FEATURE_LEVEL,
+            D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_11_1, D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
             Fxc::{D3DCOMPILE_ENABLE_STRICTNESS, D3DCOMPILE_OPTIMIZATION_LEVEL3, D3DCompile},
+            ID3DBlob,
         },
         Direct3D11::{
+            D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_SHADER_RESOURCE, D3D11_BUFFER_DESC,
             D3D11_COMPARISON_FUNC, D3D11_CREATE_DEVICE_BGRA_SUPPORT,
             D3D11_FILTER_MIN_MAG_MIP_LINEAR, D3D11_SAMPLER_DESC, D3D11_SDK_VERSION,
-            D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_VIEWPORT, D3D11CreateDevice, ID3D11Device,
-            ID3D11DeviceContext, ID3D11PixelShader, ID3D11RenderTargetView, ID3D11SamplerState,
-            ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11VertexShader,
+            D3D11_SUBRESOURCE_DATA, D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_TEXTURE2D_DESC,
+            D3D11_TEXTURE3D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_IMMUTABLE, D3D11_VIEWPORT,
+            D3D11CreateDevice, ID3D11Buffer, ID3D11Device, ID3D11DeviceContext, ID3D11PixelShader,
+            ID3D11RenderTargetView, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D,
+            ID3D11Texture3D, ID3D11VertexShader,
+        },
+        Dwm::{DWMWA_EXTENDED_FRAME_BOUNDS, DwmGetWindowAttribute},
+        Dxgi::Common::{
+            DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R16G16B16A16_FLOAT,
+            DXGI_SAMPLE_DESC,
         },
-        Dxgi::Common::{DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC},
         Dxgi::{
             DXGI_PRESENT, DXGI_SCALING_STRETCH, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_CHAIN_FLAG,
             DXGI_SWAP_CHAIN_FULLSCREEN_DESC, DXGI_SWAP_EFFECT_FLIP_DISCARD,
             DXGI_USAGE_RENDER_TARGET_OUTPUT, IDXGIAdapter, IDXGIDevice, IDXGIFactory2,
             IDXGIKeyedMutex, IDXGISwapChain1,
         },
+        Gdi::ClientToScreen,
     },
     UI::WindowsAndMessaging::{
-        GWL_EXSTYLE, GetWindowLongPtrW, HWND_TOPMOST, SWP_ASYNCWINDOWPOS, SWP_NOACTIVATE,
-        SWP_NOMOVE, SWP_NOSIZE, SetWindowDisplayAffinity, SetWindowLongPtrW, SetWindowPos,
-        WDA_EXCLUDEFROMCAPTURE, WS_EX_LAYERED, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_EX_TRANSPARENT,
+        GWL_EXSTYLE, GetClientRect, GetWindowLongPtrW, HWND_TOPMOST, IsWindow, SWP_ASYNCWINDOWPOS,
+        SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SetWindowDisplayAffinity, SetWindowLongPtrW,
+        SetWindowPos, WDA_EXCLUDEFROMCAPTURE, WS_EX_LAYERED, WS_EX_TOOLWINDOW, WS_EX_TOPMOST,
+        WS_EX_TRANSPARENT,
     },
 };
 use windows::core::{Interface, PCSTR};
 use windows_capture::settings::{
     ColorFormat, CursorCaptureSettings, DirtyRegionSettings, DrawBorderSettings,
     MinimumUpdateIntervalSettings, SecondaryWindowSettings, Settings as CaptureSettings,
+    TryIntoCaptureItemWithType,
+};
+use windows_capture::{
+    capture::{CaptureControl, GraphicsCaptureApiHandler},
+    monitor::Monitor,
+    window::Window as CaptureWindow,
 };
-use windows_capture::{capture::GraphicsCaptureApiHandler, monitor::Monitor};
 use winit::{
     application::ApplicationHandler,
-    platform::windows::WindowAttributesExtWindows,
-    window::{Window, WindowAttributes},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoopProxy},
+    platform::windows::{MonitorHandleExtWindows, WindowAttributesExtWindows},
+    window::{Window, WindowAttributes, WindowId},
 };
 
-use crate::capture::{CaptureBuffer, Capturer, SharedHandle};
+use crate::capture::{
+    CaptureBuffer, CaptureDevice, Capturer, DeviceMode, GraphicsCaptureSource, SharedSlot,
+    dxgi_format,
+};
+use crate::config::Config;
+use crate::config_watch::{ConfigWatcher, FileWatcher};
+use crate::custom_shader;
+use crate::error::OverlayError;
+use crate::exposure::ExposureController;
+use crate::filter::PixelFormat;
+use crate::flash::{FlashDetector, FlashSettings};
+use crate::flash_safety::{FlashSafety, SafetySettings};
+use crate::foreground;
+use crate::global_hotkeys;
+use crate::hotkeys::{Enhancement, HotkeyAction};
+use crate::luma_probe::{LumaProbe, ProbeInput};
+use crate::lut::{Lut3d, LutStage};
+use crate::mailbox::MailboxStats;
+use crate::options::OverlayOptions;
+use crate::params::{FilterParams, ShaderParams};
+use crate::pass_graph::{self, CompiledPass, GraphInput, PassGraph};
+use crate::passes::{self, Pass};
+use crate::profiles::{ForegroundApp, Profile, ProfileAction};
+use crate::render::{CpuRenderer, FrameRenderer, RendererKind};
+use crate::source::{Frame, FramePixels, FrameSource};
+use crate::target::{
+    CaptureTarget, MonitorInfo, MonitorSelection, OverlayLayout, Rect, WindowInfo, select_monitors,
+};
 
-const SHADER_SOURCE: &str = include_str!("shader.hlsl");
+pub(crate) const SHADER_SOURCE: &str = concat!(
+    include_str!("shader_interface.hlsl"),
+    include_str!("shader.hlsl")
+);
 
 struct App {
     window: Arc<Window>,
-    capture_buffer: CaptureBuffer,
+    source: Box<dyn FrameSource>,
+    pending_frame: Option<Frame>,
     device: ID3D11Device,
     context: ID3D11DeviceContext,
     swapchain: IDXGISwapChain1,
@@ -56,29 +110,72 @@ struct App {
     vs: ID3D11VertexShader,
     ps: ID3D11PixelShader,
     sampler: ID3D11SamplerState,
-    shared_handle: Option<SharedHandle>,
-    shared_texture: Option<ID3D11Texture2D>,
-    shared_srv: Option<ID3D11ShaderResourceView>,
-    shared_mutex: Option<IDXGIKeyedMutex>,
-    shared_size: (u32, u32),
-    last_frame_id: u64,
+    /// The curve in use, or the strongest lift allowed with adaptive exposure.
+    params: FilterParams,
+    params_buffer: ID3D11Buffer,
+    params_dirty: bool,
+    /// Present while adaptive exposure or one of the flash modes needs frames measured.
+    meter: Option<FrameMeter>,
+    lut: Option<LutTexture>,
+    graph: PassGraph,
+    /// Whether the frame being drawn is new rather than a repaint.
+    fresh_frame: bool,
+    /// Opened slots of the capture ring, reopened when the capture size changes.
+    shared_textures: Vec<SharedTexture>,
+    capture_buffer: Option<CaptureBuffer>,
+    capture: Option<CaptureControl<Capturer, anyhow::Error>>,
+    /// Set after a resize so the last frame is drawn again instead of leaving a blank window.
+    needs_repaint: bool,
+    /// Cleared by the toggle hotkey, which hides the overlay.
+    enabled: bool,
+    upload_texture: Option<ID3D11Texture2D>,
+    upload_srv: Option<ID3D11ShaderResourceView>,
+    upload_layout: (u32, u32, PixelFormat),
+    target_window: Option<HWND>,
+    layout: Option<OverlayLayout>,
     size: winit::dpi::PhysicalSize<u32>,
+    /// Filters CPU frames before they are drawn, bypassing the filter shader; set when
+    /// there is no hardware device or `--renderer cpu` asks for it.
+    cpu_renderer: Option<CpuRenderer>,
+    /// Draws what `cpu_renderer` filtered into the viewport; set along with it.
+    copy_ps: Option<ID3D11PixelShader>,
 }
 
 impl App {
-    async fn new(window: Arc<Window>, capture_buffer: CaptureBuffer) -> Self {
+    /// Renders on `device` when given, otherwise on a device of its own. With
+    /// `cpu_renderer` that device only presents, so WARP will do.
+    async fn new(
+        window: Arc<Window>,
+        source: Box<dyn FrameSource>,
+        params: FilterParams,
+        device: Option<CaptureDevice>,
+        cpu_renderer: Option<CpuRenderer>,
+    ) -> Result<Self, OverlayError> {
         let size = window.inner_size();
-        let hwnd = window_to_hwnd(&window).expect("Failed to get HWND");
-        let (device, context) = create_d3d_device().expect("Failed to create D3D11 device");
-        let swapchain = create_swapchain(&device, hwnd, size).expect("Failed to create swapchain");
-        let rtv = create_render_target_view(&device, &swapchain)
-            .expect("Failed to create render target view");
-        let (vs, ps) = create_shaders(&device).expect("Failed to create shaders");
-        let sampler = create_sampler(&device).expect("Failed to create sampler");
+        let hwnd = window_to_hwnd(&window).map_err(OverlayError::Swapchain)?;
+        let driver_type = match cpu_renderer {
+            Some(_) => D3D_DRIVER_TYPE_WARP,
+            None => D3D_DRIVER_TYPE_HARDWARE,
+        };
+        let (device, context) = match device {
+            Some(CaptureDevice { device, context }) => (device, context),
+            None => create_d3d_device(driver_type).map_err(OverlayError::Device)?,
+        };
+        let swapchain = create_swapchain(&device, hwnd, size).map_err(OverlayError::Swapchain)?;
+        let rtv =
+            create_render_target_view(&device, &swapchain).map_err(OverlayError::Swapchain)?;
+        let (vs, ps) = create_shaders(&device).map_err(OverlayError::Shader)?;
+        let copy_ps = match cpu_renderer {
+            Some(_) => Some(create_pixel_shader(&device, "ps_copy").map_err(OverlayError::Shader)?),
+            None => None,
+        };
+        let sampler = create_sampler(&device).map_err(OverlayError::Device)?;
+        let params_buffer = create_params_buffer(&device).map_err(OverlayError::Device)?;
 
         let app = Self {
             window,
-            capture_buffer,
+            source,
+            pending_frame: None,
             device,
             context,
             swapchain,
@@ -86,35 +183,59 @@ impl App {
             vs,
             ps,
             sampler,
-            shared_handle: None,
-            shared_texture: None,
-            shared_srv: None,
-            shared_mutex: None,
-            shared_size: (0, 0),
-            last_frame_id: 0,
+            params: params.clamped(),
+            params_buffer,
+            params_dirty: true,
+            meter: None,
+            lut: None,
+            graph: PassGraph::filter_only(),
+            fresh_frame: false,
+            shared_textures: Vec::new(),
+            capture_buffer: None,
+            capture: None,
+            needs_repaint: false,
+            enabled: true,
+            upload_texture: None,
+            upload_srv: None,
+            upload_layout: (0, 0, PixelFormat::default()),
+            target_window: None,
+            layout: None,
             size,
+            cpu_renderer,
+            copy_ps,
         };
-        app.set_viewport(size);
-        app
+        app.set_viewport();
+        Ok(app)
+    }
+
+    fn set_viewport(&self) {
+        unsafe {
+            self.context.RSSetViewports(Some(&[self.viewport()]));
+        }
     }
 
-    fn set_viewport(&self, size: winit::dpi::PhysicalSize<u32>) {
-        let viewport = D3D11_VIEWPORT {
-            TopLeftX: 0.0,
-            TopLeftY: 0.0,
-            Width: size.width as f32,
-            Height: size.height as f32,
+    /// Where the image goes in the back buffer.
+    fn viewport(&self) -> D3D11_VIEWPORT {
+        let (origin, size) = match self.layout {
+            Some(layout) => (layout.viewport_origin, layout.viewport_size),
+            None => (
+                (0.0, 0.0),
+                (self.size.width as f32, self.size.height as f32),
+            ),
+        };
+        D3D11_VIEWPORT {
+            TopLeftX: origin.0,
+            TopLeftY: origin.1,
+            Width: size.0,
+            Height: size.1,
             MinDepth: 0.0,
             MaxDepth: 1.0,
-        };
-        unsafe {
-            self.context.RSSetViewports(Some(&[viewport]));
         }
     }
 
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) -> Result<(), OverlayError> {
         if new_size.width == 0 || new_size.height == 0 {
-            return;
+            return Ok(());
         }
         self.size = new_size;
         unsafe {
@@ -126,157 +247,1354 @@ impl App {
                 DXGI_SWAP_CHAIN_FLAG(0),
             );
         }
-        self.rtv = create_render_target_view(&self.device, &self.swapchain).expect("RTV resize");
-        self.set_viewport(new_size);
+        self.rtv = create_render_target_view(&self.device, &self.swapchain)
+            .map_err(OverlayError::Swapchain)?;
+        self.set_viewport();
+        self.needs_repaint = true;
+        Ok(())
     }
 
-    fn render(&mut self) {
-        let (handle, width, height, frame_id) = {
-            let shared = self.capture_buffer.lock().unwrap();
-            (shared.handle, shared.width, shared.height, shared.frame_id)
+    /// Keeps the overlay over the target window's client area.
+    ///
+    /// Returns `false` once the target window has been closed.
+    fn track_target(&mut self) -> bool {
+        let Some(hwnd) = self.target_window else {
+            return true;
         };
-        let Some(handle) = handle else {
+        if !unsafe { IsWindow(Some(hwnd)) }.as_bool() {
+            return false;
+        }
+        let layout = target_layout(hwnd).ok().flatten();
+        if layout == self.layout {
+            return true;
+        }
+        self.layout = layout;
+        match layout {
+            Some(layout) => {
+                self.window.set_outer_position(PhysicalPosition::new(
+                    layout.position.0,
+                    layout.position.1,
+                ));
+                let _ = self
+                    .window
+                    .request_inner_size(PhysicalSize::new(layout.size.0, layout.size.1));
+                self.window.set_visible(self.enabled);
+            }
+            // Minimized; the overlay would otherwise float over whatever is behind it.
+            None => self.window.set_visible(false),
+        }
+        self.set_viewport();
+        true
+    }
+
+    /// Switches to new curve parameters and repaints the last frame with them.
+    fn set_params(&mut self, params: FilterParams) {
+        self.params = params.clamped();
+        self.params_dirty = true;
+        self.needs_repaint = true;
+        self.window.request_redraw();
+    }
+
+    /// Turns adaptive exposure on or off; stays off if the probe cannot be created.
+    fn set_adaptive(&mut self, adaptive: bool) {
+        let current = self
+            .meter
+            .as_ref()
+            .is_some_and(|meter| meter.exposure.is_some());
+        if adaptive != current {
+            self.change_meter(|meter| {
+                meter.exposure = adaptive.then(ExposureController::default);
+            });
+        }
+    }
+
+    /// Sets how long flash dampening lasts; zero turns it off.
+    fn set_flash_decay(&mut self, decay: Duration) {
+        let current = self
+            .meter
+            .as_ref()
+            .and_then(|meter| meter.flash.as_ref())
+            .map_or(Duration::ZERO, |flash| flash.settings().decay);
+        if decay != current {
+            self.change_meter(|meter| {
+                meter.flash = (!decay.is_zero()).then(|| {
+                    FlashDetector::new(FlashSettings {
+                        decay,
+                        ..FlashSettings::default()
+                    })
+                });
+            });
+        }
+    }
+
+    /// Grades with `lut` from now on; keeps the previous grade if it cannot be uploaded.
+    fn set_lut(&mut self, lut: Option<&Arc<LutStage>>) {
+        if self.lut.as_ref().map(|texture| &texture.stage) == lut {
             return;
+        }
+        self.lut = match lut {
+            Some(stage) => match create_lut_texture(&self.device, &stage.lut) {
+                Ok(srv) => Some(LutTexture {
+                    stage: stage.clone(),
+                    srv,
+                }),
+                Err(err) => {
+                    eprintln!("Failed to upload LUT: {err:?}");
+                    return;
+                }
+            },
+            None => None,
         };
-        if frame_id == self.last_frame_id {
-            return;
+        self.params_dirty = true;
+        self.needs_repaint = true;
+        self.window.request_redraw();
+    }
+
+    /// Draws with the pixel shader compiled into `blob` from now on; keeps the current one
+    /// if the device rejects it.
+    fn set_pixel_shader(&mut self, blob: &ID3DBlob) {
+        let mut ps = None;
+        let result = unsafe {
+            self.device
+                .CreatePixelShader(blob_bytes(blob), None, Some(&mut ps))
+        };
+        match (result, ps) {
+            (Ok(()), Some(ps)) => self.ps = ps,
+            (result, _) => {
+                eprintln!("Failed to create pixel shader: {result:?}");
+                return;
+            }
         }
-        if (self.shared_handle != Some(handle) || self.shared_size != (width, height))
-            && let Err(err) = self.open_shared_texture(handle, width, height)
-        {
-            eprintln!("Failed to open shared texture: {err:?}");
-            return;
+        self.needs_repaint = true;
+        self.window.request_redraw();
+    }
+
+    /// Draws through `passes` from now on; keeps the current chain if the device rejects
+    /// them.
+    fn set_passes(&mut self, passes: &[CompiledPass]) {
+        match PassGraph::new(&self.device, passes) {
+            Ok(graph) => self.graph = graph,
+            Err(err) => {
+                eprintln!("Failed to create passes: {err:?}");
+                return;
+            }
         }
-        let Some(shared_srv) = &self.shared_srv else {
+        self.needs_repaint = true;
+        self.window.request_redraw();
+    }
+
+    /// Turns the photosensitive safety mode on or off.
+    fn set_flash_safety(&mut self, enabled: bool) {
+        let current = self
+            .meter
+            .as_ref()
+            .is_some_and(|meter| meter.safety.is_some());
+        if enabled != current {
+            self.change_meter(|meter| {
+                meter.safety = enabled.then(|| FlashSafety::new(SafetySettings::default()));
+            });
+        }
+    }
+
+    /// Creates the probe on first use and drops it once nothing needs measurements.
+    fn change_meter(&mut self, change: impl FnOnce(&mut FrameMeter)) {
+        // The CPU path never draws through the probe, so there is nothing to measure.
+        if self.cpu_renderer.is_some() {
             return;
+        }
+        let mut meter = match self.meter.take() {
+            Some(meter) => meter,
+            None => match LumaProbe::new(&self.device) {
+                Ok(probe) => FrameMeter {
+                    probe,
+                    exposure: None,
+                    flash: None,
+                    safety: None,
+                    last_update: None,
+                },
+                Err(err) => {
+                    eprintln!("Frame measurements unavailable: {err:?}");
+                    return;
+                }
+            },
         };
-        let Some(shared_mutex) = &self.shared_mutex else {
+        change(&mut meter);
+        let needed = meter.exposure.is_some() || meter.flash.is_some() || meter.safety.is_some();
+        self.meter = needed.then_some(meter);
+        self.params_dirty = true;
+        self.needs_repaint = true;
+        self.window.request_redraw();
+    }
+
+    /// The curve to draw with: the adaptive one once it has measured a frame.
+    fn effective_params(&self) -> FilterParams {
+        self.meter
+            .as_ref()
+            .and_then(|meter| meter.exposure.as_ref()?.current())
+            .unwrap_or(self.params)
+    }
+
+    fn flash_damping(&self) -> f32 {
+        self.meter
+            .as_ref()
+            .and_then(|meter| meter.flash.as_ref())
+            .map_or(0.0, FlashDetector::damping)
+    }
+
+    /// Softening strength and level of the photosensitive safety mode.
+    fn safety(&self) -> (f32, f32) {
+        self.meter
+            .as_ref()
+            .and_then(|meter| meter.safety.as_ref())
+            .map_or((0.0, 0.0), |safety| (safety.strength(), safety.level()))
+    }
+
+    /// Measures `input` and updates the adaptive curve and the flash modes from it.
+    /// Keeps repainting until the newest frame has been measured, the curve has settled,
+    /// any flash has faded and the safety mode has stopped softening, even if no new
+    /// frames arrive.
+    fn update_measurements(&mut self, input: &ID3D11ShaderResourceView) {
+        let Some(meter) = &mut self.meter else {
             return;
         };
+        let probe_input = ProbeInput {
+            vs: &self.vs,
+            sampler: &self.sampler,
+            params: &self.params_buffer,
+            frame: input,
+        };
+        if let Some(grid) = meter
+            .probe
+            .measure(&self.context, &probe_input, self.fresh_frame)
+        {
+            let now = Instant::now();
+            let elapsed = meter.last_update.map_or(Duration::ZERO, |last| now - last);
+            meter.last_update = Some(now);
+            let histogram = grid.histogram();
+            if let Some(controller) = &mut meter.exposure {
+                let before = controller.current();
+                let params = controller.update(&histogram, self.params, elapsed);
+                self.params_dirty |= before != Some(params);
+            }
+            if let Some(flash) = &mut meter.flash
+                && let Some(mean) = histogram.mean()
+            {
+                let before = flash.damping();
+                self.params_dirty |= flash.update(mean, elapsed) != before;
+            }
+            if let Some(safety) = &mut meter.safety {
+                let before = (safety.strength(), safety.level());
+                safety.update(&grid, elapsed);
+                self.params_dirty |= (safety.strength(), safety.level()) != before;
+            }
+        }
+        let exposure_moving = meter
+            .exposure
+            .as_ref()
+            .is_some_and(|controller| !controller.is_settled());
+        let flash_fading = meter
+            .flash
+            .as_ref()
+            .is_some_and(|flash| flash.damping() > 0.0);
+        let safety_fading = meter
+            .safety
+            .as_ref()
+            .is_some_and(|safety| safety.strength() > 0.0);
+        if !meter.probe.is_up_to_date() || exposure_moving || flash_fading || safety_fading {
+            self.needs_repaint = true;
+            self.window.request_redraw();
+        }
+        self.set_viewport();
+    }
 
-        if unsafe { shared_mutex.AcquireSync(1, 0) }.is_err() {
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        // A window target that is minimized stays hidden until it is restored.
+        let target_visible = self.target_window.is_none() || self.layout.is_some();
+        self.window.set_visible(enabled && target_visible);
+        if enabled {
+            self.needs_repaint = true;
+            self.window.request_redraw();
+        }
+    }
+
+    /// Draws the newest frame, if any. Returns `true` when a frame is still waiting to be
+    /// drawn and another redraw should be requested.
+    fn render(&mut self) -> bool {
+        let frame = match self.source.next_frame() {
+            Ok(frame) => frame.or_else(|| self.pending_frame.take()),
+            Err(err) => {
+                eprintln!("Failed to read frame: {err:?}");
+                return false;
+            }
+        };
+        self.fresh_frame = frame.is_some();
+        let frame = match frame {
+            Some(frame) => Some(frame),
+            None if self.needs_repaint => self.source.repeat_frame().unwrap_or_else(|err| {
+                eprintln!("Failed to repeat frame: {err:?}");
+                None
+            }),
+            None => None,
+        };
+        let Some(frame) = frame else {
+            return false;
+        };
+        self.needs_repaint = false;
+        match &frame.pixels {
+            FramePixels::Shared(shared) => {
+                let slot = shared.slot.clone();
+                self.render_shared(slot, frame);
+            }
+            FramePixels::Cpu(_) | FramePixels::CpuSlot(_) if self.cpu_renderer.is_some() => {
+                if let Err(err) = self.draw_filtered(&frame) {
+                    eprintln!("Failed to filter frame: {err:?}");
+                    return false;
+                }
+                self.present();
+            }
+            FramePixels::Cpu(_) | FramePixels::CpuSlot(_) => {
+                let pixels = frame.pixels.as_cpu().unwrap_or_default();
+                if let Err(err) = self.upload_frame(&frame, pixels) {
+                    eprintln!("Failed to upload frame: {err:?}");
+                    return false;
+                }
+                let Some(upload_srv) = self.upload_srv.clone() else {
+                    return false;
+                };
+                self.draw(&upload_srv, (frame.width, frame.height));
+                self.present();
+            }
+        }
+        self.pending_frame.is_some()
+    }
+
+    /// Draws a slot of the capture ring. Dropping `frame` afterwards hands the slot back.
+    fn render_shared(&mut self, slot: SharedSlot, frame: Frame) {
+        let shared = match self.shared_texture(slot, frame.width, frame.height) {
+            Ok(shared) => shared,
+            Err(err) => {
+                eprintln!("Failed to open shared texture: {err:?}");
+                return;
+            }
+        };
+
+        if let Some(mutex) = &shared.mutex
+            && unsafe { mutex.AcquireSync(0, 0) }.is_err()
+        {
+            // The copy into this slot has not been released yet; retry on the next redraw.
+            self.pending_frame = Some(frame);
             return;
         }
 
+        self.draw(&shared.srv, (frame.width, frame.height));
+
+        if let Some(mutex) = &shared.mutex {
+            let _ = unsafe { mutex.ReleaseSync(0) };
+        }
+        self.present();
+    }
+
+    /// Stops the capture thread feeding this overlay and waits for it to finish.
+    fn stop_capture(&mut self) {
+        if let Some(capture) = self.capture.take()
+            && let Err(err) = capture.stop()
+        {
+            eprintln!("Failed to stop capture: {err}");
+        }
+    }
+
+    /// Mailbox counters of the capture feeding this overlay, if it is a live capture.
+    fn frame_stats(&self) -> Option<MailboxStats> {
+        let shared = self.capture_buffer.as_ref()?.lock().unwrap();
+        Some(match shared.device_mode {
+            Some(DeviceMode::Readback) => shared.cpu_frames.stats(),
+            _ => shared.mailbox.stats(),
+        })
+    }
+
+    /// Presents on the next vertical blank, so rendering never outpaces the display.
+    fn present(&self) {
+        let _ = unsafe { self.swapchain.Present(1, DXGI_PRESENT(0)) };
+    }
+
+    fn draw(&mut self, input: &ID3D11ShaderResourceView, input_size: (u32, u32)) {
+        self.update_measurements(input);
+        if self.params_dirty {
+            let mut shader_params = self.effective_params().to_shader();
+            shader_params.flash_damping = self.flash_damping();
+            (shader_params.safety, shader_params.safety_level) = self.safety();
+            if let Some(lut) = &self.lut {
+                shader_params = shader_params.with_lut(&lut.stage);
+            }
+            unsafe {
+                self.context.UpdateSubresource(
+                    &self.params_buffer,
+                    0,
+                    None,
+                    (&raw const shader_params).cast(),
+                    0,
+                    0,
+                );
+            }
+            self.params_dirty = false;
+        }
+
         unsafe {
             self.context
-                .OMSetRenderTargets(Some(&[Some(self.rtv.clone())]), None);
+                .ClearRenderTargetView(&self.rtv, &[0.0, 0.0, 0.0, 0.0]);
+        }
+        let graph_input = GraphInput {
+            vs: &self.vs,
+            filter_ps: &self.ps,
+            sampler: &self.sampler,
+            params: &self.params_buffer,
+            lut: self.lut.as_ref().map(|lut| &lut.srv),
+            frame: input,
+            frame_size: input_size,
+            output: &self.rtv,
+            viewport: self.viewport(),
+        };
+        if let Err(err) = self.graph.draw(&self.device, &self.context, &graph_input) {
+            eprintln!("Failed to draw passes: {err:?}");
+        }
+    }
+
+    /// Filters a CPU frame with [`Self::cpu_renderer`], uploads the result and draws it
+    /// into the viewport with [`Self::copy_ps`], scaled like the shader path scales frames.
+    fn draw_filtered(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        let lut = self.lut.as_ref().map(|lut| lut.stage.clone());
+        let Some(mut renderer) = self.cpu_renderer.take() else {
+            return Ok(());
+        };
+        let uploaded = renderer
+            .filter(frame, &self.params, lut.as_ref())
+            .and_then(|pixels| self.upload_frame(frame, pixels));
+        self.cpu_renderer = Some(renderer);
+        uploaded?;
+        let (Some(copy_ps), Some(upload_srv)) = (&self.copy_ps, &self.upload_srv) else {
+            return Ok(());
+        };
+        unsafe {
             self.context
                 .ClearRenderTargetView(&self.rtv, &[0.0, 0.0, 0.0, 0.0]);
+            self.context
+                .OMSetRenderTargets(Some(&[Some(self.rtv.clone())]), None);
+            self.context.RSSetViewports(Some(&[self.viewport()]));
             self.context.IASetInputLayout(None);
             self.context
                 .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
             self.context.VSSetShader(&self.vs, None);
-            self.context.PSSetShader(&self.ps, None);
-            self.context
-                .PSSetShaderResources(0, Some(&[Some(shared_srv.clone())]));
+            self.context.PSSetShader(copy_ps, None);
             self.context
                 .PSSetSamplers(0, Some(&[Some(self.sampler.clone())]));
+            self.context
+                .PSSetShaderResources(0, Some(&[Some(upload_srv.clone())]));
             self.context.Draw(3, 0);
+            self.context.PSSetShaderResources(0, Some(&[None]));
         }
+        Ok(())
+    }
 
-        let _ = unsafe { shared_mutex.ReleaseSync(0) };
-        let _ = unsafe { self.swapchain.Present(0, DXGI_PRESENT(0)) };
-        self.last_frame_id = frame_id;
+    /// Copies a CPU frame into a texture the pixel shader can sample.
+    fn upload_frame(&mut self, frame: &Frame, pixels: &[u8]) -> anyhow::Result<()> {
+        let layout = (frame.width, frame.height, frame.format);
+        if self.upload_texture.is_none() || self.upload_layout != layout {
+            let desc = D3D11_TEXTURE2D_DESC {
+                Width: frame.width,
+                Height: frame.height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: dxgi_format(frame.format),
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
+                CPUAccessFlags: 0,
+                MiscFlags: 0,
+            };
+            let mut texture = None;
+            let mut srv = None;
+            unsafe {
+                self.device
+                    .CreateTexture2D(&desc, None, Some(&mut texture))?;
+            }
+            let texture =
+                texture.ok_or_else(|| anyhow::anyhow!("Failed to create upload texture"))?;
+            unsafe {
+                self.device
+                    .CreateShaderResourceView(&texture, None, Some(&mut srv))?;
+            }
+            self.upload_texture = Some(texture);
+            self.upload_srv = srv;
+            self.upload_layout = layout;
+        }
+        let Some(texture) = &self.upload_texture else {
+            return Ok(());
+        };
+        let row_pitch = frame.width * frame.format.bytes_per_pixel() as u32;
+        unsafe {
+            self.context
+                .UpdateSubresource(texture, 0, None, pixels.as_ptr().cast(), row_pitch, 0);
+        }
+        Ok(())
     }
 
-    fn open_shared_texture(
+    fn shared_texture(
         &mut self,
-        handle: SharedHandle,
+        slot: SharedSlot,
         width: u32,
         height: u32,
-    ) -> anyhow::Result<()> {
-        let mut texture: Option<ID3D11Texture2D> = None;
-        unsafe {
-            self.device.OpenSharedResource(handle.0, &mut texture)?;
+    ) -> anyhow::Result<SharedTexture> {
+        // The capture thread recreates every slot when the size changes.
+        self.shared_textures
+            .retain(|shared| shared.size == (width, height));
+        if let Some(shared) = self
+            .shared_textures
+            .iter()
+            .find(|shared| shared.slot == slot)
+        {
+            return Ok(shared.clone());
         }
-        let texture = texture.ok_or_else(|| anyhow::anyhow!("Shared texture missing"))?;
-        let mutex: IDXGIKeyedMutex = texture.cast()?;
+
+        let (texture, mutex) = match &slot {
+            SharedSlot::Texture(texture) => (texture.0.clone(), None),
+            SharedSlot::Handle(handle) => {
+                let mut texture: Option<ID3D11Texture2D> = None;
+                unsafe {
+                    self.device.OpenSharedResource(handle.0, &mut texture)?;
+                }
+                let texture = texture.ok_or_else(|| anyhow::anyhow!("Shared texture missing"))?;
+                let mutex: IDXGIKeyedMutex = texture.cast()?;
+                (texture, Some(mutex))
+            }
+        };
         let mut srv = None;
         unsafe {
             self.device
                 .CreateShaderResourceView(&texture, None, Some(&mut srv))?;
         }
-        self.shared_texture = Some(texture);
-        self.shared_mutex = Some(mutex);
-        self.shared_srv = srv;
-        self.shared_handle = Some(handle);
-        self.shared_size = (width, height);
-        Ok(())
+        let srv = srv.ok_or_else(|| anyhow::anyhow!("Failed to create shared texture view"))?;
+        let shared = SharedTexture {
+            slot,
+            size: (width, height),
+            srv,
+            mutex,
+        };
+        self.shared_textures.push(shared.clone());
+        Ok(shared)
     }
 }
 
-#[derive(Default)]
+/// The luma probe and what is derived from its measurements.
+struct FrameMeter {
+    probe: LumaProbe,
+    exposure: Option<ExposureController>,
+    flash: Option<FlashDetector>,
+    safety: Option<FlashSafety>,
+    last_update: Option<Instant>,
+}
+
+/// A LUT uploaded as a 3D texture.
+struct LutTexture {
+    stage: Arc<LutStage>,
+    srv: ID3D11ShaderResourceView,
+}
+
+/// A capture ring slot opened on the render device.
+#[derive(Clone)]
+struct SharedTexture {
+    slot: SharedSlot,
+    size: (u32, u32),
+    srv: ID3D11ShaderResourceView,
+    mutex: Option<IDXGIKeyedMutex>,
+}
+
+/// How often the overlay checks whether a target window moved, resized or closed.
+const TARGET_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often `--frame-stats` prints the capture mailbox counters.
+const FRAME_STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// What messages about the CPU filter call it.
+const CPU_RENDERER: &str = "The CPU renderer";
+
+/// Wakes the event loop from other threads.
+#[derive(Debug)]
+pub enum OverlayEvent {
+    /// A new capture frame is ready for the overlay window.
+    FrameReady(WindowId),
+    /// A global hotkey was pressed.
+    Hotkey(HotkeyAction),
+    /// The config file was edited and still loads.
+    ConfigChanged(Box<Config>),
+    /// Another application came to the foreground.
+    Foreground(ForegroundApp),
+    /// The `--shader` file was edited.
+    ShaderChanged,
+    /// A capture thread stopped on an error; the overlay exits with it.
+    CaptureFailed(anyhow::Error),
+}
+
+/// The `--shader` file, compiled.
+struct CustomShader {
+    /// What `blob` was compiled from, so saves that change nothing are skipped.
+    source: String,
+    blob: ID3DBlob,
+}
+
+/// How long the renderer waits for the capture thread to offer its device.
+const CAPTURE_DEVICE_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct AppHandler {
-    app: Option<App>,
+    proxy: EventLoopProxy<OverlayEvent>,
+    apps: HashMap<WindowId, App>,
+    options: OverlayOptions,
+    enhancement: Enhancement,
+    config_watcher: Option<ConfigWatcher>,
+    custom_shader: Option<CustomShader>,
+    shader_watcher: Option<FileWatcher>,
+    passes: Vec<CompiledPass>,
+    watching_foreground: bool,
+    foreground: Option<ForegroundApp>,
+    /// The profile last applied; a new one only takes effect when the match changes, so
+    /// hotkey adjustments survive switching between windows of the same game.
+    active_profile: Option<String>,
+    hidden_by_profile: bool,
+    /// When to print the next `--frame-stats` report; `None` when disabled.
+    next_stats_report: Option<Instant>,
+    /// Filter on the CPU instead of with the pixel shader.
+    cpu_filter: bool,
+    /// Why the event loop was asked to exit, if it was not a clean shutdown.
+    error: Option<OverlayError>,
 }
 
-impl ApplicationHandler for AppHandler {
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        if self.app.is_some() {
-            return;
+impl AppHandler {
+    pub fn new(proxy: EventLoopProxy<OverlayEvent>, options: OverlayOptions) -> Self {
+        let next_stats_report = options
+            .frame_stats
+            .then(|| Instant::now() + FRAME_STATS_INTERVAL);
+        let enhancement = options.settings.enhancement();
+        Self {
+            proxy,
+            apps: HashMap::new(),
+            options,
+            enhancement,
+            config_watcher: None,
+            custom_shader: None,
+            shader_watcher: None,
+            passes: Vec::new(),
+            watching_foreground: false,
+            foreground: None,
+            active_profile: None,
+            hidden_by_profile: false,
+            next_stats_report,
+            cpu_filter: false,
+            error: None,
         }
-        let window = event_loop
-            .create_window(
-                WindowAttributes::default()
-                    .with_title("Ban-Shadow Overlay")
-                    .with_decorations(false)
-                    .with_transparent(true)
-                    .with_resizable(false)
-                    .with_skip_taskbar(true)
-                    .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None))),
-            )
-            .unwrap();
-        apply_click_through(&window).unwrap();
-
-        let primary_monitor = Monitor::primary().unwrap();
-        let capture_buffer = CaptureBuffer::default();
-        let settings = CaptureSettings::new(
-            primary_monitor,
-            CursorCaptureSettings::WithoutCursor,
-            DrawBorderSettings::WithoutBorder,
-            SecondaryWindowSettings::Exclude,
-            MinimumUpdateIntervalSettings::Custom(
-                Duration::from_secs(1) / primary_monitor.refresh_rate().unwrap(),
-            ),
-            DirtyRegionSettings::Default,
-            ColorFormat::Bgra8,
+    }
+
+    /// Call after the event loop returns; the error that stopped the overlay, if any.
+    pub fn finish(&mut self) -> Result<(), OverlayError> {
+        self.close_overlays();
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Stops the event loop, keeping the first error for [`Self::finish`] to return.
+    fn fail(&mut self, event_loop: &ActiveEventLoop, err: OverlayError) {
+        self.error.get_or_insert(err);
+        event_loop.exit();
+    }
+
+    /// Picks the CPU filter for `--renderer cpu`, and for `auto` when Direct3D 11 has no
+    /// hardware device, as in RDP sessions, VMs and with broken drivers. The CPU filter
+    /// cannot soften flashes, so it refuses the photosensitive safety mode.
+    fn choose_renderer(&mut self) -> Result<(), OverlayError> {
+        self.cpu_filter = match self.options.renderer {
+            RendererKind::Cpu => true,
+            RendererKind::Auto => match create_d3d_device(D3D_DRIVER_TYPE_HARDWARE) {
+                Ok(_) => false,
+                Err(err) => {
+                    eprintln!("No Direct3D 11 hardware device ({err:#}); filtering on the CPU");
+                    true
+                }
+            },
+            RendererKind::D3d11 | RendererKind::Wgpu => false,
+        };
+        if !self.cpu_filter {
+            return Ok(());
+        }
+        self.options
+            .settings
+            .check_flash_safety(CPU_RENDERER)
+            .map_err(OverlayError::Config)?;
+        eprintln!("Filtering on {}", CpuRenderer::default().description());
+        for feature in self.options.unsupported_features(false) {
+            eprintln!("{CPU_RENDERER} does not support {feature} yet; ignoring it");
+        }
+        Ok(())
+    }
+
+    /// Opens an overlay for the window target, or one per selected monitor.
+    fn open_overlays(&mut self, event_loop: &ActiveEventLoop) -> Result<(), OverlayError> {
+        if self.options.settings.target.is_window() {
+            return self.open_window_overlay(event_loop);
+        }
+        let monitors =
+            find_monitors(&self.options.settings.monitors).map_err(OverlayError::Capture)?;
+        for monitor in monitors {
+            self.open_monitor_overlay(event_loop, monitor)?;
+        }
+        Ok(())
+    }
+
+    fn close_overlays(&mut self) {
+        for (_, mut app) in self.apps.drain() {
+            app.stop_capture();
+        }
+    }
+
+    fn open_window_overlay(&mut self, event_loop: &ActiveEventLoop) -> Result<(), OverlayError> {
+        let target_window =
+            find_target_window(&self.options.settings.target).map_err(OverlayError::Capture)?;
+        let hwnd = HWND(target_window.as_raw_hwnd());
+        let layout = target_layout(hwnd).ok().flatten();
+
+        let attributes = match layout {
+            Some(layout) => overlay_attributes()
+                .with_position(PhysicalPosition::new(layout.position.0, layout.position.1))
+                .with_inner_size(PhysicalSize::new(layout.size.0, layout.size.1)),
+            None => overlay_attributes().with_visible(false),
+        };
+        let window = create_overlay_window(event_loop, attributes)?;
+
+        let capture_buffer = self.capture_buffer_for(window.id());
+        let refresh_rate = target_window
+            .monitor()
+            .and_then(|monitor| monitor.refresh_rate().ok())
+            .unwrap_or(60);
+        let capture = spawn_capture(
+            target_window,
+            self.capture_rate(refresh_rate),
             capture_buffer.clone(),
-        );
-        thread::Builder::new()
-            .name("capture".to_string())
-            .spawn(move || {
-                Capturer::start(settings).unwrap();
-            })
-            .unwrap();
+        )
+        .map_err(OverlayError::Capture)?;
+
+        let mut app = self.create_app(window, capture_buffer, capture)?;
+        app.target_window = Some(hwnd);
+        app.layout = layout;
+        app.set_viewport();
+        self.apps.insert(app.window.id(), app);
+        Ok(())
+    }
+
+    fn open_monitor_overlay(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        monitor: Monitor,
+    ) -> Result<(), OverlayError> {
+        let handle = event_loop
+            .available_monitors()
+            .find(|handle| handle.hmonitor() == monitor.as_raw_hmonitor() as isize);
+        let window = create_overlay_window(
+            event_loop,
+            overlay_attributes()
+                .with_fullscreen(Some(winit::window::Fullscreen::Borderless(handle))),
+        )?;
+
+        let capture_buffer = self.capture_buffer_for(window.id());
+        let refresh_rate = monitor
+            .refresh_rate()
+            .map_err(|err| OverlayError::Capture(err.into()))?;
+        let capture = spawn_capture(
+            monitor,
+            self.capture_rate(refresh_rate),
+            capture_buffer.clone(),
+        )
+        .map_err(OverlayError::Capture)?;
+
+        let app = self.create_app(window, capture_buffer, capture)?;
+        self.apps.insert(app.window.id(), app);
+        Ok(())
+    }
+
+    /// Frames per second to capture: the refresh rate, limited by the preset's cap.
+    fn capture_rate(&self, refresh_rate: u32) -> u32 {
+        self.options
+            .settings
+            .fps_cap
+            .map_or(refresh_rate, |fps_cap| refresh_rate.min(fps_cap))
+    }
 
-        self.app = Some(pollster::block_on(App::new(
+    /// A capture buffer that asks the event loop to redraw `window_id` on every frame, and
+    /// to stop if the capture fails.
+    fn capture_buffer_for(&self, window_id: WindowId) -> CaptureBuffer {
+        let capture_buffer = CaptureBuffer::default();
+        let mut shared = capture_buffer.lock().unwrap();
+        let proxy = self.proxy.clone();
+        shared.on_frame = Some(Box::new(move || {
+            let _ = proxy.send_event(OverlayEvent::FrameReady(window_id));
+        }));
+        let proxy = self.proxy.clone();
+        shared.on_error = Some(Box::new(move |err| {
+            let _ = proxy.send_event(OverlayEvent::CaptureFailed(err));
+        }));
+        drop(shared);
+        capture_buffer
+    }
+
+    /// Stops `capture` again if the overlay for it cannot be created.
+    fn create_app(
+        &self,
+        window: Window,
+        capture_buffer: CaptureBuffer,
+        capture: CaptureControl<Capturer, anyhow::Error>,
+    ) -> Result<App, OverlayError> {
+        let cpu_renderer = self.cpu_filter.then(CpuRenderer::default);
+        let device = if self.cpu_filter || self.options.separate_devices {
+            None
+        } else {
+            wait_for_capture_device(&capture_buffer)
+        };
+        let device_mode = match (&cpu_renderer, &device) {
+            (Some(_), _) => DeviceMode::Readback,
+            (None, Some(_)) => DeviceMode::Single,
+            (None, None) => DeviceMode::Separate,
+        };
+        let source: Box<dyn FrameSource> = match device_mode {
+            DeviceMode::Readback => Box::new(capture_buffer.lock().unwrap().cpu_frames.source()),
+            DeviceMode::Single | DeviceMode::Separate => {
+                Box::new(GraphicsCaptureSource::new(capture_buffer.clone()))
+            }
+        };
+        let app = pollster::block_on(App::new(
             Arc::new(window),
-            capture_buffer,
-        )));
+            source,
+            self.enhancement.params(),
+            device,
+            cpu_renderer,
+        ));
+        let mut app = match app {
+            Ok(app) => app,
+            Err(err) => {
+                let _ = capture.stop();
+                return Err(err);
+            }
+        };
+        capture_buffer.lock().unwrap().device_mode = Some(device_mode);
+        app.capture_buffer = Some(capture_buffer);
+        app.capture = Some(capture);
+        app.set_adaptive(self.enhancement.adaptive());
+        app.set_lut(self.enhancement.lut());
+        app.set_flash_decay(self.options.settings.flash_decay);
+        app.set_flash_safety(self.flash_safety());
+        if let Some(shader) = &self.custom_shader {
+            app.set_pixel_shader(&shader.blob);
+        }
+        app.set_passes(&self.passes);
+        if !self.overlay_visible() {
+            app.set_enabled(false);
+        }
+        Ok(app)
+    }
+
+    /// Shown unless toggled off by hotkey or hidden by the foreground app's profile.
+    fn overlay_visible(&self) -> bool {
+        self.enhancement.enabled() && !self.hidden_by_profile
+    }
+
+    fn watch_foreground(&mut self) {
+        if self.watching_foreground || self.options.settings.profiles.is_empty() {
+            return;
+        }
+        match foreground::spawn(self.proxy.clone()) {
+            Ok(()) => self.watching_foreground = true,
+            Err(err) => eprintln!("Profiles unavailable: {err:?}"),
+        }
+    }
+
+    /// Applies the profile matching the foreground app, if it differs from the last one.
+    fn apply_profile(&mut self) {
+        let Some(app) = &self.foreground else {
+            return;
+        };
+        let profile = self.options.settings.profiles.select(app).cloned();
+        let name = profile.as_ref().map(|profile| profile.name.clone());
+        if name == self.active_profile {
+            return;
+        }
+        self.active_profile = name;
+
+        let hidden = match &profile {
+            Some(Profile {
+                name,
+                action: ProfileAction::Preset(preset),
+                ..
+            }) => {
+                if self.enhancement.select_named(preset) {
+                    self.apply_curve();
+                }
+                eprintln!("Profile {name}: preset {preset}");
+                false
+            }
+            Some(Profile {
+                name,
+                action: ProfileAction::Hide,
+                ..
+            }) => {
+                eprintln!("Profile {name}: overlay hidden");
+                true
+            }
+            None => false,
+        };
+        if hidden != self.hidden_by_profile {
+            self.hidden_by_profile = hidden;
+            let visible = self.overlay_visible();
+            for app in self.apps.values_mut() {
+                app.set_enabled(visible);
+            }
+        }
+    }
+
+    /// Applies an edited config on top of the command line. The curve changes in place;
+    /// the capture restarts only when the target, monitors or FPS cap changed. While
+    /// filtering on the CPU, a config that turns the photosensitive safety mode on is
+    /// rejected like any other that does not load.
+    fn reload_config(&mut self, event_loop: &ActiveEventLoop, config: &Config) {
+        let settings = match self.options.overrides.resolve(config) {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("Keeping the previous config: {err}");
+                return;
+            }
+        };
+        if self.cpu_filter
+            && let Err(err) = settings.check_flash_safety(CPU_RENDERER)
+        {
+            eprintln!("Keeping the previous config: {err}");
+            return;
+        }
+        let restart = self.options.settings.needs_capture_restart(&settings);
+        let ignored = self.options.unsupported_features(false);
+        let previous = std::mem::replace(&mut self.options.settings, settings);
+        if self.cpu_filter {
+            for feature in self.options.unsupported_features(false) {
+                if !ignored.contains(&feature) {
+                    eprintln!("{CPU_RENDERER} does not support {feature} yet; ignoring it");
+                }
+            }
+        }
+        self.enhancement.reload(
+            self.options.settings.params.clamped(),
+            self.options.settings.cycled_presets(),
+        );
+        self.enhancement
+            .set_adaptive(self.options.settings.adaptive);
+        self.enhancement
+            .set_flash_safety(self.options.settings.flash_safety);
+        self.enhancement.set_lut(self.options.settings.lut.clone());
+        eprintln!(
+            "Config reloaded: preset {}, gamma {:.2}",
+            self.enhancement.preset().name,
+            self.enhancement.params().gamma
+        );
+        // Let the foreground app's profile apply again on top of the reloaded preset.
+        self.active_profile = None;
+        self.watch_foreground();
+        if self.options.settings.passes != previous.passes {
+            self.reload_passes(&previous.passes);
+        }
+
+        if restart {
+            eprintln!("Capture settings changed; restarting capture");
+            self.close_overlays();
+            if let Err(err) = self.open_overlays(event_loop) {
+                eprintln!("Keeping the previous capture settings: {err}");
+                self.close_overlays();
+                let settings = &mut self.options.settings;
+                settings.target = previous.target;
+                settings.monitors = previous.monitors;
+                settings.fps_cap = previous.fps_cap;
+                if let Err(err) = self.open_overlays(event_loop) {
+                    self.fail(event_loop, err);
+                }
+            }
+        } else {
+            self.apply_curve();
+            for app in self.apps.values_mut() {
+                app.set_flash_decay(self.options.settings.flash_decay);
+            }
+        }
+        self.apply_profile();
+    }
+
+    fn apply_hotkey(&mut self, action: HotkeyAction) {
+        if !self.enhancement.apply(action) {
+            return;
+        }
+        let enhancement = &self.enhancement;
+        match action {
+            HotkeyAction::Toggle => {
+                let state = if enhancement.enabled() { "on" } else { "off" };
+                eprintln!("Enhancement {state}");
+                let visible = self.overlay_visible();
+                for app in self.apps.values_mut() {
+                    app.set_enabled(visible);
+                }
+            }
+            HotkeyAction::NextPreset
+            | HotkeyAction::PreviousPreset
+            | HotkeyAction::StrengthUp
+            | HotkeyAction::StrengthDown => {
+                eprintln!(
+                    "Preset {}, gamma {:.2}",
+                    enhancement.preset().name,
+                    enhancement.params().gamma
+                );
+                self.apply_curve();
+            }
+        }
+    }
+
+    /// Whether the photosensitive safety mode is on: as the current preset says, or for
+    /// good with `--flash-safety`, so switching presets never turns that off.
+    fn flash_safety(&self) -> bool {
+        self.options.overrides.flash_safety || self.enhancement.flash_safety()
+    }
+
+    /// Hands the current curve, adaptive setting, safety mode and LUT to every overlay.
+    /// The CPU filter cannot soften flashes, so a preset that asks for it is reported.
+    fn apply_curve(&mut self) {
+        let flash_safety = self.flash_safety();
+        if self.cpu_filter && flash_safety {
+            eprintln!(
+                "{CPU_RENDERER} cannot soften flashes; preset {} runs without the \
+                 photosensitive safety mode",
+                self.enhancement.preset().name
+            );
+        }
+        for app in self.apps.values_mut() {
+            app.set_params(self.enhancement.params());
+            app.set_adaptive(self.enhancement.adaptive());
+            app.set_flash_safety(flash_safety);
+            app.set_lut(self.enhancement.lut());
+        }
+    }
+
+    /// Compiles the configured passes and hands them to every overlay; on error the
+    /// `previous` ones stay in use.
+    fn reload_passes(&mut self, previous: &[Pass]) {
+        match pass_graph::compile(&self.options.settings.passes) {
+            Ok(passes) => {
+                self.passes = passes;
+                for app in self.apps.values_mut() {
+                    app.set_passes(&self.passes);
+                }
+                let names: Vec<String> = passes::chain(&self.options.settings.passes)
+                    .iter()
+                    .map(Pass::to_string)
+                    .collect();
+                eprintln!("Passes: {}", names.join(", "));
+            }
+            Err(err) => {
+                eprintln!("Keeping the previous passes: {err:#}");
+                self.options.settings.passes = previous.to_vec();
+            }
+        }
+    }
+
+    /// Compiles the `--shader` file and hands it to every overlay; `false` if it has not
+    /// changed since the last time. On error the previous shader stays in use.
+    fn reload_shader(&mut self) -> anyhow::Result<bool> {
+        let Some(path) = &self.options.shader_path else {
+            return Ok(false);
+        };
+        let source = custom_shader::load(path)?;
+        if self
+            .custom_shader
+            .as_ref()
+            .is_some_and(|shader| shader.source == source)
+        {
+            return Ok(false);
+        }
+        let blob = compile_shader(&source, custom_shader::ENTRY_POINT, "ps_5_0")
+            .map_err(|err| anyhow::anyhow!("Failed to compile {}: {err}", path.display()))?;
+        for app in self.apps.values_mut() {
+            app.set_pixel_shader(&blob);
+        }
+        self.custom_shader = Some(CustomShader { source, blob });
+        Ok(true)
+    }
+
+    fn report_frame_stats(&self) {
+        for (window_id, app) in &self.apps {
+            if let Some(stats) = app.frame_stats() {
+                eprintln!(
+                    "{window_id:?}: {} captured, {} rendered, {} dropped, {} reused",
+                    stats.published, stats.consumed, stats.dropped, stats.reused
+                );
+            }
+        }
+    }
+}
+
+impl ApplicationHandler<OverlayEvent> for AppHandler {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.apps.is_empty() {
+            return;
+        }
+        if let Err(err) = self.choose_renderer() {
+            self.fail(event_loop, err);
+            return;
+        }
+        let passes = self
+            .reload_shader()
+            .and_then(|_| pass_graph::compile(&self.options.settings.passes));
+        match passes {
+            Ok(passes) => self.passes = passes,
+            Err(err) => {
+                self.fail(event_loop, OverlayError::Shader(err));
+                return;
+            }
+        }
+        if let Err(err) = self.open_overlays(event_loop) {
+            self.fail(event_loop, err);
+            return;
+        }
+        if let Err(err) = global_hotkeys::spawn(self.options.hotkeys.clone(), self.proxy.clone()) {
+            eprintln!("Hotkeys unavailable: {err:?}");
+        }
+        if let Some(path) = &self.options.config_path {
+            let proxy = self.proxy.clone();
+            match ConfigWatcher::spawn(path, move |config| {
+                let _ = proxy.send_event(OverlayEvent::ConfigChanged(Box::new(config)));
+            }) {
+                Ok(watcher) => self.config_watcher = Some(watcher),
+                Err(err) => eprintln!("Config reload unavailable: {err:?}"),
+            }
+        }
+        if let Some(path) = &self.options.shader_path {
+            let proxy = self.proxy.clone();
+            match FileWatcher::spawn(path, "shader-watch", move |_| {
+                let _ = proxy.send_event(OverlayEvent::ShaderChanged);
+            }) {
+                Ok(watcher) => self.shader_watcher = Some(watcher),
+                Err(err) => eprintln!("Shader reload unavailable: {err:?}"),
+            }
+        }
+        self.watch_foreground();
     }
 
     fn window_event(
         &mut self,
-        event_loop: &winit::event_loop::ActiveEventLoop,
-        window_id: winit::window::WindowId,
+        event_loop: &ActiveEventLoop,
+        window_id: WindowId,
         event: winit::event::WindowEvent,
     ) {
-        let _ = (event_loop, window_id);
-        let Some(ref mut app) = self.app else {
+        let Some(app) = self.apps.get_mut(&window_id) else {
             return;
         };
         match event {
             winit::event::WindowEvent::RedrawRequested => {
-                app.render();
-                self.app.as_mut().unwrap().window.request_redraw();
+                let frame_pending = app.render();
+                if frame_pending {
+                    app.window.request_redraw();
+                }
             }
             winit::event::WindowEvent::Resized(physical_size) => {
-                app.resize(physical_size);
+                if let Err(err) = app.resize(physical_size) {
+                    self.fail(event_loop, err);
+                    return;
+                }
+                app.window.request_redraw();
             }
             _ => {}
         }
     }
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: OverlayEvent) {
+        match event {
+            OverlayEvent::FrameReady(window_id) => {
+                if let Some(app) = self.apps.get(&window_id) {
+                    app.window.request_redraw();
+                }
+            }
+            OverlayEvent::Hotkey(action) => self.apply_hotkey(action),
+            OverlayEvent::ConfigChanged(config) => self.reload_config(event_loop, &config),
+            OverlayEvent::Foreground(app) => {
+                self.foreground = Some(app);
+                self.apply_profile();
+            }
+            OverlayEvent::ShaderChanged => match self.reload_shader() {
+                Ok(true) => eprintln!("Shader reloaded"),
+                Ok(false) => {}
+                Err(err) => eprintln!("Keeping the previous shader: {err:#}"),
+            },
+            OverlayEvent::CaptureFailed(err) => self.fail(event_loop, OverlayError::Capture(err)),
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let mut tracking = false;
+        for app in self.apps.values_mut() {
+            if app.target_window.is_none() {
+                continue;
+            }
+            if !app.track_target() {
+                eprintln!("Target window closed");
+                event_loop.exit();
+                return;
+            }
+            tracking = true;
+        }
+
+        let now = Instant::now();
+        let mut wake_at = tracking.then_some(now + TARGET_POLL_INTERVAL);
+        if let Some(report_at) = self.next_stats_report {
+            let report_at = if now >= report_at {
+                self.report_frame_stats();
+                now + FRAME_STATS_INTERVAL
+            } else {
+                report_at
+            };
+            self.next_stats_report = Some(report_at);
+            wake_at = Some(wake_at.map_or(report_at, |wake_at| wake_at.min(report_at)));
+        }
+        if let Some(wake_at) = wake_at {
+            event_loop.set_control_flow(ControlFlow::WaitUntil(wake_at));
+        }
+    }
+}
+
+/// Creates an overlay window that clicks go through.
+fn create_overlay_window(
+    event_loop: &ActiveEventLoop,
+    attributes: WindowAttributes,
+) -> Result<Window, OverlayError> {
+    let window = event_loop
+        .create_window(attributes)
+        .map_err(|err| OverlayError::Swapchain(err.into()))?;
+    apply_click_through(&window).map_err(OverlayError::Swapchain)?;
+    Ok(window)
+}
+
+fn overlay_attributes() -> WindowAttributes {
+    WindowAttributes::default()
+        .with_title("Ban-Shadow Overlay")
+        .with_decorations(false)
+        .with_transparent(true)
+        .with_resizable(false)
+        .with_skip_taskbar(true)
+}
+
+/// Describes the connected monitors for `--monitor` and `--list-monitors`.
+pub fn monitor_infos() -> anyhow::Result<Vec<MonitorInfo>> {
+    let primary = Monitor::primary()?;
+    Monitor::enumerate()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, monitor)| {
+            Ok(MonitorInfo {
+                index: i + 1,
+                name: monitor.name().unwrap_or_default(),
+                device_name: monitor.device_name()?,
+                primary: monitor == primary,
+            })
+        })
+        .collect()
+}
+
+fn find_monitors(selections: &[MonitorSelection]) -> anyhow::Result<Vec<Monitor>> {
+    let infos = monitor_infos()?;
+    let selected = select_monitors(selections, &infos).map_err(anyhow::Error::msg)?;
+    selected
+        .into_iter()
+        .map(|info| Ok(Monitor::from_index(info.index)?))
+        .collect()
+}
+
+fn find_target_window(target: &CaptureTarget) -> anyhow::Result<CaptureWindow> {
+    if let CaptureTarget::Hwnd(hwnd) = target {
+        let hwnd = HWND(*hwnd as *mut std::ffi::c_void);
+        if !unsafe { IsWindow(Some(hwnd)) }.as_bool() {
+            return Err(anyhow::anyhow!("{target} is not a window"));
+        }
+        return Ok(CaptureWindow::from_raw_hwnd(hwnd.0));
+    }
+    let windows: Vec<WindowInfo> = CaptureWindow::enumerate()?
+        .into_iter()
+        .map(|window| WindowInfo {
+            hwnd: window.as_raw_hwnd() as isize,
+            title: window.title().unwrap_or_default(),
+            process_name: window.process_name().unwrap_or_default(),
+        })
+        .collect();
+    let window = target
+        .select(&windows)
+        .ok_or_else(|| anyhow::anyhow!("No window matches {target}"))?;
+    Ok(CaptureWindow::from_raw_hwnd(
+        window.hwnd as *mut std::ffi::c_void,
+    ))
+}
+
+fn target_layout(hwnd: HWND) -> anyhow::Result<Option<OverlayLayout>> {
+    let mut frame = RECT::default();
+    let mut client = RECT::default();
+    let mut origin = POINT::default();
+    unsafe {
+        DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            (&raw mut frame).cast(),
+            size_of::<RECT>() as u32,
+        )?;
+        GetClientRect(hwnd, &mut client)?;
+        ClientToScreen(hwnd, &mut origin).ok()?;
+    }
+    let frame = Rect {
+        left: frame.left,
+        top: frame.top,
+        right: frame.right,
+        bottom: frame.bottom,
+    };
+    let client = Rect {
+        left: origin.x,
+        top: origin.y,
+        right: origin.x + client.right,
+        bottom: origin.y + client.bottom,
+    };
+    Ok(OverlayLayout::new(frame, client))
+}
+
+/// The capture thread's device, once it has started and offered one.
+fn wait_for_capture_device(capture_buffer: &CaptureBuffer) -> Option<CaptureDevice> {
+    let Some(shared) = capture_buffer.wait_for_start(CAPTURE_DEVICE_TIMEOUT) else {
+        eprintln!("Capture did not start in time; rendering on a separate device");
+        return None;
+    };
+    shared.capture_device.clone()
+}
+
+fn spawn_capture<T>(
+    item: T,
+    refresh_rate: u32,
+    capture_buffer: CaptureBuffer,
+) -> anyhow::Result<CaptureControl<Capturer, anyhow::Error>>
+where
+    T: TryIntoCaptureItemWithType + Send + 'static,
+{
+    let settings = CaptureSettings::new(
+        item,
+        CursorCaptureSettings::WithoutCursor,
+        DrawBorderSettings::WithoutBorder,
+        SecondaryWindowSettings::Exclude,
+        MinimumUpdateIntervalSettings::Custom(Duration::from_secs(1) / refresh_rate),
+        DirtyRegionSettings::Default,
+        ColorFormat::Bgra8,
+        capture_buffer,
+    );
+    Capturer::start_free_threaded(settings)
+        .map_err(|err| anyhow::anyhow!("Failed to start capture: {err}"))
 }
 
 fn window_to_hwnd(window: &Window) -> anyhow::Result<HWND> {
@@ -290,7 +1608,9 @@ fn window_to_hwnd(window: &Window) -> anyhow::Result<HWND> {
     Ok(hwnd)
 }
 
-fn create_d3d_device() -> anyhow::Result<(ID3D11Device, ID3D11DeviceContext)> {
+fn create_d3d_device(
+    driver_type: D3D_DRIVER_TYPE,
+) -> anyhow::Result<(ID3D11Device, ID3D11DeviceContext)> {
     let feature_levels = [D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0];
     let mut device = None;
     let mut context = None;
@@ -298,7 +1618,7 @@ fn create_d3d_device() -> anyhow::Result<(ID3D11Device, ID3D11DeviceContext)> {
     unsafe {
         D3D11CreateDevice(
             None,
-            D3D_DRIVER_TYPE_HARDWARE,
+            driver_type,
             HMODULE::default(),
             D3D11_CREATE_DEVICE_BGRA_SUPPORT,
             Some(&feature_levels),
@@ -377,6 +1697,71 @@ fn create_shaders(
     Ok((vs, ps))
 }
 
+/// Compiles the built-in pixel shader `entry_point` of `shader.hlsl`.
+fn create_pixel_shader(
+    device: &ID3D11Device,
+    entry_point: &str,
+) -> anyhow::Result<ID3D11PixelShader> {
+    let blob = compile_shader(SHADER_SOURCE, entry_point, "ps_5_0")?;
+    let mut ps = None;
+    unsafe {
+        device.CreatePixelShader(blob_bytes(&blob), None, Some(&mut ps))?;
+    }
+    ps.ok_or_else(|| anyhow::anyhow!("Failed to create pixel shader"))
+}
+
+fn create_params_buffer(device: &ID3D11Device) -> anyhow::Result<ID3D11Buffer> {
+    let desc = D3D11_BUFFER_DESC {
+        ByteWidth: size_of::<ShaderParams>() as u32,
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: D3D11_BIND_CONSTANT_BUFFER.0 as u32,
+        CPUAccessFlags: 0,
+        MiscFlags: 0,
+        StructureByteStride: 0,
+    };
+    let mut buffer = None;
+    unsafe {
+        device.CreateBuffer(&desc, None, Some(&mut buffer))?;
+    }
+    buffer.ok_or_else(|| anyhow::anyhow!("Failed to create parameter buffer"))
+}
+
+/// Uploads `lut` as half floats, which the sampler can filter on every feature level.
+fn create_lut_texture(
+    device: &ID3D11Device,
+    lut: &Lut3d,
+) -> anyhow::Result<ID3D11ShaderResourceView> {
+    let size = lut.size() as u32;
+    let texels: Vec<f16> = lut
+        .table()
+        .iter()
+        .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(f16::from_f32))
+        .collect();
+    let texel_bytes = size_of::<[f16; 4]>() as u32;
+    let desc = D3D11_TEXTURE3D_DESC {
+        Width: size,
+        Height: size,
+        Depth: size,
+        MipLevels: 1,
+        Format: DXGI_FORMAT_R16G16B16A16_FLOAT,
+        Usage: D3D11_USAGE_IMMUTABLE,
+        BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
+        CPUAccessFlags: 0,
+        MiscFlags: 0,
+    };
+    let data = D3D11_SUBRESOURCE_DATA {
+        pSysMem: texels.as_ptr().cast(),
+        SysMemPitch: size * texel_bytes,
+        SysMemSlicePitch: size * size * texel_bytes,
+    };
+    let mut texture: Option<ID3D11Texture3D> = None;
+    unsafe { device.CreateTexture3D(&desc, Some(&data), Some(&mut texture))? };
+    let texture = texture.ok_or_else(|| anyhow::anyhow!("Failed to create LUT texture"))?;
+    let mut srv = None;
+    unsafe { device.CreateShaderResourceView(&texture, None, Some(&mut srv))? };
+    srv.ok_or_else(|| anyhow::anyhow!("Failed to create LUT view"))
+}
+
 fn create_sampler(device: &ID3D11Device) -> anyhow::Result<ID3D11SamplerState> {
     let desc = D3D11_SAMPLER_DESC {
         Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
@@ -397,7 +1782,7 @@ fn create_sampler(device: &ID3D11Device) -> anyhow::Result<ID3D11SamplerState> {
     sampler.ok_or_else(|| anyhow::anyhow!("Failed to create sampler"))
 }
 
-fn compile_shader(
+pub(crate) fn compile_shader(
     source: &str,
     entry: &str,
     target: &str,
@@ -432,7 +1817,7 @@ fn compile_shader(
     shader.ok_or_else(|| anyhow::anyhow!("Shader blob missing"))
 }
 
-fn blob_bytes(blob: &windows::Win32::Graphics::Direct3D::ID3DBlob) -> &[u8] {
+pub(crate) fn blob_bytes(blob: &windows::Win32::Graphics::Direct3D::ID3DBlob) -> &[u8] {
     unsafe {
         std::slice::from_raw_parts(blob.GetBufferPointer().cast::<u8>(), blob.GetBufferSize())
     }
@@ -1,9 +1,11 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, LockResult, Mutex, MutexGuard};
+use std::time::Duration;
 
 use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
 use windows::Win32::Graphics::Direct3D11::{
     D3D11_BIND_SHADER_RESOURCE, D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX, D3D11_TEXTURE2D_DESC,
-    D3D11_USAGE_DEFAULT, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
+    D3D11_USAGE_DEFAULT, ID3D11Device, ID3D11DeviceContext, ID3D11Multithread, ID3D11Texture2D,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM,
@@ -14,7 +16,31 @@ use windows::core::Interface;
 use windows_capture::capture::GraphicsCaptureApiHandler;
 use windows_capture::settings::ColorFormat;
 
-pub type CaptureBuffer = Arc<Mutex<SharedData>>;
+use crate::filter::PixelFormat;
+use crate::mailbox::{DEFAULT_SLOT_COUNT, Mailbox, ReadSlot};
+use crate::source::{CpuFrames, Frame, FramePixels, FrameSource};
+
+/// [`SharedData`] shared between the capture thread and the renderer, with a condition
+/// variable signalled once the capture thread is running.
+#[derive(Clone, Default)]
+pub struct CaptureBuffer(Arc<(Mutex<SharedData>, Condvar)>);
+
+impl CaptureBuffer {
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, SharedData>> {
+        self.0.0.lock()
+    }
+
+    /// Waits up to `timeout` for the capture thread to start; `None` if it did not.
+    pub fn wait_for_start(&self, timeout: Duration) -> Option<MutexGuard<'_, SharedData>> {
+        let (data, started) = &*self.0;
+        let (shared, waited) = started
+            .wait_timeout_while(data.lock().unwrap(), timeout, |shared| {
+                !shared.capture_started
+            })
+            .unwrap();
+        (!waited.timed_out()).then_some(shared)
+    }
+}
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct SharedHandle(pub HANDLE);
@@ -22,22 +48,102 @@ pub struct SharedHandle(pub HANDLE);
 unsafe impl Send for SharedHandle {}
 unsafe impl Sync for SharedHandle {}
 
+/// A ring texture created on the capture device, for a renderer using that same device.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DeviceTexture(pub ID3D11Texture2D);
+
+unsafe impl Send for DeviceTexture {}
+unsafe impl Sync for DeviceTexture {}
+
+/// How the renderer reaches one slot of the capture ring.
+#[derive(Clone, PartialEq, Eq)]
+pub enum SharedSlot {
+    /// The renderer shares the capture device and samples the texture directly.
+    Texture(DeviceTexture),
+    /// The renderer has its own device and opens the keyed-mutex texture by handle.
+    Handle(SharedHandle),
+}
+
+/// The device windows-capture created, offered to the renderer so frames never have to
+/// cross devices. Multithread protection is on, so both threads may use its context.
+#[derive(Clone)]
+pub struct CaptureDevice {
+    pub device: ID3D11Device,
+    pub context: ID3D11DeviceContext,
+}
+
+unsafe impl Send for CaptureDevice {}
+
+/// Whether the renderer adopted [`CaptureDevice`], kept a device of its own or filters
+/// on the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceMode {
+    Single,
+    Separate,
+    /// Frames are read back and published to [`SharedData::cpu_frames`] instead of the
+    /// texture ring.
+    Readback,
+}
+
+/// Called on the capture thread every time a new frame has been published.
+pub type FrameNotifier = Box<dyn Fn() + Send>;
+
+/// Called on the capture thread when it stops because a frame could not be handled.
+pub type ErrorNotifier = Box<dyn Fn(anyhow::Error) + Send>;
+
 #[derive(Default)]
 pub struct SharedData {
-    pub handle: Option<SharedHandle>,
+    /// Set once the capture thread is running, whether or not it offers its device.
+    pub capture_started: bool,
+    pub capture_device: Option<CaptureDevice>,
+    /// Chosen by the renderer; frames are skipped until it is set.
+    pub device_mode: Option<DeviceMode>,
+    /// One texture per mailbox slot, all of the same size and format.
+    pub slots: Vec<SharedSlot>,
     pub width: u32,
     pub height: u32,
-    pub frame_id: u64,
+    pub format: PixelFormat,
+    pub mailbox: Mailbox,
+    /// Read-back frames, used instead of the ring in [`DeviceMode::Readback`].
+    pub cpu_frames: CpuFrames,
+    pub on_frame: Option<FrameNotifier>,
+    pub on_error: Option<ErrorNotifier>,
+}
+
+/// A published slot the renderer may sample until the frame is dropped.
+pub struct SharedFrame {
+    pub slot: SharedSlot,
+    _lease: ReadLease,
+}
+
+struct ReadLease {
+    shared_buffer: CaptureBuffer,
+    read: ReadSlot,
+}
+
+impl Drop for ReadLease {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared_buffer.lock() {
+            shared.mailbox.end_read(self.read);
+        }
+    }
+}
+
+struct RingSlot {
+    texture: ID3D11Texture2D,
+    /// Only present when the ring is shared with another device.
+    mutex: Option<IDXGIKeyedMutex>,
 }
 
 pub struct Capturer {
     shared_buffer: CaptureBuffer,
     device: ID3D11Device,
     context: ID3D11DeviceContext,
-    shared_texture: Option<ID3D11Texture2D>,
-    shared_mutex: Option<IDXGIKeyedMutex>,
+    slots: Vec<RingSlot>,
     shared_size: (u32, u32),
-    current_frame_id: u64,
+    device_mode: Option<DeviceMode>,
+    /// Reused for every read-back frame; swapped with a free [`CpuFrames`] slot.
+    readback: Vec<u8>,
 }
 
 impl GraphicsCaptureApiHandler for Capturer {
@@ -46,67 +152,126 @@ impl GraphicsCaptureApiHandler for Capturer {
 
     fn new(ctx: windows_capture::capture::Context<Self::Flags>) -> Result<Self, Self::Error> {
         let shared_buffer = ctx.flags;
+        {
+            let mut shared = shared_buffer.lock().unwrap();
+            shared.capture_started = true;
+            match offer_device(&ctx.device) {
+                Ok(()) => {
+                    shared.capture_device = Some(CaptureDevice {
+                        device: ctx.device.clone(),
+                        context: ctx.device_context.clone(),
+                    });
+                }
+                Err(err) => eprintln!("Capture device cannot be shared with the renderer: {err}"),
+            }
+        }
+        shared_buffer.0.1.notify_all();
         Ok(Self {
             shared_buffer,
             device: ctx.device,
             context: ctx.device_context,
-            shared_texture: None,
-            shared_mutex: None,
+            slots: Vec::new(),
             shared_size: (0, 0),
-            current_frame_id: 0,
+            device_mode: None,
+            readback: Vec::new(),
         })
     }
 
+    /// Stops the capture on the first frame that cannot be handled and reports why,
+    /// rather than letting the error end the thread unnoticed.
     fn on_frame_arrived(
         &mut self,
         frame: &mut windows_capture::frame::Frame,
         capture_control: windows_capture::graphics_capture_api::InternalCaptureControl,
     ) -> Result<(), Self::Error> {
-        let _ = capture_control;
-        self.ensure_shared_texture(frame)?;
-        let Some(shared_texture) = &self.shared_texture else {
+        if let Err(err) = self.handle_frame(frame) {
+            if let Some(on_error) = &self.shared_buffer.lock().unwrap().on_error {
+                on_error(err);
+            }
+            capture_control.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Capturer {
+    fn handle_frame(&mut self, frame: &mut windows_capture::frame::Frame) -> anyhow::Result<()> {
+        let Some(device_mode) = self.shared_buffer.lock().unwrap().device_mode else {
             return Ok(());
         };
-        let Some(shared_mutex) = &self.shared_mutex else {
+        if device_mode == DeviceMode::Readback {
+            return self.read_back(frame);
+        }
+        self.ensure_shared_textures(frame, device_mode)?;
+
+        let slot = self.shared_buffer.lock().unwrap().mailbox.begin_write();
+        let Some((slot, target)) = slot.and_then(|slot| Some((slot, self.slots.get(slot)?))) else {
             return Ok(());
         };
 
-        if unsafe { shared_mutex.AcquireSync(0, 0) }.is_err() {
+        // The mailbox keeps the renderer off this slot, so the keyed mutex only orders
+        // the copy against the renderer's earlier reads on the other device.
+        if let Some(mutex) = &target.mutex
+            && unsafe { mutex.AcquireSync(0, 0) }.is_err()
+        {
+            self.shared_buffer.lock().unwrap().mailbox.discard();
             return Ok(());
         }
 
         unsafe {
             self.context
-                .CopyResource(shared_texture, frame.as_raw_texture());
+                .CopyResource(&target.texture, frame.as_raw_texture());
         }
 
-        let _ = unsafe { shared_mutex.ReleaseSync(1) };
+        if let Some(mutex) = &target.mutex {
+            let _ = unsafe { mutex.ReleaseSync(0) };
+        }
 
-        self.current_frame_id += 1;
         let mut shared = self.shared_buffer.lock().unwrap();
-        shared.frame_id = self.current_frame_id;
+        shared.mailbox.publish(slot);
+        if let Some(on_frame) = &shared.on_frame {
+            on_frame();
+        }
         Ok(())
     }
-}
 
-impl Capturer {
-    fn ensure_shared_texture(
+    /// Copies the frame into CPU memory and publishes it to [`SharedData::cpu_frames`].
+    fn read_back(&mut self, frame: &mut windows_capture::frame::Frame) -> anyhow::Result<()> {
+        let size = (frame.width(), frame.height());
+        let mut buffer = frame.buffer()?;
+        let pixels = buffer.as_nopadding_buffer()?;
+        self.readback.clear();
+        self.readback.extend_from_slice(pixels);
+
+        let shared = self.shared_buffer.lock().unwrap();
+        shared.cpu_frames.publish(&mut self.readback, size);
+        if let Some(on_frame) = &shared.on_frame {
+            on_frame();
+        }
+        Ok(())
+    }
+
+    fn ensure_shared_textures(
         &mut self,
         frame: &windows_capture::frame::Frame,
+        device_mode: DeviceMode,
     ) -> Result<(), anyhow::Error> {
         let width = frame.width();
         let height = frame.height();
-        if self.shared_texture.is_some() && self.shared_size == (width, height) {
+        if !self.slots.is_empty()
+            && self.shared_size == (width, height)
+            && self.device_mode == Some(device_mode)
+        {
             return Ok(());
         }
 
-        let format = dxgi_format_from_color(frame.color_format());
+        let format = pixel_format_from_color(frame.color_format());
         let desc = D3D11_TEXTURE2D_DESC {
             Width: width,
             Height: height,
             MipLevels: 1,
             ArraySize: 1,
-            Format: format,
+            Format: dxgi_format(format),
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
                 Quality: 0,
@@ -114,41 +279,120 @@ impl Capturer {
             Usage: D3D11_USAGE_DEFAULT,
             BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
             CPUAccessFlags: 0,
-            MiscFlags: D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX.0 as u32,
+            MiscFlags: match device_mode {
+                DeviceMode::Single | DeviceMode::Readback => 0,
+                DeviceMode::Separate => D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX.0 as u32,
+            },
         };
 
-        let mut texture = None;
-        unsafe {
-            self.device
-                .CreateTexture2D(&desc, None, Some(&mut texture))?;
-        }
-        let texture = texture.ok_or_else(|| anyhow::anyhow!("Failed to create shared texture"))?;
+        let mut slots = Vec::with_capacity(DEFAULT_SLOT_COUNT);
+        let mut shared_slots = Vec::with_capacity(DEFAULT_SLOT_COUNT);
+        for _ in 0..DEFAULT_SLOT_COUNT {
+            let mut texture = None;
+            unsafe {
+                self.device
+                    .CreateTexture2D(&desc, None, Some(&mut texture))?;
+            }
+            let texture =
+                texture.ok_or_else(|| anyhow::anyhow!("Failed to create shared texture"))?;
 
-        let dxgi_resource: IDXGIResource = texture.cast()?;
-        let handle = unsafe { dxgi_resource.GetSharedHandle()? };
-
-        let keyed_mutex: IDXGIKeyedMutex = texture.cast()?;
+            let (shared_slot, mutex) = match device_mode {
+                DeviceMode::Single | DeviceMode::Readback => {
+                    (SharedSlot::Texture(DeviceTexture(texture.clone())), None)
+                }
+                DeviceMode::Separate => {
+                    let dxgi_resource: IDXGIResource = texture.cast()?;
+                    let handle = SharedHandle(unsafe { dxgi_resource.GetSharedHandle()? });
+                    (SharedSlot::Handle(handle), Some(texture.cast()?))
+                }
+            };
+            shared_slots.push(shared_slot);
+            slots.push(RingSlot { texture, mutex });
+        }
 
         {
             let mut shared = self.shared_buffer.lock().unwrap();
-            shared.handle = Some(SharedHandle(handle));
+            shared.slots = shared_slots;
             shared.width = width;
             shared.height = height;
-            shared.frame_id = 0;
+            shared.format = format;
+            shared.mailbox.reset();
         }
 
-        self.shared_texture = Some(texture);
-        self.shared_mutex = Some(keyed_mutex);
+        self.slots = slots;
         self.shared_size = (width, height);
+        self.device_mode = Some(device_mode);
 
         Ok(())
     }
 }
 
-fn dxgi_format_from_color(format: ColorFormat) -> DXGI_FORMAT {
+/// Hands the frames [`Capturer`] publishes to the renderer as shared textures.
+pub struct GraphicsCaptureSource {
+    shared_buffer: CaptureBuffer,
+}
+
+impl GraphicsCaptureSource {
+    pub fn new(shared_buffer: CaptureBuffer) -> Self {
+        Self { shared_buffer }
+    }
+
+    fn frame(&self, shared: &SharedData, read: ReadSlot) -> Frame {
+        Frame {
+            id: read.frame_id,
+            width: shared.width,
+            height: shared.height,
+            format: shared.format,
+            pixels: FramePixels::Shared(SharedFrame {
+                slot: shared.slots[read.slot].clone(),
+                _lease: ReadLease {
+                    shared_buffer: self.shared_buffer.clone(),
+                    read,
+                },
+            }),
+        }
+    }
+}
+
+impl FrameSource for GraphicsCaptureSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut shared = self.shared_buffer.lock().unwrap();
+        let read = shared.mailbox.begin_read();
+        Ok(read.map(|read| self.frame(&shared, read)))
+    }
+
+    fn repeat_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut shared = self.shared_buffer.lock().unwrap();
+        let read = shared.mailbox.begin_reread();
+        Ok(read.map(|read| self.frame(&shared, read)))
+    }
+}
+
+/// Makes the capture device safe to hand to the render thread.
+fn offer_device(device: &ID3D11Device) -> anyhow::Result<()> {
+    let feature_level = unsafe { device.GetFeatureLevel() };
+    if feature_level.0 < D3D_FEATURE_LEVEL_11_0.0 {
+        anyhow::bail!("feature level {:#x} is below 11_0", feature_level.0);
+    }
+    let multithread: ID3D11Multithread = device.cast()?;
+    unsafe {
+        let _ = multithread.SetMultithreadProtected(true);
+    }
+    Ok(())
+}
+
+fn pixel_format_from_color(format: ColorFormat) -> PixelFormat {
+    match format {
+        ColorFormat::Rgba16F => PixelFormat::Rgba16F,
+        ColorFormat::Rgba8 => PixelFormat::Rgba8,
+        ColorFormat::Bgra8 => PixelFormat::Bgra8,
+    }
+}
+
+pub(crate) fn dxgi_format(format: PixelFormat) -> DXGI_FORMAT {
     match format {
-        ColorFormat::Rgba16F => DXGI_FORMAT_R16G16B16A16_FLOAT,
-        ColorFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
-        ColorFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+        PixelFormat::Rgba16F => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        PixelFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        PixelFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
     }
 }
@@ -0,0 +1,872 @@
+//! The optional TOML config file: named presets and which one to start with.
+//!
+//! ```toml
+//! default_preset = "night"
+//!
+//! [presets.night]
+//! gamma = 0.6
+//! target = "process:game.exe"
+//! fps_cap = 60
+//! flash_decay = 1.5  # seconds sudden bright frames stay damped
+//! flash_safety = true  # soften rapid flashing for photosensitive players
+//! lut = "grade.cube"  # relative to this file
+//!
+//! [presets.strong]   # overrides only what it sets on the built-in preset
+//! protect_high = 0.4
+//!
+//! [profiles.elden]    # switches preset while the game is in the foreground
+//! exe = "eldenring.exe"
+//! preset = "strong"
+//!
+//! [profiles.default]  # anything no other profile matches
+//! hide = true
+//!
+//! [[passes]]          # drawn in order, each from the output of the one before
+//! shader = "denoise.hlsl"
+//! scale = 0.5
+//!
+//! [[passes]]          # no `shader`: the curve, LUT and flash modes of the preset
+//! ```
+//!
+//! Presets keep the order they are written in, after the built-in ones, so the preset
+//! hotkeys cycle through them predictably.
+
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use toml::Spanned;
+
+use crate::lut::{Interpolation, Lut3d, LutStage};
+use crate::params::{FilterParams, GAMMA_RANGE};
+use crate::passes::{self, Pass, PassShader};
+use crate::presets::{self, Preset};
+use crate::profiles::{DEFAULT_PROFILE, Pattern, Profile, ProfileAction, Profiles};
+use crate::target::{CaptureTarget, MonitorSelection};
+
+/// Looked for next to the executable.
+pub const FILE_NAME: &str = "ban-shadow.toml";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub default_preset: String,
+    /// The built-in presets, overridden or extended by the file.
+    pub presets: Vec<Preset>,
+    pub profiles: Profiles,
+    /// Passes each frame is drawn through; empty for just the filter.
+    pub passes: Vec<Pass>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_preset: presets::DEFAULT_PRESET.to_string(),
+            presets: presets::BUILTIN.to_vec(),
+            profiles: Profiles::default(),
+            passes: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads and parses `path`, naming it in any error.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse_in(&source, dir).map_err(|err| {
+            anyhow::Error::new(ConfigError {
+                path: Some(path.to_path_buf()),
+                ..err
+            })
+        })
+    }
+
+    /// Parses `source`, with LUT and shader paths relative to the working directory.
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        Self::parse_in(source, Path::new(""))
+    }
+
+    /// Parses `source`, with LUT and shader paths relative to `dir`.
+    fn parse_in(source: &str, dir: &Path) -> Result<Self, ConfigError> {
+        let file: ConfigFile = toml::from_str(source)
+            .map_err(|err| ConfigError::at(source, err.span(), err.message()))?;
+
+        let mut config = Self::default();
+        for (name, preset) in file.presets.0 {
+            let span = preset.span();
+            let preset = preset
+                .into_inner()
+                .resolve(&name, config.preset(&name), dir)
+                .map_err(|message| ConfigError::at(source, Some(span), &message))?;
+            match config
+                .presets
+                .iter_mut()
+                .find(|existing| existing.name.eq_ignore_ascii_case(&name))
+            {
+                Some(existing) => *existing = preset,
+                None => config.presets.push(preset),
+            }
+        }
+
+        if let Some(default_preset) = file.default_preset {
+            if config.preset(default_preset.get_ref()).is_none() {
+                let message = format!(
+                    "unknown preset `{}`; expected one of {}",
+                    default_preset.get_ref(),
+                    config.preset_names()
+                );
+                return Err(ConfigError::at(
+                    source,
+                    Some(default_preset.span()),
+                    &message,
+                ));
+            }
+            config.default_preset = default_preset.into_inner();
+        }
+
+        let mut profiles = Vec::new();
+        for (name, profile) in file.profiles.0 {
+            let span = profile.span();
+            let profile = profile
+                .into_inner()
+                .resolve(name, &config)
+                .map_err(|message| ConfigError::at(source, Some(span), &message))?;
+            profiles.push(profile);
+        }
+        config.profiles = Profiles::new(profiles);
+
+        let last_pass = file.passes.len().saturating_sub(1);
+        for (i, pass) in file.passes.into_iter().enumerate() {
+            let span = pass.span();
+            let pass = pass
+                .into_inner()
+                .resolve(dir)
+                .map_err(|message| ConfigError::at(source, Some(span.clone()), &message))?;
+            if i == last_pass && pass.scale != 1.0 {
+                return Err(ConfigError::at(
+                    source,
+                    Some(span),
+                    "the last pass draws to the overlay and cannot be scaled",
+                ));
+            }
+            config.passes.push(pass);
+        }
+        Ok(config)
+    }
+
+    /// Looks up a preset by name, ignoring ASCII case.
+    pub fn preset(&self, name: &str) -> Option<&Preset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Comma-separated preset names for error messages.
+    pub fn preset_names(&self) -> String {
+        let names: Vec<&str> = self.presets.iter().map(|preset| &*preset.name).collect();
+        names.join(", ")
+    }
+}
+
+/// The first config file that exists: next to the executable, then in the user's
+/// config directory.
+pub fn discover() -> Option<PathBuf> {
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(FILE_NAME)));
+    let user = user_config_dir().map(|dir| dir.join("ban-shadow").join("config.toml"));
+    [beside_exe, user]
+        .into_iter()
+        .flatten()
+        .find(|path| path.is_file())
+}
+
+#[cfg(target_os = "windows")]
+fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))
+}
+
+/// A config error pointing at the offending spot, 1-based.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn at(source: &str, span: Option<Range<usize>>, message: &str) -> Self {
+        let offset = span.map_or(0, |span| span.start.min(source.len()));
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Self {
+            path: None,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    default_preset: Option<Spanned<String>>,
+    #[serde(default)]
+    presets: Tables<PresetFile>,
+    #[serde(default)]
+    profiles: Tables<ProfileFile>,
+    #[serde(default)]
+    passes: Vec<Spanned<PassFile>>,
+}
+
+/// `[presets.*]` or `[profiles.*]` tables in the order they appear in the file.
+struct Tables<T>(Vec<(String, Spanned<T>)>);
+
+impl<T> Default for Tables<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Tables<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TablesVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for TablesVisitor<T> {
+            type Value = Tables<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a table of named tables")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut tables = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    tables.push(entry);
+                }
+                Ok(Tables(tables))
+            }
+        }
+
+        deserializer.deserialize_map(TablesVisitor(std::marker::PhantomData))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetFile {
+    #[serde(default, deserialize_with = "gamma")]
+    gamma: Option<f32>,
+    #[serde(default, deserialize_with = "unit_interval")]
+    protect_low: Option<f32>,
+    #[serde(default, deserialize_with = "unit_interval")]
+    protect_high: Option<f32>,
+    #[serde(default, deserialize_with = "luma_weights")]
+    luma_weights: Option<[f32; 3]>,
+    #[serde(default, deserialize_with = "from_str")]
+    target: Option<CaptureTarget>,
+    #[serde(default, deserialize_with = "monitors")]
+    monitor: Vec<MonitorSelection>,
+    #[serde(default, deserialize_with = "fps_cap")]
+    fps_cap: Option<u32>,
+    adaptive: Option<bool>,
+    #[serde(default, deserialize_with = "flash_decay")]
+    flash_decay: Option<Duration>,
+    #[serde(default)]
+    flash_safety: bool,
+    lut: Option<PathBuf>,
+    #[serde(default, deserialize_with = "from_str")]
+    lut_interpolation: Option<Interpolation>,
+    lut_replaces_curve: Option<bool>,
+}
+
+impl PresetFile {
+    /// Fills unset fields from `base`, the preset of the same name, or the defaults, and
+    /// loads the LUT from `dir`.
+    fn resolve(self, name: &str, base: Option<&Preset>, dir: &Path) -> Result<Preset, String> {
+        let adaptive = self
+            .adaptive
+            .unwrap_or(base.is_some_and(|base| base.adaptive));
+        let lut = match self.lut {
+            Some(path) => {
+                let lut = Lut3d::load(&dir.join(path)).map_err(|err| format!("{err:#}"))?;
+                Some(Arc::new(LutStage {
+                    lut,
+                    interpolation: self.lut_interpolation.unwrap_or_default(),
+                    replaces_curve: self.lut_replaces_curve.unwrap_or(false),
+                }))
+            }
+            None if self.lut_interpolation.is_some() || self.lut_replaces_curve.is_some() => {
+                return Err("`lut_interpolation` and `lut_replaces_curve` need `lut`".to_string());
+            }
+            None => base.and_then(|base| base.lut.clone()),
+        };
+        let base = base.map_or(FilterParams::DEFAULT, |base| base.params);
+        let params = FilterParams {
+            gamma: self.gamma.unwrap_or(base.gamma),
+            protect_low: self.protect_low.unwrap_or(base.protect_low),
+            protect_high: self.protect_high.unwrap_or(base.protect_high),
+            luma_weights: self.luma_weights.unwrap_or(base.luma_weights),
+        };
+        if params.protect_low >= params.protect_high {
+            return Err(format!(
+                "protect_low ({}) must be below protect_high ({})",
+                params.protect_low, params.protect_high
+            ));
+        }
+        Ok(Preset {
+            name: name.to_string().into(),
+            params: params.clamped(),
+            target: self.target,
+            monitors: self.monitor,
+            fps_cap: self.fps_cap,
+            adaptive,
+            flash_decay: self.flash_decay,
+            flash_safety: self.flash_safety,
+            lut,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    #[serde(default, deserialize_with = "from_str")]
+    exe: Option<Pattern>,
+    #[serde(default, deserialize_with = "from_str")]
+    class: Option<Pattern>,
+    #[serde(default)]
+    priority: i32,
+    preset: Option<String>,
+    #[serde(default)]
+    hide: bool,
+}
+
+impl ProfileFile {
+    /// Checks the profile against the presets `config` ended up with.
+    fn resolve(self, name: String, config: &Config) -> Result<Profile, String> {
+        let is_default = name.eq_ignore_ascii_case(DEFAULT_PROFILE);
+        let has_pattern = self.exe.is_some() || self.class.is_some();
+        if is_default && has_pattern {
+            return Err(format!(
+                "the `{DEFAULT_PROFILE}` profile applies when nothing matches; remove `exe` and `class`"
+            ));
+        }
+        if !is_default && !has_pattern {
+            return Err("a profile needs `exe`, `class` or both".to_string());
+        }
+        let action = match (self.preset, self.hide) {
+            (Some(_), true) => {
+                return Err("`preset` and `hide = true` exclude each other".to_string());
+            }
+            (None, false) => return Err("a profile needs `preset` or `hide = true`".to_string()),
+            (None, true) => ProfileAction::Hide,
+            (Some(preset), false) => match config.preset(&preset) {
+                Some(preset) => ProfileAction::Preset(preset.name.to_string()),
+                None => {
+                    return Err(format!(
+                        "unknown preset `{preset}`; expected one of {}",
+                        config.preset_names()
+                    ));
+                }
+            },
+        };
+        Ok(Profile {
+            name,
+            exe: self.exe,
+            class: self.class,
+            priority: self.priority,
+            action,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PassFile {
+    shader: Option<PathBuf>,
+    #[serde(default, deserialize_with = "pass_scale")]
+    scale: Option<f32>,
+}
+
+impl PassFile {
+    /// Reads the shader from `dir`; compiling it is up to the renderer.
+    fn resolve(self, dir: &Path) -> Result<Pass, String> {
+        let shader = match self.shader {
+            Some(path) => {
+                let path = dir.join(path);
+                let source = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("Failed to read shader {}: {err}", path.display()))?;
+                PassShader::File { path, source }
+            }
+            None => PassShader::Filter,
+        };
+        Ok(Pass {
+            shader,
+            scale: self.scale.unwrap_or(1.0),
+        })
+    }
+}
+
+fn finite<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(de::Error::custom("value must be a finite number"))
+    }
+}
+
+fn gamma<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let value = finite(deserializer)?;
+    if GAMMA_RANGE.contains(&value) {
+        Ok(Some(value))
+    } else {
+        Err(de::Error::custom(format!(
+            "gamma must be between {} and {}",
+            GAMMA_RANGE.start(),
+            GAMMA_RANGE.end()
+        )))
+    }
+}
+
+fn unit_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let value = finite(deserializer)?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(Some(value))
+    } else {
+        Err(de::Error::custom("value must be between 0 and 1"))
+    }
+}
+
+fn luma_weights<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[f32; 3]>, D::Error> {
+    let weights = <[f32; 3]>::deserialize(deserializer)?;
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+        return Err(de::Error::custom(
+            "luma_weights must be three non-negative numbers with a positive sum",
+        ));
+    }
+    Ok(Some(weights))
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn fps_cap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match u32::deserialize(deserializer)? {
+        0 => Err(de::Error::custom("fps_cap must be at least 1")),
+        fps => Ok(Some(fps)),
+    }
+}
+
+/// Longest decay window `flash_decay` accepts, in seconds.
+pub const MAX_FLASH_DECAY: f32 = 10.0;
+
+/// Seconds, where `0` turns flash dampening off.
+fn flash_decay<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let seconds = finite(deserializer)?;
+    if (0.0..=MAX_FLASH_DECAY).contains(&seconds) {
+        Ok(Some(Duration::from_secs_f32(seconds)))
+    } else {
+        Err(de::Error::custom(format!(
+            "flash_decay must be between 0 and {MAX_FLASH_DECAY} seconds"
+        )))
+    }
+}
+
+fn pass_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let scale = finite(deserializer)?;
+    if (passes::MIN_SCALE..=1.0).contains(&scale) {
+        Ok(Some(scale))
+    } else {
+        Err(de::Error::custom(format!(
+            "scale must be between {} and 1",
+            passes::MIN_SCALE
+        )))
+    }
+}
+
+/// `monitor = "all"`, `monitor = 2` or `monitor = [1, "DELL U2720Q"]`.
+fn monitors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<MonitorSelection>, D::Error> {
+    struct MonitorsVisitor;
+
+    impl<'de> Visitor<'de> for MonitorsVisitor {
+        type Value = Vec<MonitorSelection>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a monitor index, a monitor name, or a list of them")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(vec![value.parse().map_err(E::custom)?])
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            match usize::try_from(value) {
+                Ok(index) => self.visit_str(&index.to_string()),
+                Err(_) => Err(E::custom("monitor indices start at 1")),
+            }
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut selections = Vec::new();
+            while let Some(selection) = seq.next_element::<MonitorElement>()? {
+                selections.push(selection.0);
+            }
+            Ok(selections)
+        }
+    }
+
+    struct MonitorElement(MonitorSelection);
+
+    impl<'de> Deserialize<'de> for MonitorElement {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let mut selections = deserializer.deserialize_any(MonitorsVisitor)?;
+            match selections.len() {
+                1 => Ok(Self(selections.remove(0))),
+                _ => Err(de::Error::custom("monitor lists cannot be nested")),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(MonitorsVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::ForegroundApp;
+
+    #[test]
+    fn empty_file_is_the_builtin_presets() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parses_a_full_preset() {
+        let config = Config::parse(
+            r#"
+default_preset = "night"
+
+[presets.night]
+gamma = 0.6
+protect_low = 0.1
+protect_high = 0.4
+luma_weights = [1, 1, 2]
+target = "process:game.exe"
+monitor = [2, "primary"]
+fps_cap = 60
+adaptive = true
+flash_decay = 1.5
+flash_safety = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.default_preset, "night");
+        let night = config.preset("Night").unwrap();
+        assert_eq!(night.params.gamma, 0.6);
+        assert_eq!(
+            (night.params.protect_low, night.params.protect_high),
+            (0.1, 0.4)
+        );
+        assert_eq!(night.params.luma_weights, [0.25, 0.25, 0.5]);
+        assert_eq!(
+            night.target,
+            Some(CaptureTarget::Process("game.exe".into()))
+        );
+        assert_eq!(
+            night.monitors,
+            [MonitorSelection::Index(2), MonitorSelection::Primary]
+        );
+        assert_eq!(night.fps_cap, Some(60));
+        assert!(night.adaptive);
+        assert_eq!(night.flash_decay, Some(Duration::from_millis(1500)));
+        assert!(night.flash_safety);
+    }
+
+    #[test]
+    fn presets_override_builtins_in_place_and_append_in_file_order() {
+        let config = Config::parse(
+            "[presets.zeta]\n[presets.STRONG]\nprotect_high = 0.4\n[presets.alpha]\nmonitor = \"all\"\n",
+        )
+        .unwrap();
+        let names: Vec<&str> = config.presets.iter().map(|p| &*p.name).collect();
+        assert_eq!(names, ["subtle", "default", "STRONG", "zeta", "alpha"]);
+
+        let strong = config.preset("strong").unwrap();
+        assert_eq!(
+            strong.params.gamma,
+            presets::find("strong").unwrap().params.gamma
+        );
+        assert_eq!(strong.params.protect_high, 0.4);
+        assert_eq!(
+            config.preset("alpha").unwrap().monitors,
+            [MonitorSelection::All]
+        );
+        assert_eq!(config.preset("zeta").unwrap().params, FilterParams::DEFAULT);
+    }
+
+    #[test]
+    fn parses_profiles_against_the_presets() {
+        let config = Config::parse(
+            r#"
+[profiles.elden]
+exe = "eldenring.exe"
+preset = "Strong"
+priority = 2
+
+[profiles.default]
+hide = true
+"#,
+        )
+        .unwrap();
+        let elden = ForegroundApp {
+            exe: "eldenring.exe".to_string(),
+            class: String::new(),
+        };
+        let profile = config.profiles.select(&elden).unwrap();
+        assert_eq!(
+            (profile.priority, &profile.action),
+            (2, &ProfileAction::Preset("strong".to_string()))
+        );
+        let other = ForegroundApp::default();
+        assert_eq!(
+            config.profiles.select(&other).unwrap().action,
+            ProfileAction::Hide
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_profiles() {
+        for (source, message) in [
+            (
+                "[profiles.a]
+exe = \"a.exe\"
+preset = \"nope\"
+",
+                "unknown preset `nope`",
+            ),
+            (
+                "[profiles.a]
+preset = \"strong\"
+",
+                "needs `exe`, `class`",
+            ),
+            (
+                "[profiles.a]
+class = \"x\"
+",
+                "needs `preset`",
+            ),
+            (
+                "[profiles.a]
+class = \"x\"
+preset = \"strong\"
+hide = true
+",
+                "exclude",
+            ),
+            (
+                "[profiles.default]
+exe = \"*\"
+hide = true
+",
+                "remove `exe`",
+            ),
+            (
+                "[profiles.a]
+exe = \"re:(\"
+hide = true
+",
+                "invalid regular expression",
+            ),
+        ] {
+            let err = Config::parse(source).unwrap_err();
+            assert!(err.message.contains(message), "{source}: {err}");
+            assert_eq!(
+                err.line,
+                if message.contains("regular") { 2 } else { 1 },
+                "{source}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn syntax_errors_report_line_and_column() {
+        let err = Config::parse("[presets.night]\ngamma = = 0.5\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9), "{err}");
+    }
+
+    #[test]
+    fn value_errors_point_at_the_value() {
+        let err = Config::parse("[presets.night]\n  gamma = 3.0\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 11), "{err}");
+        assert!(err.message.contains("gamma must be between"), "{err}");
+
+        let err = Config::parse("[presets.a]\ntarget = \"window:x\"\n").unwrap_err();
+        assert_eq!(err.line, 2, "{err}");
+        assert!(err.message.contains("unknown target"), "{err}");
+
+        let err = Config::parse("[presets.a]\nfps_cap = 0\n").unwrap_err();
+        assert!(err.message.contains("at least 1"), "{err}");
+
+        let err = Config::parse("[presets.a]\nflash_decay = -1\n").unwrap_err();
+        assert!(err.message.contains("flash_decay must be between"), "{err}");
+
+        let err = Config::parse("[presets.a]\nmonitor = [0]\n").unwrap_err();
+        assert!(err.message.contains("start at 1"), "{err}");
+    }
+
+    #[test]
+    fn luts_load_relative_to_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("ban-shadow-lut-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity: String = (0..8)
+            .map(|i| format!("{} {} {}\n", i & 1, i >> 1 & 1, i >> 2))
+            .collect();
+        std::fs::write(dir.join("grade.cube"), format!("LUT_3D_SIZE 2\n{identity}")).unwrap();
+        let path = dir.join(FILE_NAME);
+        std::fs::write(
+            &path,
+            "[presets.graded]\nlut = \"grade.cube\"\nlut_interpolation = \"trilinear\"\n\n[presets.missing]\nlut = \"missing.cube\"\n",
+        )
+        .unwrap();
+        let err = Config::load(&path).unwrap_err().to_string();
+        std::fs::write(
+            &path,
+            "[presets.graded]\nlut = \"grade.cube\"\nlut_interpolation = \"trilinear\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            err.contains(":5:1: Failed to read LUT") && err.contains("missing.cube"),
+            "{err}"
+        );
+        let stage = config.preset("graded").unwrap().lut.as_deref().unwrap();
+        assert_eq!(stage.lut, Lut3d::identity(2));
+        assert_eq!(stage.interpolation, Interpolation::Trilinear);
+        assert!(!stage.replaces_curve);
+
+        let err = Config::parse("[presets.a]\nlut_replaces_curve = true\n").unwrap_err();
+        assert!(err.message.contains("need `lut`"), "{err}");
+    }
+
+    #[test]
+    fn passes_keep_their_order_and_read_shaders_beside_the_file() {
+        let dir = std::env::temp_dir().join(format!("ban-shadow-passes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let shader = "float4 ps_main(VSOut input) : SV_Target { return 0; }\n";
+        std::fs::write(dir.join("denoise.hlsl"), shader).unwrap();
+        let path = dir.join(FILE_NAME);
+        std::fs::write(
+            &path,
+            "[[passes]]\nshader = \"denoise.hlsl\"\nscale = 0.5\n\n[[passes]]\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        std::fs::write(&path, "[[passes]]\nshader = \"missing.hlsl\"\n").unwrap();
+        let err = Config::load(&path).unwrap_err().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            config.passes,
+            [
+                Pass {
+                    shader: PassShader::File {
+                        path: dir.join("denoise.hlsl"),
+                        source: shader.to_string(),
+                    },
+                    scale: 0.5,
+                },
+                Pass::FILTER,
+            ]
+        );
+        assert!(
+            err.contains("Failed to read shader") && err.contains("missing.hlsl"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn pass_scales_are_checked() {
+        let err = Config::parse("[[passes]]\nscale = 0.5\n").unwrap_err();
+        assert!(err.message.contains("last pass"), "{err}");
+
+        let err = Config::parse("[[passes]]\nscale = 2\n\n[[passes]]\n").unwrap_err();
+        assert_eq!(err.line, 2, "{err}");
+        assert!(err.message.contains("scale must be between"), "{err}");
+
+        let config = Config::parse("[[passes]]\n").unwrap();
+        assert_eq!(config.passes, [Pass::FILTER]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = Config::parse("[presets.a]\ngama = 0.5\n").unwrap_err();
+        assert_eq!(err.line, 2, "{err}");
+        assert!(err.message.contains("gama"), "{err}");
+    }
+
+    #[test]
+    fn unordered_protect_thresholds_are_rejected() {
+        let err =
+            Config::parse("\n[presets.a]\nprotect_low = 0.5\nprotect_high = 0.2\n").unwrap_err();
+        assert!(err.message.contains("protect_low"), "{err}");
+        assert_eq!(err.line, 2, "{err}");
+    }
+
+    #[test]
+    fn unknown_default_preset_lists_the_choices() {
+        let err = Config::parse("default_preset = \"nope\"\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 18), "{err}");
+        assert!(err.message.contains("subtle, default, strong"), "{err}");
+    }
+
+    #[test]
+    fn errors_display_path_line_and_column() {
+        let err = ConfigError {
+            path: Some(PathBuf::from("ban-shadow.toml")),
+            ..Config::parse("x = 1").unwrap_err()
+        };
+        assert!(
+            err.to_string().starts_with("ban-shadow.toml:1:1: "),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Reloads the config file, or any other watched file, whenever it changes on disk.
+
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::config::Config;
+
+/// Editors often save in several steps; wait this long after the last change before
+/// reading the file.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Keeps watching for as long as it is alive.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    /// Calls `on_change` on a thread named `thread_name` each time the file at `path`
+    /// changes and then stays put for [`SETTLE_TIME`].
+    pub fn spawn(
+        path: &Path,
+        thread_name: &str,
+        mut on_change: impl FnMut(&Path) + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to watch {}", path.display()))?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        // Watch the directory: editors that save by renaming replace the file itself.
+        let directory = path.parent().unwrap_or(Path::new("."));
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+        thread::Builder::new()
+            .name(thread_name.to_string())
+            .spawn(move || {
+                while wait_for_change(&events, &path) {
+                    on_change(&path);
+                }
+            })?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// Keeps watching for as long as it is alive.
+pub struct ConfigWatcher {
+    _watcher: FileWatcher,
+}
+
+impl ConfigWatcher {
+    /// Calls `on_change` on a thread of its own with each new version of the file at
+    /// `path` that loads. Edits that fail to load are reported and otherwise ignored, so
+    /// the last good config stays in effect.
+    pub fn spawn(
+        path: &Path,
+        mut on_change: impl FnMut(Config) + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let mut last_good = Config::load(path).ok();
+        let watcher =
+            FileWatcher::spawn(path, "config-watch", move |path| match Config::load(path) {
+                Ok(config) if last_good.as_ref() != Some(&config) => {
+                    last_good = Some(config.clone());
+                    on_change(config);
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("Keeping the previous config: {err:#}"),
+            })?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// Blocks until `path` changed and then stayed put for [`SETTLE_TIME`]; `false` once the
+/// watcher is gone.
+fn wait_for_change(events: &Receiver<notify::Result<notify::Event>>, path: &Path) -> bool {
+    loop {
+        let Ok(event) = events.recv() else {
+            return false;
+        };
+        if touches(&event, path) {
+            break;
+        }
+    }
+    loop {
+        match events.recv_timeout(SETTLE_TIME) {
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+fn touches(event: &notify::Result<notify::Event>, path: &Path) -> bool {
+    match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|changed| same_file(changed, path))
+        }
+        Err(err) => {
+            eprintln!("File watcher: {err}");
+            false
+        }
+    }
+}
+
+fn same_file(changed: &Path, path: &Path) -> bool {
+    changed == path || changed.canonicalize().is_ok_and(|changed| changed == path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reports_good_edits_and_skips_bad_ones() {
+        let dir = std::env::temp_dir().join(format!("ban-shadow-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "[presets.night]\ngamma = 0.6\n").unwrap();
+
+        let (sender, reloads) = mpsc::channel();
+        let _watcher = ConfigWatcher::spawn(&path, move |config| {
+            sender.send(config).unwrap();
+        })
+        .unwrap();
+
+        fs::write(&path, "[presets.night]\ngamma = 7\n").unwrap();
+        thread::sleep(SETTLE_TIME * 2);
+        fs::write(&path, "[presets.night]\ngamma = 0.4\n").unwrap();
+        let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config.preset("night").unwrap().params.gamma, 0.4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
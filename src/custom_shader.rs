@@ -0,0 +1,72 @@
+//! Pixel shaders loaded from an HLSL file instead of the built-in `ps_main`.
+//!
+//! The file is compiled after [`INTERFACE`], which declares everything the renderer
+//! binds: the image to filter `t_diffuse` (t0), its sampler `s_diffuse` (s0), the LUT
+//! `t_lut` (t1), the captured frame `t_frame` (t2) and the `FilterParams` constant
+//! buffer (b0) holding the current preset.
+//! The file itself only has to define `float4 ps_main(VSOut input) : SV_Target`.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+/// Declarations every pixel shader is compiled with, the built-in one included.
+pub const INTERFACE: &str = include_str!("shader_interface.hlsl");
+
+/// Function the file has to define.
+pub const ENTRY_POINT: &str = "ps_main";
+
+/// Reads the shader at `path` and returns it ready to compile, naming the file in any
+/// error.
+pub fn load(path: &Path) -> anyhow::Result<String> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read shader {}", path.display()))?;
+    Ok(with_interface(path, &source))
+}
+
+/// Prepends [`INTERFACE`] to `source`, keeping compiler messages pointing at the lines
+/// of the file at `path`.
+pub fn with_interface(path: &Path, source: &str) -> String {
+    let name = path
+        .display()
+        .to_string()
+        .replace('\\', "/")
+        .replace('"', "");
+    format!("{INTERFACE}\n#line 1 \"{name}\"\n{source}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_follows_the_interface_at_its_own_line_numbers() {
+        let source = with_interface(
+            Path::new(r"C:\shaders\tint.hlsl"),
+            "float4 ps_main(VSOut input) : SV_Target { return 1; }\n",
+        );
+        assert!(source.starts_with(INTERFACE));
+        let rest = &source[INTERFACE.len()..];
+        assert!(rest.starts_with("\n#line 1 \"C:/shaders/tint.hlsl\"\nfloat4 ps_main"));
+    }
+
+    #[test]
+    fn interface_declares_what_the_renderer_binds() {
+        for declaration in [
+            "Texture2D t_diffuse : register(t0);",
+            "SamplerState s_diffuse : register(s0);",
+            "Texture3D t_lut : register(t1);",
+            "Texture2D t_frame : register(t2);",
+            "cbuffer FilterParams : register(b0)",
+            "struct VSOut",
+        ] {
+            assert!(INTERFACE.contains(declaration), "{declaration}");
+        }
+    }
+
+    #[test]
+    fn missing_file_is_named() {
+        let err = load(Path::new("no-such-shader.hlsl")).unwrap_err();
+        assert!(format!("{err:#}").contains("no-such-shader.hlsl"));
+    }
+}
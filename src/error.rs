@@ -0,0 +1,108 @@
+//! Failures that stop the overlay, each with a hint on what to try and its own exit code,
+//! so the frontends can shut down cleanly instead of panicking.
+
+use std::fmt;
+
+/// Why the overlay could not start or had to stop.
+#[derive(Debug)]
+pub enum OverlayError {
+    /// The config file, a preset, a LUT or a command-line setting is unusable.
+    Config(anyhow::Error),
+    /// The screen, monitor or target window cannot be captured.
+    Capture(anyhow::Error),
+    /// There is no device to filter on, or it failed.
+    Device(anyhow::Error),
+    /// The overlay window or its swapchain cannot be created or resized.
+    Swapchain(anyhow::Error),
+    /// The built-in, `--shader` or pass shaders do not compile.
+    Shader(anyhow::Error),
+}
+
+impl OverlayError {
+    /// Distinct per kind; 1 is left for other errors and 2 for command-line usage.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Config(_) => 3,
+            Self::Capture(_) => 4,
+            Self::Device(_) => 5,
+            Self::Swapchain(_) => 6,
+            Self::Shader(_) => 7,
+        }
+    }
+
+    /// What the user can try next.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::Config(_) => {
+                "check the config file and the command-line flags; the built-in presets are used without `--config`"
+            }
+            Self::Capture(_) => {
+                "check that the target is open and visible, and pick monitors from `--list-monitors`"
+            }
+            Self::Device(_) => {
+                "update the graphics driver, or pass `--renderer cpu` to filter without a GPU"
+            }
+            Self::Swapchain(_) => {
+                "make sure a desktop session is running and unlocked, then try again"
+            }
+            Self::Shader(_) => {
+                "fix the shader the message points at, or leave out `--shader` and the passes"
+            }
+        }
+    }
+
+    fn cause(&self) -> &anyhow::Error {
+        match self {
+            Self::Config(err)
+            | Self::Capture(err)
+            | Self::Device(err)
+            | Self::Swapchain(err)
+            | Self::Shader(err) => err,
+        }
+    }
+}
+
+impl fmt::Display for OverlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Config(_) => "Invalid configuration",
+            Self::Capture(_) => "Capture failed",
+            Self::Device(_) => "No usable graphics device",
+            Self::Swapchain(_) => "Cannot present the overlay",
+            Self::Shader(_) => "Shader failed to compile",
+        };
+        write!(f, "{kind}: {:#}", self.cause())
+    }
+}
+
+impl std::error::Error for OverlayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_have_distinct_exit_codes() {
+        let errors = [
+            OverlayError::Config(anyhow::anyhow!("a")),
+            OverlayError::Capture(anyhow::anyhow!("a")),
+            OverlayError::Device(anyhow::anyhow!("a")),
+            OverlayError::Swapchain(anyhow::anyhow!("a")),
+            OverlayError::Shader(anyhow::anyhow!("a")),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(OverlayError::exit_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|&code| code > 2));
+    }
+
+    #[test]
+    fn survives_anyhow_context() {
+        let err = anyhow::Error::new(OverlayError::Capture(anyhow::anyhow!("gone")))
+            .context("Failed to start the overlay");
+        let overlay = err.downcast_ref::<OverlayError>().unwrap();
+        assert_eq!(overlay.exit_code(), 4);
+        assert_eq!(overlay.to_string(), "Capture failed: gone");
+    }
+}
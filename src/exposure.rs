@@ -0,0 +1,437 @@
+//! Adaptive exposure: derives the curve from each frame's luma histogram.
+//!
+//! The overlay measures a downscaled copy of every captured frame; this module turns
+//! those measurements into [`FilterParams`] and eases towards them so the image does not
+//! pump when the scene changes. It is plain arithmetic on histograms, so it is tested
+//! with synthetic frame statistics instead of captures.
+
+use std::time::Duration;
+
+use anyhow::bail;
+
+use crate::filter::{self, PixelFormat};
+use crate::params::FilterParams;
+
+pub const HISTOGRAM_BINS: usize = 64;
+
+/// Luma values of one frame, counted in equal-width bins over `0..=1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    bins: [u32; HISTOGRAM_BINS],
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            bins: [0; HISTOGRAM_BINS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn from_lumas(lumas: impl IntoIterator<Item = f32>) -> Self {
+        let mut histogram = Self::default();
+        lumas.into_iter().for_each(|luma| histogram.add(luma));
+        histogram
+    }
+
+    /// Measures a tightly packed pixel buffer with `weights`, like the overlay's probe.
+    pub fn from_pixels(
+        buffer: &[u8],
+        format: PixelFormat,
+        weights: [f32; 3],
+    ) -> anyhow::Result<Self> {
+        let bpp = format.bytes_per_pixel();
+        if !buffer.len().is_multiple_of(bpp) {
+            bail!(
+                "Buffer length {} is not a multiple of {bpp} bytes per pixel",
+                buffer.len()
+            );
+        }
+        Ok(Self::from_lumas(buffer.chunks_exact(bpp).map(|px| {
+            filter::luma(filter::read_rgb(px, format), weights)
+        })))
+    }
+
+    /// Counts one sample; values outside `0..=1` land in the first or last bin.
+    pub fn add(&mut self, luma: f32) {
+        let bin = if luma.is_nan() {
+            0
+        } else {
+            ((luma.clamp(0.0, 1.0) * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+        };
+        self.bins[bin] += 1;
+        self.total += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Average luma, taking each sample at the centre of its bin. `None` when empty.
+    pub fn mean(&self) -> Option<f32> {
+        if self.total == 0 {
+            return None;
+        }
+        let sum: f64 = self
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| (index as f64 + 0.5) * f64::from(count))
+            .sum();
+        Some((sum / self.total as f64 / HISTOGRAM_BINS as f64) as f32)
+    }
+
+    /// The luma below which `fraction` of the samples lie, interpolated within its bin.
+    /// `None` for an empty histogram.
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        if self.total == 0 {
+            return None;
+        }
+        let wanted = f64::from(fraction.clamp(0.0, 1.0)) * self.total as f64;
+        let mut below = 0.0;
+        for (index, &count) in self.bins.iter().enumerate() {
+            let count = f64::from(count);
+            if count > 0.0 && below + count >= wanted {
+                let within = ((wanted - below) / count) as f32;
+                return Some((index as f32 + within) / HISTOGRAM_BINS as f32);
+            }
+            below += count;
+        }
+        Some(1.0)
+    }
+}
+
+/// A downscaled frame's lumas, row by row, as the overlay's probe measures them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LumaGrid {
+    width: usize,
+    height: usize,
+    lumas: Vec<f32>,
+}
+
+impl LumaGrid {
+    /// `lumas` holds `width * height` values, row by row.
+    pub fn new(width: usize, height: usize, lumas: Vec<f32>) -> Self {
+        assert_eq!(lumas.len(), width * height, "luma grid size mismatch");
+        Self {
+            width,
+            height,
+            lumas,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The lumas of row `y`.
+    pub fn row(&self, y: usize) -> &[f32] {
+        &self.lumas[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn histogram(&self) -> Histogram {
+        Histogram::from_lumas(self.lumas.iter().copied())
+    }
+}
+
+/// How the adaptive curve follows the scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExposureSettings {
+    /// Median luma a dark scene is lifted towards; scenes already this bright get no lift.
+    pub target_key: f32,
+    /// Seconds to cover most of the way when the scene darkens and the lift grows.
+    pub brighten_time: f32,
+    /// Seconds to cover most of the way when the scene brightens and the lift shrinks;
+    /// shorter, so a sudden bright scene is not over-lifted for long.
+    pub darken_time: f32,
+    /// Differences smaller than this are left alone so noise does not keep the curve
+    /// moving.
+    pub deadband: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        Self {
+            target_key: 0.25,
+            brighten_time: 1.2,
+            darken_time: 0.3,
+            deadband: 0.005,
+        }
+    }
+}
+
+/// Smallest and largest `protect_high`: always lift the deepest shadows, never the
+/// highlights.
+const PROTECT_HIGH_RANGE: (f32, f32) = (0.1, 0.6);
+
+/// The curve a single frame asks for, or `None` without samples.
+///
+/// `gamma` is chosen so the median luma lands on the target key, limited by
+/// `base.gamma` as the strongest lift allowed. Everything up to twice the median is
+/// lifted and the darkest tenth of the frame gets the full lift.
+pub fn target_params(
+    histogram: &Histogram,
+    base: FilterParams,
+    settings: &ExposureSettings,
+) -> Option<FilterParams> {
+    let median = histogram.percentile(0.5)?;
+    let key = settings.target_key.clamp(0.01, 0.99);
+    // The darkest bin still has a width, so a black frame asks for the strongest lift
+    // rather than a division by `ln(0)`.
+    let median_for_key = median.max(0.5 / HISTOGRAM_BINS as f32);
+    let gamma = if median_for_key >= key {
+        1.0
+    } else {
+        key.ln() / median_for_key.ln()
+    };
+    let protect_high = (median * 2.0).clamp(PROTECT_HIGH_RANGE.0, PROTECT_HIGH_RANGE.1);
+    let protect_low = histogram.percentile(0.1)?.min(protect_high * 0.5);
+    Some(
+        FilterParams {
+            gamma: gamma.clamp(base.gamma, 1.0),
+            protect_low,
+            protect_high,
+            luma_weights: base.luma_weights,
+        }
+        .clamped(),
+    )
+}
+
+/// Eases the curve towards what each measured frame asks for.
+#[derive(Clone, Debug)]
+pub struct ExposureController {
+    settings: ExposureSettings,
+    current: Option<FilterParams>,
+    settled: bool,
+}
+
+impl Default for ExposureController {
+    fn default() -> Self {
+        Self::new(ExposureSettings::default())
+    }
+}
+
+impl ExposureController {
+    pub fn new(settings: ExposureSettings) -> Self {
+        Self {
+            settings,
+            current: None,
+            settled: true,
+        }
+    }
+
+    /// The curve in effect, `None` before the first measurement.
+    pub fn current(&self) -> Option<FilterParams> {
+        self.current
+    }
+
+    /// Whether the last update reached its target; otherwise more updates are needed
+    /// even if the scene stays the same.
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    /// Moves the curve towards what `histogram` asks for, `elapsed` after the previous
+    /// update. The first measurement is taken as is.
+    pub fn update(
+        &mut self,
+        histogram: &Histogram,
+        base: FilterParams,
+        elapsed: Duration,
+    ) -> FilterParams {
+        let Some(target) = target_params(histogram, base, &self.settings) else {
+            return self.current.unwrap_or(base);
+        };
+        let Some(current) = self.current else {
+            self.current = Some(target);
+            self.settled = true;
+            return target;
+        };
+
+        let time_constant = if target.gamma < current.gamma {
+            self.settings.brighten_time
+        } else {
+            self.settings.darken_time
+        };
+        let step = 1.0 - (-elapsed.as_secs_f32() / time_constant.max(f32::EPSILON)).exp();
+        let deadband = self.settings.deadband;
+        let ease = |from: f32, to: f32| {
+            if (to - from).abs() <= deadband {
+                from
+            } else {
+                from + (to - from) * step
+            }
+        };
+        let next = FilterParams {
+            gamma: ease(current.gamma, target.gamma),
+            protect_low: ease(current.protect_low, target.protect_low),
+            protect_high: ease(current.protect_high, target.protect_high),
+            luma_weights: base.luma_weights,
+        }
+        .clamped();
+        self.settled = (next.gamma - target.gamma).abs() <= deadband
+            && (next.protect_low - target.protect_low).abs() <= deadband
+            && (next.protect_high - target.protect_high).abs() <= deadband;
+        self.current = Some(next);
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    /// A frame where every pixel has one of `lumas`, in equal shares.
+    fn scene(lumas: &[f32]) -> Histogram {
+        Histogram::from_lumas(lumas.iter().cycle().take(lumas.len() * 100).copied())
+    }
+
+    fn base() -> FilterParams {
+        FilterParams {
+            gamma: 0.3,
+            ..FilterParams::DEFAULT
+        }
+    }
+
+    fn target(histogram: &Histogram) -> FilterParams {
+        target_params(histogram, base(), &ExposureSettings::default()).unwrap()
+    }
+
+    #[test]
+    fn percentiles_interpolate_within_bins() {
+        let histogram = Histogram::from_lumas((0..1000).map(|i| i as f32 / 1000.0));
+        assert_eq!(histogram.total(), 1000);
+        assert!((histogram.percentile(0.5).unwrap() - 0.5).abs() < 0.02);
+        assert!((histogram.percentile(0.1).unwrap() - 0.1).abs() < 0.02);
+        assert_eq!(Histogram::default().percentile(0.5), None);
+        assert!((histogram.mean().unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(Histogram::default().mean(), None);
+    }
+
+    #[test]
+    fn out_of_range_samples_land_in_the_end_bins() {
+        let histogram = Histogram::from_lumas([-1.0, f32::NAN, 2.0]);
+        assert_eq!(histogram.bins[0], 2);
+        assert_eq!(histogram.bins[HISTOGRAM_BINS - 1], 1);
+    }
+
+    #[test]
+    fn darker_scenes_get_more_lift() {
+        let dim = target(&scene(&[0.05, 0.1, 0.15]));
+        let dark = target(&scene(&[0.02, 0.04, 0.06]));
+        assert!(dark.gamma < dim.gamma, "{dark:?} vs {dim:?}");
+        assert!(dim.gamma < 1.0);
+    }
+
+    #[test]
+    fn bright_scenes_are_left_alone() {
+        let bright = target(&scene(&[0.3, 0.6, 0.9]));
+        assert_eq!(bright.gamma, 1.0);
+        assert_eq!(bright.protect_high, PROTECT_HIGH_RANGE.1);
+    }
+
+    #[test]
+    fn lift_never_exceeds_the_base_strength() {
+        let black = target(&scene(&[0.0]));
+        assert_eq!(black.gamma, base().gamma);
+        assert_eq!(black.protect_high, PROTECT_HIGH_RANGE.0);
+        assert!(black.protect_low < black.protect_high);
+    }
+
+    #[test]
+    fn median_lands_on_the_key_when_unclamped() {
+        let histogram = scene(&[0.08, 0.1, 0.12]);
+        let params = target(&histogram);
+        let median = histogram.percentile(0.5).unwrap();
+        assert!((median.powf(params.gamma) - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn first_update_snaps_then_eases_without_overshoot() {
+        let mut controller = ExposureController::default();
+        let bright = scene(&[0.5]);
+        let dark = scene(&[0.03]);
+        assert_eq!(controller.update(&bright, base(), FRAME).gamma, 1.0);
+
+        let goal = target(&dark).gamma;
+        let mut previous = 1.0;
+        for _ in 0..10 {
+            let gamma = controller.update(&dark, base(), FRAME).gamma;
+            assert!(gamma < previous && gamma > goal, "{gamma}");
+            previous = gamma;
+        }
+        assert!(!controller.is_settled());
+        for _ in 0..1000 {
+            controller.update(&dark, base(), FRAME);
+        }
+        assert!(controller.is_settled());
+        assert!((controller.current().unwrap().gamma - goal).abs() <= 0.005);
+    }
+
+    #[test]
+    fn lift_shrinks_faster_than_it_grows() {
+        let bright = scene(&[0.5]);
+        let dark = scene(&[0.03]);
+        let half_way = |from: &Histogram, to: &Histogram| {
+            let mut controller = ExposureController::default();
+            controller.update(from, base(), FRAME);
+            let goal = target(to).gamma;
+            let start = controller.current().unwrap().gamma;
+            (1..)
+                .find(|_| {
+                    let gamma = controller.update(to, base(), FRAME).gamma;
+                    (gamma - start).abs() >= (goal - start).abs() / 2.0
+                })
+                .unwrap()
+        };
+        assert!(half_way(&dark, &bright) < half_way(&bright, &dark));
+    }
+
+    #[test]
+    fn small_changes_do_not_move_the_curve() {
+        let mut controller = ExposureController::default();
+        let first = controller.update(&scene(&[0.1]), base(), FRAME);
+        let nudged = controller.update(&scene(&[0.1005]), base(), FRAME);
+        assert_eq!(first, nudged);
+        assert!(controller.is_settled());
+    }
+
+    #[test]
+    fn empty_frames_keep_the_current_curve() {
+        let mut controller = ExposureController::default();
+        assert_eq!(
+            controller.update(&Histogram::default(), base(), FRAME),
+            base()
+        );
+        let first = controller.update(&scene(&[0.1]), base(), FRAME);
+        assert_eq!(
+            controller.update(&Histogram::default(), base(), FRAME),
+            first
+        );
+    }
+
+    #[test]
+    fn measures_pixel_buffers() {
+        let pixels = [[0u8, 0, 0, 255], [255, 255, 255, 255]].concat();
+        let histogram = Histogram::from_pixels(
+            &pixels,
+            PixelFormat::Rgba8,
+            FilterParams::DEFAULT.luma_weights,
+        )
+        .unwrap();
+        assert_eq!(
+            (histogram.bins[0], histogram.bins[HISTOGRAM_BINS - 1]),
+            (1, 1)
+        );
+        assert!(Histogram::from_pixels(&[0; 3], PixelFormat::Rgba8, [1.0, 0.0, 0.0]).is_err());
+    }
+}
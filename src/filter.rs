@@ -0,0 +1,320 @@
+//! CPU reference implementation of the shadow-lift filter.
+//!
+//! Everything here mirrors `ps_main` in `shader.hlsl` operation for operation, so the
+//! GPU output can be checked against it without a Windows machine.
+
+use anyhow::bail;
+use half::f16;
+
+use crate::flash;
+use crate::flash_safety::SAFETY_CUT;
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+
+/// Pixel layouts the capture side can hand us, matching `windows_capture::settings::ColorFormat`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    #[default]
+    Bgra8,
+    Rgba16F,
+}
+
+impl PixelFormat {
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8 | Self::Bgra8 => 4,
+            Self::Rgba16F => 8,
+        }
+    }
+}
+
+/// `dot(rgb, weights)`; the default weights are Rec.709.
+pub fn luma(rgb: [f32; 3], weights: [f32; 3]) -> f32 {
+    rgb[0] * weights[0] + rgb[1] * weights[1] + rgb[2] * weights[2]
+}
+
+/// HLSL `smoothstep`.
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// HLSL `lerp`.
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Applies the filter to one linear-in-texture RGB value, exactly like `ps_main`.
+pub fn shade(rgb: [f32; 3], params: &FilterParams) -> [f32; 3] {
+    shade_damped(rgb, params, 0.0)
+}
+
+/// [`shade`] while a flash is being damped by `damping` from `0` to `1`.
+pub fn shade_damped(rgb: [f32; 3], params: &FilterParams, damping: f32) -> [f32; 3] {
+    let luma = luma(rgb, params.luma_weights);
+    let gamma = lerp(params.gamma, 1.0, damping);
+    let protect = smoothstep(params.protect_low, params.protect_high, luma);
+    let cut = 1.0 - damping * flash::FLASH_CUT * smoothstep(flash::FLASH_KNEE, 1.0, luma);
+    rgb.map(|c| lerp(c.powf(gamma), c, protect) * cut)
+}
+
+/// [`shade`] followed by the LUT, or the LUT alone if it replaces the curve, like
+/// `ps_main` with a LUT bound.
+pub fn grade(rgb: [f32; 3], params: &FilterParams, stage: &LutStage) -> [f32; 3] {
+    let shaded = if stage.replaces_curve {
+        rgb
+    } else {
+        shade(rgb, params)
+    };
+    stage.lut.sample(shaded, stage.interpolation)
+}
+
+/// Softens an already shaded value towards `level` by `strength` from `0` to `1`, like
+/// the end of `ps_main` does while the photosensitive safety mode is active.
+pub fn soften(rgb: [f32; 3], strength: f32, level: f32) -> [f32; 3] {
+    rgb.map(|c| lerp(c, level, strength * SAFETY_CUT))
+}
+
+/// Filters a tightly packed pixel buffer in place.
+///
+/// Alpha is written as fully opaque, since `ps_main` returns `1.0` for it.
+pub fn apply(buffer: &mut [u8], format: PixelFormat, params: &FilterParams) -> anyhow::Result<()> {
+    apply_with(buffer, format, |rgb| shade(rgb, params))
+}
+
+/// [`apply`] with an optional LUT stage, see [`grade`].
+pub fn apply_graded(
+    buffer: &mut [u8],
+    format: PixelFormat,
+    params: &FilterParams,
+    lut: Option<&LutStage>,
+) -> anyhow::Result<()> {
+    match lut {
+        Some(stage) => apply_with(buffer, format, |rgb| grade(rgb, params, stage)),
+        None => apply(buffer, format, params),
+    }
+}
+
+fn apply_with(
+    buffer: &mut [u8],
+    format: PixelFormat,
+    shade: impl Fn([f32; 3]) -> [f32; 3],
+) -> anyhow::Result<()> {
+    let bpp = format.bytes_per_pixel();
+    if !buffer.len().is_multiple_of(bpp) {
+        bail!(
+            "Buffer length {} is not a multiple of {bpp} bytes per pixel",
+            buffer.len()
+        );
+    }
+    let pixels = buffer.chunks_exact_mut(bpp);
+    match format {
+        PixelFormat::Rgba8 => pixels.for_each(|px| shade_unorm(px, 0, 2, &shade)),
+        PixelFormat::Bgra8 => pixels.for_each(|px| shade_unorm(px, 2, 0, &shade)),
+        PixelFormat::Rgba16F => pixels.for_each(|px| shade_f16(px, &shade)),
+    }
+    Ok(())
+}
+
+fn shade_unorm(px: &mut [u8], r: usize, b: usize, shade: impl Fn([f32; 3]) -> [f32; 3]) {
+    let rgb = [px[r], px[1], px[b]].map(unorm_to_f32);
+    let [out_r, out_g, out_b] = shade(rgb);
+    px[r] = f32_to_unorm(out_r);
+    px[1] = f32_to_unorm(out_g);
+    px[b] = f32_to_unorm(out_b);
+    px[3] = u8::MAX;
+}
+
+fn shade_f16(px: &mut [u8], shade: impl Fn([f32; 3]) -> [f32; 3]) {
+    let channel = |i: usize| f16::from_le_bytes([px[i * 2], px[i * 2 + 1]]).to_f32();
+    let rgb = shade([channel(0), channel(1), channel(2)]);
+    for (i, value) in rgb.into_iter().chain([1.0]).enumerate() {
+        px[i * 2..i * 2 + 2].copy_from_slice(&f16::from_f32(value).to_le_bytes());
+    }
+}
+
+/// Reads the RGB channels of one pixel, the way the shader samples them.
+pub(crate) fn read_rgb(px: &[u8], format: PixelFormat) -> [f32; 3] {
+    match format {
+        PixelFormat::Rgba8 => [px[0], px[1], px[2]].map(unorm_to_f32),
+        PixelFormat::Bgra8 => [px[2], px[1], px[0]].map(unorm_to_f32),
+        PixelFormat::Rgba16F => {
+            [0, 1, 2].map(|i| f16::from_le_bytes([px[i * 2], px[i * 2 + 1]]).to_f32())
+        }
+    }
+}
+
+pub(crate) fn unorm_to_f32(value: u8) -> f32 {
+    f32::from(value) / 255.0
+}
+
+/// D3D float to UNORM conversion: saturate, scale, round to nearest.
+pub(crate) fn f32_to_unorm(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lut::{Interpolation, Lut3d};
+
+    fn load_rgba8(name: &str) -> image::RgbaImage {
+        let path = format!("{}/{name}", env!("CARGO_MANIFEST_DIR"));
+        image::open(&path)
+            .unwrap_or_else(|err| panic!("Failed to open {path}: {err}"))
+            .to_rgba8()
+    }
+
+    fn mean_luma(rgba: &[u8]) -> f64 {
+        let total: f64 = rgba
+            .chunks_exact(4)
+            .map(|px| {
+                f64::from(luma(
+                    [px[0], px[1], px[2]].map(unorm_to_f32),
+                    FilterParams::default().luma_weights,
+                ))
+            })
+            .sum();
+        total / (rgba.len() / 4) as f64
+    }
+
+    #[test]
+    fn black_and_white_are_fixed_points() {
+        let params = FilterParams::default();
+        assert_eq!(shade([0.0; 3], &params), [0.0; 3]);
+        assert_eq!(shade([1.0; 3], &params), [1.0; 3]);
+    }
+
+    #[test]
+    fn bright_pixels_are_protected() {
+        let rgb = [0.6, 0.5, 0.4];
+        assert_eq!(shade(rgb, &FilterParams::default()), rgb);
+    }
+
+    #[test]
+    fn dark_pixels_are_fully_lifted() {
+        let rgb = [0.02, 0.03, 0.01];
+        let params = FilterParams::default();
+        assert_eq!(shade(rgb, &params), rgb.map(|c| c.powf(params.gamma)));
+    }
+
+    #[test]
+    fn lower_gamma_lifts_more() {
+        let rgb = [0.02, 0.03, 0.01];
+        let default = shade(rgb, &FilterParams::default());
+        let strong = shade(
+            rgb,
+            &FilterParams {
+                gamma: 0.5,
+                ..Default::default()
+            },
+        );
+        assert!(strong.iter().zip(default).all(|(s, d)| *s > d));
+    }
+
+    #[test]
+    fn flash_damping_drops_the_lift_and_darkens_highlights() {
+        let params = FilterParams::default();
+        let dark = [0.02, 0.03, 0.01];
+        let bright = [0.9, 0.95, 0.85];
+        assert_eq!(shade_damped(dark, &params, 0.0), shade(dark, &params));
+        assert_eq!(shade_damped(dark, &params, 1.0), dark);
+        let damped = shade_damped(bright, &params, 1.0);
+        assert!(damped.iter().zip(bright).all(|(d, b)| *d < b * 0.6));
+        assert_eq!(shade_damped([0.0; 3], &params, 1.0), [0.0; 3]);
+    }
+
+    #[test]
+    fn softening_reduces_contrast_around_the_level() {
+        let rgb = [0.0, 0.5, 1.0];
+        assert_eq!(soften(rgb, 0.0, 0.4), rgb);
+        let [dark, _, bright] = soften(rgb, 1.0, 0.4);
+        assert!(dark > 0.0 && bright < 1.0);
+        assert!((bright - dark - (1.0 - SAFETY_CUT)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lut_follows_or_replaces_the_curve() {
+        let params = FilterParams::default();
+        let dark = [0.02, 0.03, 0.01];
+        let mut stage = LutStage {
+            lut: Lut3d::identity(17),
+            interpolation: Interpolation::Tetrahedral,
+            replaces_curve: false,
+        };
+        let after = grade(dark, &params, &stage);
+        assert!(
+            after
+                .iter()
+                .zip(shade(dark, &params))
+                .all(|(a, s)| (a - s).abs() < 1e-5)
+        );
+        stage.replaces_curve = true;
+        let instead = grade(dark, &params, &stage);
+        assert!(instead.iter().zip(dark).all(|(a, d)| (a - d).abs() < 1e-5));
+    }
+
+    #[test]
+    fn smoothstep_matches_hlsl() {
+        assert_eq!(smoothstep(0.05, 0.3, 0.0), 0.0);
+        assert_eq!(smoothstep(0.05, 0.3, 1.0), 1.0);
+        assert!((smoothstep(0.05, 0.3, 0.175) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejects_partial_pixels() {
+        let params = FilterParams::default();
+        assert!(apply(&mut [0; 6], PixelFormat::Rgba8, &params).is_err());
+        assert!(apply(&mut [0; 12], PixelFormat::Rgba16F, &params).is_err());
+    }
+
+    #[test]
+    fn bgra8_matches_rgba8() {
+        let before = load_rgba8("before.png");
+        let mut rgba = before.as_raw().clone();
+        let mut bgra = rgba.clone();
+        bgra.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+
+        apply(&mut rgba, PixelFormat::Rgba8, &FilterParams::default()).unwrap();
+        apply(&mut bgra, PixelFormat::Bgra8, &FilterParams::default()).unwrap();
+        bgra.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+        assert!(rgba == bgra);
+    }
+
+    #[test]
+    fn rgba16f_matches_rgba8() {
+        let before = load_rgba8("before.png");
+        let mut rgba8 = before.as_raw().clone();
+        let mut rgba16f: Vec<u8> = rgba8
+            .iter()
+            .flat_map(|&c| f16::from_f32(unorm_to_f32(c)).to_le_bytes())
+            .collect();
+
+        apply(&mut rgba8, PixelFormat::Rgba8, &FilterParams::default()).unwrap();
+        apply(&mut rgba16f, PixelFormat::Rgba16F, &FilterParams::default()).unwrap();
+        for (unorm, half) in rgba8.iter().zip(rgba16f.chunks_exact(2)) {
+            let half = f16::from_le_bytes([half[0], half[1]]).to_f32();
+            assert!((unorm_to_f32(*unorm) - half).abs() <= 1.0 / 255.0);
+        }
+    }
+
+    // `after.png` is a capture of the live overlay a moment after `before.png`, so the
+    // frames are compared by their brightness statistics rather than pixel by pixel.
+    #[test]
+    fn golden_mean_luma_matches_overlay_capture() {
+        let before = load_rgba8("before.png");
+        let after = load_rgba8("after.png");
+        let mut filtered = before.as_raw().clone();
+        apply(&mut filtered, PixelFormat::Rgba8, &FilterParams::default()).unwrap();
+
+        let before = mean_luma(before.as_raw());
+        let filtered = mean_luma(&filtered);
+        let after = mean_luma(after.as_raw());
+        assert!(
+            (filtered - after).abs() / after < 0.03,
+            "filtered {filtered} vs after {after}"
+        );
+        assert!((before - after).abs() / after > 0.5);
+    }
+}
@@ -0,0 +1,196 @@
+//! Flash dampening: notices sudden jumps in frame brightness, such as flashbangs and
+//! explosions, and reports how strongly to hold the image back while they fade.
+//!
+//! The detector only sees one mean luma per frame, so it is tested with synthetic
+//! brightness sequences. The shader turns the damping it reports into a weaker lift and
+//! darker highlights.
+
+use std::time::Duration;
+
+/// How sudden brightness is detected and how long the dampening lasts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlashSettings {
+    /// Rise in mean luma over the recent average that counts as a flash. A rise twice
+    /// this size gets the full damping.
+    pub jump: f32,
+    /// Seconds the recent average takes to follow the scene, so slow changes such as
+    /// walking outdoors never count as a flash.
+    pub baseline_time: f32,
+    /// How long the damping takes to fade out after a flash.
+    pub decay: Duration,
+}
+
+impl Default for FlashSettings {
+    fn default() -> Self {
+        Self {
+            jump: 0.2,
+            baseline_time: 0.5,
+            decay: DEFAULT_DECAY,
+        }
+    }
+}
+
+/// Decay window used when none is configured.
+pub const DEFAULT_DECAY: Duration = Duration::from_millis(1500);
+
+/// Luma above which full damping starts darkening highlights; `ps_main` repeats it.
+pub const FLASH_KNEE: f32 = 0.5;
+/// Share of the brightest pixels' value taken away at full damping.
+pub const FLASH_CUT: f32 = 0.6;
+
+#[derive(Clone, Debug)]
+pub struct FlashDetector {
+    settings: FlashSettings,
+    baseline: Option<f32>,
+    damping: f32,
+}
+
+impl FlashDetector {
+    pub fn new(settings: FlashSettings) -> Self {
+        Self {
+            settings,
+            baseline: None,
+            damping: 0.0,
+        }
+    }
+
+    pub fn settings(&self) -> &FlashSettings {
+        &self.settings
+    }
+
+    /// Damping from `0` (none) to `1` (full) currently in effect.
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    /// Feeds the mean luma of a frame measured `elapsed` after the previous one and
+    /// returns the damping to draw it with.
+    pub fn update(&mut self, mean_luma: f32, elapsed: Duration) -> f32 {
+        let decay = self.settings.decay.as_secs_f32();
+        self.damping = if decay > 0.0 {
+            (self.damping - elapsed.as_secs_f32() / decay).max(0.0)
+        } else {
+            0.0
+        };
+        let mean_luma = if mean_luma.is_finite() {
+            mean_luma.clamp(0.0, 1.0)
+        } else {
+            return self.damping;
+        };
+        let Some(baseline) = self.baseline else {
+            self.baseline = Some(mean_luma);
+            return self.damping;
+        };
+
+        let jump = self.settings.jump.max(f32::EPSILON);
+        let rise = mean_luma - baseline;
+        if rise >= jump && decay > 0.0 {
+            self.damping = self.damping.max((rise / (2.0 * jump)).min(1.0));
+        }
+        let follow =
+            1.0 - (-elapsed.as_secs_f32() / self.settings.baseline_time.max(f32::EPSILON)).exp();
+        self.baseline = Some(baseline + (mean_luma - baseline) * follow);
+        self.damping
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn detector() -> FlashDetector {
+        FlashDetector::new(FlashSettings::default())
+    }
+
+    /// Feeds `lumas` one frame apart and returns the damping after each.
+    fn run(detector: &mut FlashDetector, lumas: impl IntoIterator<Item = f32>) -> Vec<f32> {
+        lumas
+            .into_iter()
+            .map(|luma| detector.update(luma, FRAME))
+            .collect()
+    }
+
+    #[test]
+    fn steady_scenes_are_not_damped() {
+        let mut detector = detector();
+        let damping = run(&mut detector, [0.1, 0.12, 0.09, 0.11].repeat(20));
+        assert!(damping.iter().all(|&d| d == 0.0));
+    }
+
+    #[test]
+    fn sudden_jump_triggers_full_damping() {
+        let mut detector = detector();
+        run(&mut detector, [0.05; 30]);
+        assert_eq!(detector.update(0.9, FRAME), 1.0);
+    }
+
+    #[test]
+    fn smaller_jumps_damp_less() {
+        let mut small = detector();
+        run(&mut small, [0.05; 30]);
+        let mut large = detector();
+        run(&mut large, [0.05; 30]);
+        let small = small.update(0.3, FRAME);
+        let large = large.update(0.4, FRAME);
+        assert!(small > 0.0 && small < large, "{small} vs {large}");
+    }
+
+    #[test]
+    fn slow_brightening_is_not_a_flash() {
+        let mut detector = detector();
+        let ramp = (0..300).map(|i| 0.05 + 0.8 * i as f32 / 300.0);
+        assert!(run(&mut detector, ramp).iter().all(|&d| d == 0.0));
+    }
+
+    #[test]
+    fn damping_fades_out_over_the_decay_window() {
+        let mut detector = detector();
+        run(&mut detector, [0.05; 30]);
+        detector.update(0.95, FRAME);
+
+        let decay_frames = (DEFAULT_DECAY.as_secs_f32() / FRAME.as_secs_f32()).ceil() as usize;
+        let damping = run(&mut detector, vec![0.05; decay_frames]);
+        assert!(
+            damping
+                .windows(2)
+                .all(|pair| pair[1] < pair[0] || pair[1] == 0.0)
+        );
+        let half_way = damping[decay_frames / 2];
+        assert!((half_way - 0.5).abs() < 0.05, "{half_way}");
+        assert_eq!(*damping.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn damping_holds_while_the_flash_stays_on_screen() {
+        let mut detector = detector();
+        run(&mut detector, [0.05; 30]);
+        detector.update(0.95, FRAME);
+
+        let damping = run(&mut detector, vec![0.95; 250]);
+        assert!(damping.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(damping[30] > 0.9, "{}", damping[30]);
+        assert_eq!(*damping.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn a_second_flash_renews_the_damping() {
+        let mut detector = detector();
+        run(&mut detector, [0.05; 30]);
+        detector.update(0.9, FRAME);
+        run(&mut detector, [0.05; 60]);
+        let faded = detector.damping();
+        assert!(detector.update(0.9, FRAME) > faded);
+    }
+
+    #[test]
+    fn zero_decay_disables_damping() {
+        let mut detector = FlashDetector::new(FlashSettings {
+            decay: Duration::ZERO,
+            ..FlashSettings::default()
+        });
+        run(&mut detector, [0.05; 30]);
+        assert_eq!(detector.update(1.0, FRAME), 0.0);
+    }
+}
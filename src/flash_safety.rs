@@ -0,0 +1,399 @@
+//! Photosensitive safety mode: counts flashes per screen region following WCAG 2.3.1's
+//! general flash threshold, and softens the image while it is exceeded.
+//!
+//! A flash is a pair of opposing changes in relative luminance of at least a tenth,
+//! where the darker side is below 0.8; more than three in any one second fail. The
+//! screen is split into regions roughly the size of the area WCAG allows to flash, so
+//! a flashing part of the screen is caught even when the frame as a whole barely
+//! changes. Saturated red flashes are not told apart from others.
+//!
+//! The analyzer works on the luma grid the overlay measures, so it is tested with
+//! synthetic flashing sequences.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::exposure::LumaGrid;
+
+/// How much of the way to the recent average brightness full softening pulls each
+/// pixel; `ps_main` repeats it.
+pub const SAFETY_CUT: f32 = 0.75;
+
+/// What counts as too much flashing and how the softening follows it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SafetySettings {
+    /// Regions across and down the screen, each checked on its own.
+    pub columns: usize,
+    pub rows: usize,
+    /// Change in relative luminance that counts as half a flash.
+    pub transition: f32,
+    /// A change only counts when its darker side is below this relative luminance.
+    pub dark_limit: f32,
+    /// Flashes any region may show within `window`.
+    pub max_flashes: usize,
+    pub window: Duration,
+    /// How long the softening stays after the limit was last exceeded.
+    pub hold: Duration,
+    /// How long the softening takes to come on, and to go away after `hold`.
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+impl Default for SafetySettings {
+    fn default() -> Self {
+        Self {
+            columns: 8,
+            rows: 6,
+            transition: 0.1,
+            dark_limit: 0.8,
+            max_flashes: 3,
+            window: Duration::from_secs(1),
+            hold: Duration::from_secs(1),
+            attack: Duration::from_millis(50),
+            release: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where a region's luminance last turned.
+#[derive(Clone, Copy, Debug)]
+enum Trend {
+    /// No transition yet; the range seen so far.
+    Unknown {
+        low: f32,
+        high: f32,
+    },
+    Rising {
+        peak: f32,
+    },
+    Falling {
+        trough: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Region {
+    trend: Trend,
+    /// When each transition within the window happened.
+    transitions: VecDeque<Duration>,
+}
+
+impl Region {
+    fn new(luminance: f32) -> Self {
+        Self {
+            trend: Trend::Unknown {
+                low: luminance,
+                high: luminance,
+            },
+            transitions: VecDeque::new(),
+        }
+    }
+
+    /// Follows the region to `luminance` at `now` and returns whether it is flashing
+    /// more often than allowed.
+    fn update(&mut self, luminance: f32, now: Duration, settings: &SafetySettings) -> bool {
+        let counts = |from: f32, to: f32| {
+            (to - from).abs() >= settings.transition && from.min(to) < settings.dark_limit
+        };
+        let (transition, trend) = match self.trend {
+            Trend::Unknown { low, .. } if counts(low, luminance) && luminance > low => {
+                (true, Trend::Rising { peak: luminance })
+            }
+            Trend::Unknown { high, .. } if counts(high, luminance) && luminance < high => {
+                (true, Trend::Falling { trough: luminance })
+            }
+            Trend::Unknown { low, high } => (
+                false,
+                Trend::Unknown {
+                    low: low.min(luminance),
+                    high: high.max(luminance),
+                },
+            ),
+            Trend::Rising { peak } if luminance < peak && counts(peak, luminance) => {
+                (true, Trend::Falling { trough: luminance })
+            }
+            Trend::Rising { peak } => (
+                false,
+                Trend::Rising {
+                    peak: peak.max(luminance),
+                },
+            ),
+            Trend::Falling { trough } if luminance > trough && counts(trough, luminance) => {
+                (true, Trend::Rising { peak: luminance })
+            }
+            Trend::Falling { trough } => (
+                false,
+                Trend::Falling {
+                    trough: trough.min(luminance),
+                },
+            ),
+        };
+        self.trend = trend;
+        if transition {
+            self.transitions.push_back(now);
+        }
+        while self
+            .transitions
+            .front()
+            .is_some_and(|&at| now.saturating_sub(at) >= settings.window)
+        {
+            self.transitions.pop_front();
+        }
+        // Two transitions make a flash.
+        self.transitions.len() > settings.max_flashes * 2
+    }
+}
+
+/// Watches frames for flashing and says how strongly to soften them.
+#[derive(Clone, Debug)]
+pub struct FlashSafety {
+    settings: SafetySettings,
+    regions: Vec<Region>,
+    /// Time since the first frame, summed from the `elapsed` of each update.
+    clock: Duration,
+    exceeded_at: Option<Duration>,
+    strength: f32,
+    /// Recent average luma, what softened pixels are pulled towards.
+    level: Option<f32>,
+}
+
+impl FlashSafety {
+    pub fn new(settings: SafetySettings) -> Self {
+        Self {
+            settings,
+            regions: Vec::new(),
+            clock: Duration::ZERO,
+            exceeded_at: None,
+            strength: 0.0,
+            level: None,
+        }
+    }
+
+    /// Softening from `0` (none) to `1` (full) currently in effect.
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    /// The luma softened pixels are pulled towards.
+    pub fn level(&self) -> f32 {
+        self.level.unwrap_or(0.0)
+    }
+
+    /// Whether some region flashed more than allowed within the last window.
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded_at == Some(self.clock)
+    }
+
+    /// Feeds a frame measured `elapsed` after the previous one and returns the
+    /// softening to draw it with.
+    pub fn update(&mut self, grid: &LumaGrid, elapsed: Duration) -> f32 {
+        self.clock += elapsed;
+        let means = region_means(grid, self.settings.columns, self.settings.rows);
+        if self.regions.len() != means.len() {
+            self.regions = means
+                .iter()
+                .map(|&luma| Region::new(relative_luminance(luma)))
+                .collect();
+        }
+        let mut exceeded = false;
+        for (region, &luma) in self.regions.iter_mut().zip(&means) {
+            exceeded |= region.update(relative_luminance(luma), self.clock, &self.settings);
+        }
+        if exceeded {
+            self.exceeded_at = Some(self.clock);
+        }
+
+        let holding = self
+            .exceeded_at
+            .is_some_and(|at| self.clock - at <= self.settings.hold);
+        let elapsed = elapsed.as_secs_f32();
+        self.strength = if holding {
+            (self.strength + elapsed / self.settings.attack.as_secs_f32().max(f32::EPSILON))
+                .min(1.0)
+        } else {
+            (self.strength - elapsed / self.settings.release.as_secs_f32().max(f32::EPSILON))
+                .max(0.0)
+        };
+
+        if !means.is_empty() {
+            let mean = means.iter().sum::<f32>() / means.len() as f32;
+            let follow =
+                1.0 - (-elapsed / self.settings.window.as_secs_f32().max(f32::EPSILON)).exp();
+            self.level = Some(match self.level {
+                Some(level) => level + (mean - level) * follow,
+                None => mean,
+            });
+        }
+        self.strength
+    }
+}
+
+/// Mean luma of each region, row by row. A grid smaller than the regions gets one
+/// region per cell.
+fn region_means(grid: &LumaGrid, columns: usize, rows: usize) -> Vec<f32> {
+    let (width, height) = (grid.width(), grid.height());
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let columns = columns.clamp(1, width);
+    let rows = rows.clamp(1, height);
+    let mut means = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        let ys = row * height / rows..(row + 1) * height / rows;
+        for column in 0..columns {
+            let xs = column * width / columns..(column + 1) * width / columns;
+            let count = xs.len() * ys.len();
+            let sum: f32 = ys
+                .clone()
+                .map(|y| grid.row(y)[xs.clone()].iter().sum::<f32>())
+                .sum();
+            means.push(sum / count as f32);
+        }
+    }
+    means
+}
+
+/// Relative luminance of an sRGB-encoded luma, which stands in for the mean of the
+/// decoded pixels.
+fn relative_luminance(luma: f32) -> f32 {
+    let luma = luma.clamp(0.0, 1.0);
+    if luma <= 0.04045 {
+        luma / 12.92
+    } else {
+        ((luma + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 36;
+
+    fn uniform(luma: f32) -> LumaGrid {
+        LumaGrid::new(WIDTH, HEIGHT, vec![luma; WIDTH * HEIGHT])
+    }
+
+    /// `luma` in the top-left eighth by sixth of the screen, `background` elsewhere.
+    fn corner(luma: f32, background: f32) -> LumaGrid {
+        let lumas = (0..HEIGHT)
+            .flat_map(|y| {
+                (0..WIDTH).map(move |x| {
+                    if x < WIDTH / 8 && y < HEIGHT / 6 {
+                        luma
+                    } else {
+                        background
+                    }
+                })
+            })
+            .collect();
+        LumaGrid::new(WIDTH, HEIGHT, lumas)
+    }
+
+    /// Alternates between `a` and `b` every `period` frames, for `frames` frames.
+    fn flashing(a: &LumaGrid, b: &LumaGrid, period: usize, frames: usize) -> Vec<LumaGrid> {
+        (0..frames)
+            .map(|frame| {
+                if (frame / period).is_multiple_of(2) {
+                    a
+                } else {
+                    b
+                }
+                .clone()
+            })
+            .collect()
+    }
+
+    /// Feeds `frames` one frame apart and returns the strength after each.
+    fn run(safety: &mut FlashSafety, frames: &[LumaGrid]) -> Vec<f32> {
+        frames
+            .iter()
+            .map(|grid| safety.update(grid, FRAME))
+            .collect()
+    }
+
+    fn safety() -> FlashSafety {
+        FlashSafety::new(SafetySettings::default())
+    }
+
+    #[test]
+    fn steady_and_slowly_changing_scenes_are_safe() {
+        let mut safety = safety();
+        let fade: Vec<_> = (0..120).map(|i| uniform(i as f32 / 120.0)).collect();
+        assert!(run(&mut safety, &fade).iter().all(|&s| s == 0.0));
+        assert!(
+            run(&mut safety, &vec![uniform(0.5); 60])
+                .iter()
+                .all(|&s| s == 0.0)
+        );
+    }
+
+    #[test]
+    fn fast_full_screen_flashing_is_softened() {
+        let mut safety = safety();
+        // Black and white swapping every 3 frames: about ten flashes a second.
+        let frames = flashing(&uniform(0.0), &uniform(1.0), 3, 60);
+        let strength = run(&mut safety, &frames);
+        assert!(safety.is_exceeded());
+        assert_eq!(*strength.last().unwrap(), 1.0);
+        assert!(strength[..18].iter().all(|&s| s == 0.0), "{strength:?}");
+    }
+
+    #[test]
+    fn three_flashes_a_second_are_allowed() {
+        let mut safety = safety();
+        // A swap every 12 frames is two and a half flashes a second.
+        let frames = flashing(&uniform(0.0), &uniform(1.0), 12, 240);
+        assert!(run(&mut safety, &frames).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn small_or_bright_changes_are_not_flashes() {
+        let mut dim = safety();
+        let frames = flashing(&uniform(0.1), &uniform(0.15), 3, 120);
+        assert!(run(&mut dim, &frames).iter().all(|&s| s == 0.0));
+
+        // Both sides are above the dark limit.
+        let mut bright = safety();
+        let frames = flashing(&uniform(0.95), &uniform(1.0), 3, 120);
+        assert!(run(&mut bright, &frames).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn flashing_in_one_region_is_enough() {
+        let mut safety = safety();
+        let frames = flashing(&corner(0.0, 0.2), &corner(1.0, 0.2), 3, 60);
+        run(&mut safety, &frames);
+        assert!(safety.is_exceeded());
+    }
+
+    #[test]
+    fn softening_holds_then_releases() {
+        let mut safety = safety();
+        run(&mut safety, &flashing(&uniform(0.0), &uniform(1.0), 3, 60));
+        let hold_frames = (SafetySettings::default().hold.as_millis() / FRAME.as_millis()) as usize;
+        // The flashes stay within the window for a while after they stop.
+        let after = run(&mut safety, &vec![uniform(0.3); hold_frames * 4]);
+        assert!(!safety.is_exceeded());
+        assert!(after[..hold_frames - 5].iter().all(|&s| s == 1.0));
+        assert!(after.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(*after.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn level_follows_the_average_brightness() {
+        let mut safety = safety();
+        run(&mut safety, &flashing(&uniform(0.0), &uniform(1.0), 3, 300));
+        assert!((safety.level() - 0.5).abs() < 0.1, "{}", safety.level());
+    }
+
+    #[test]
+    fn regions_cover_the_whole_grid() {
+        let grid = LumaGrid::new(3, 2, vec![0.0, 0.5, 1.0, 1.0, 0.5, 0.0]);
+        assert_eq!(region_means(&grid, 8, 6), [0.0, 0.5, 1.0, 1.0, 0.5, 0.0]);
+        assert_eq!(region_means(&grid, 1, 1), [0.5]);
+        assert!(region_means(&LumaGrid::new(0, 0, Vec::new()), 8, 6).is_empty());
+    }
+}
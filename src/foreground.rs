@@ -0,0 +1,91 @@
+//! Reports which application is in the foreground so [`Profiles`] can follow it.
+//!
+//! [`Profiles`]: crate::profiles::Profiles
+
+use std::cell::RefCell;
+use std::thread;
+
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Accessibility::{HWINEVENTHOOK, SetWinEventHook};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EVENT_SYSTEM_FOREGROUND, GetClassNameW, GetForegroundWindow, GetMessageW, MSG,
+    WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS,
+};
+use windows_capture::window::Window as CaptureWindow;
+use winit::event_loop::EventLoopProxy;
+
+use crate::app::OverlayEvent;
+use crate::profiles::ForegroundApp;
+
+thread_local! {
+    /// Where the hook callback, which runs on the watcher thread, sends its reports.
+    static PROXY: RefCell<Option<EventLoopProxy<OverlayEvent>>> = const { RefCell::new(None) };
+}
+
+/// Watches the foreground window on a thread of its own for the rest of the process,
+/// reporting the current one first. Our own overlay windows are never reported.
+pub fn spawn(proxy: EventLoopProxy<OverlayEvent>) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .name("foreground".to_string())
+        .spawn(move || watch(proxy))?;
+    Ok(())
+}
+
+fn watch(proxy: EventLoopProxy<OverlayEvent>) {
+    if let Some(app) = foreground_app(unsafe { GetForegroundWindow() }) {
+        let _ = proxy.send_event(OverlayEvent::Foreground(app));
+    }
+    PROXY.with_borrow_mut(|slot| *slot = Some(proxy));
+
+    // Out-of-context hooks are called from this thread's message loop.
+    let hook = unsafe {
+        SetWinEventHook(
+            EVENT_SYSTEM_FOREGROUND,
+            EVENT_SYSTEM_FOREGROUND,
+            None,
+            Some(on_foreground),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+        )
+    };
+    if hook.is_invalid() {
+        eprintln!("Could not watch the foreground window; profiles stay inactive");
+        return;
+    }
+    let mut message = MSG::default();
+    while unsafe { GetMessageW(&mut message, None, 0, 0) }.as_bool() {}
+}
+
+unsafe extern "system" fn on_foreground(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    hwnd: HWND,
+    _object: i32,
+    _child: i32,
+    _thread: u32,
+    _time: u32,
+) {
+    let Some(app) = foreground_app(hwnd) else {
+        return;
+    };
+    PROXY.with_borrow(|proxy| {
+        if let Some(proxy) = proxy {
+            let _ = proxy.send_event(OverlayEvent::Foreground(app));
+        }
+    });
+}
+
+fn foreground_app(hwnd: HWND) -> Option<ForegroundApp> {
+    if hwnd.is_invalid() {
+        return None;
+    }
+    let mut class = [0u16; 256];
+    let len = unsafe { GetClassNameW(hwnd, &mut class) };
+    let class = String::from_utf16_lossy(&class[..len.max(0) as usize]);
+    // Elevated processes cannot be inspected; they still match on the class.
+    let exe = CaptureWindow::from_raw_hwnd(hwnd.0)
+        .process_name()
+        .unwrap_or_default();
+    Some(ForegroundApp { exe, class })
+}
@@ -0,0 +1,46 @@
+//! Registers [`Hotkeys`] with Win32 and forwards presses to the overlay event loop.
+
+use std::thread;
+
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    HOT_KEY_MODIFIERS, MOD_NOREPEAT, RegisterHotKey,
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+use winit::event_loop::EventLoopProxy;
+
+use crate::app::OverlayEvent;
+use crate::hotkeys::Hotkeys;
+
+/// Listens for `hotkeys` on a thread of its own for the rest of the process.
+///
+/// Chords another program already owns are reported and skipped.
+pub fn spawn(hotkeys: Hotkeys, proxy: EventLoopProxy<OverlayEvent>) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .name("hotkeys".to_string())
+        .spawn(move || listen(&hotkeys, &proxy))?;
+    Ok(())
+}
+
+fn listen(hotkeys: &Hotkeys, proxy: &EventLoopProxy<OverlayEvent>) {
+    for (id, (binding, action)) in hotkeys.bindings().iter().enumerate() {
+        let modifiers = HOT_KEY_MODIFIERS(binding.modifiers.0) | MOD_NOREPEAT;
+        // Without a window the hotkey is posted to this thread's message queue.
+        if let Err(err) = unsafe { RegisterHotKey(None, id as i32, modifiers, binding.key.0) } {
+            eprintln!("Could not register {binding} for {action}: {err}");
+        }
+    }
+
+    let mut message = MSG::default();
+    while unsafe { GetMessageW(&mut message, None, 0, 0) }.as_bool() {
+        if message.message != WM_HOTKEY {
+            continue;
+        }
+        let Some(action) = hotkeys.action(message.wParam.0) else {
+            continue;
+        };
+        if proxy.send_event(OverlayEvent::Hotkey(action)).is_err() {
+            // The event loop has exited.
+            return;
+        }
+    }
+}
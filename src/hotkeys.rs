@@ -0,0 +1,663 @@
+//! Global hotkey bindings and what they do to the running filter.
+//!
+//! Parsing and dispatch are kept free of Win32 calls; the Windows frontend registers
+//! each [`KeyBinding`] with `RegisterHotKey` using the modifier bits and virtual-key
+//! codes defined here, and feeds the resulting [`HotkeyAction`]s into [`Enhancement`].
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::lut::LutStage;
+use crate::params::{FilterParams, GAMMA_RANGE};
+use crate::presets::{self, Preset};
+
+/// How much one strength step moves `gamma`.
+pub const STRENGTH_STEP: f32 = 0.05;
+
+/// Modifier bits, equal to the Win32 `MOD_*` flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(pub u32);
+
+impl Modifiers {
+    pub const ALT: Self = Self(0x1);
+    pub const CTRL: Self = Self(0x2);
+    pub const SHIFT: Self = Self(0x4);
+    pub const WIN: Self = Self(0x8);
+
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::CTRL, "Ctrl"),
+        (Self::ALT, "Alt"),
+        (Self::SHIFT, "Shift"),
+        (Self::WIN, "Win"),
+    ];
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => Some(Self::CTRL),
+            "alt" => Some(Self::ALT),
+            "shift" => Some(Self::SHIFT),
+            "win" | "super" | "meta" => Some(Self::WIN),
+            _ => None,
+        }
+    }
+}
+
+/// A Win32 virtual-key code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key(pub u32);
+
+/// Keys with a name of their own; letters, digits and `F1`..`F24` are handled separately.
+const NAMED_KEYS: &[(&str, u32)] = &[
+    ("Space", 0x20),
+    ("PageUp", 0x21),
+    ("PageDown", 0x22),
+    ("End", 0x23),
+    ("Home", 0x24),
+    ("Left", 0x25),
+    ("Up", 0x26),
+    ("Right", 0x27),
+    ("Down", 0x28),
+    ("Insert", 0x2D),
+    ("Delete", 0x2E),
+    ("Pause", 0x13),
+    ("ScrollLock", 0x91),
+    ("NumAdd", 0x6B),
+    ("NumSubtract", 0x6D),
+    ("Plus", 0xBB),
+    ("Minus", 0xBD),
+];
+
+impl Key {
+    /// Letters, digits and space would swallow ordinary typing without a modifier.
+    fn is_typing_key(self) -> bool {
+        matches!(self.0, 0x20 | 0x30..=0x39 | 0x41..=0x5A | 0xBB | 0xBD)
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let [c] = value.as_bytes()
+            && c.is_ascii_alphanumeric()
+        {
+            return Ok(Self(u32::from(c.to_ascii_uppercase())));
+        }
+        if let Some(number) = value.strip_prefix(['F', 'f'])
+            && let Ok(number @ 1..=24) = number.parse::<u32>()
+        {
+            return Ok(Self(0x70 + number - 1));
+        }
+        NAMED_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(value))
+            .map(|&(_, code)| Self(code))
+            .ok_or_else(|| format!("unknown key `{value}`"))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            code @ (0x30..=0x39 | 0x41..=0x5A) => write!(f, "{}", char::from(code as u8)),
+            code @ 0x70..=0x87 => write!(f, "F{}", code - 0x70 + 1),
+            code => match NAMED_KEYS.iter().find(|&&(_, named)| named == code) {
+                Some((name, _)) => write!(f, "{name}"),
+                None => write!(f, "{code:#04x}"),
+            },
+        }
+    }
+}
+
+/// A key chord such as `Ctrl+Alt+F9`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+impl KeyBinding {
+    pub const fn new(modifiers: Modifiers, key: Key) -> Self {
+        Self { modifiers, key }
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = value.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        if key.is_empty() {
+            return Err(format!("missing key in `{value}`"));
+        }
+        let mut modifiers = Modifiers::default();
+        for part in parts {
+            let modifier = Modifiers::parse(part)
+                .ok_or_else(|| format!("unknown modifier `{part}` in `{value}`"))?;
+            if modifiers.contains(modifier) {
+                return Err(format!("`{part}` appears twice in `{value}`"));
+            }
+            modifiers = modifiers.with(modifier);
+        }
+        let key: Key = key.parse()?;
+        if modifiers.is_empty() && key.is_typing_key() {
+            return Err(format!("`{value}` needs a modifier such as Ctrl or Alt"));
+        }
+        Ok(Self { modifiers, key })
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in Modifiers::NAMES {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HotkeyAction {
+    /// Hides or shows the overlay.
+    Toggle,
+    NextPreset,
+    PreviousPreset,
+    /// Lowers `gamma` by [`STRENGTH_STEP`], lifting shadows more.
+    StrengthUp,
+    StrengthDown,
+}
+
+impl HotkeyAction {
+    pub const ALL: [Self; 5] = [
+        Self::Toggle,
+        Self::NextPreset,
+        Self::PreviousPreset,
+        Self::StrengthUp,
+        Self::StrengthDown,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Toggle => "toggle",
+            Self::NextPreset => "next-preset",
+            Self::PreviousPreset => "previous-preset",
+            Self::StrengthUp => "strength-up",
+            Self::StrengthDown => "strength-down",
+        }
+    }
+}
+
+impl FromStr for HotkeyAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(value))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|action| action.name()).collect();
+                format!(
+                    "unknown hotkey action `{value}`; expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for HotkeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One `--hotkey action=chord` override; `action=none` unbinds the action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HotkeyAssignment {
+    pub action: HotkeyAction,
+    pub binding: Option<KeyBinding>,
+}
+
+impl FromStr for HotkeyAssignment {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (action, binding) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected `action=chord`, got `{value}`"))?;
+        let action = action.trim().parse()?;
+        let binding = binding.trim();
+        let binding = if binding.eq_ignore_ascii_case("none") {
+            None
+        } else {
+            Some(binding.parse()?)
+        };
+        Ok(Self { action, binding })
+    }
+}
+
+/// The bound chords, in registration order. A chord's position is its hotkey id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hotkeys {
+    bindings: Vec<(KeyBinding, HotkeyAction)>,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        let ctrl_alt = Modifiers::CTRL.with(Modifiers::ALT);
+        let f = |number: u32| Key(0x70 + number - 1);
+        Self {
+            bindings: vec![
+                (KeyBinding::new(ctrl_alt, f(9)), HotkeyAction::Toggle),
+                (KeyBinding::new(ctrl_alt, f(10)), HotkeyAction::NextPreset),
+                (
+                    KeyBinding::new(ctrl_alt.with(Modifiers::SHIFT), f(10)),
+                    HotkeyAction::PreviousPreset,
+                ),
+                (
+                    KeyBinding::new(ctrl_alt, Key(0x21)),
+                    HotkeyAction::StrengthUp,
+                ),
+                (
+                    KeyBinding::new(ctrl_alt, Key(0x22)),
+                    HotkeyAction::StrengthDown,
+                ),
+            ],
+        }
+    }
+}
+
+impl Hotkeys {
+    /// The default bindings with `assignments` applied in order.
+    ///
+    /// Fails if two actions would end up on the same chord.
+    pub fn with_assignments(assignments: &[HotkeyAssignment]) -> Result<Self, String> {
+        let mut hotkeys = Self::default();
+        for assignment in assignments {
+            hotkeys
+                .bindings
+                .retain(|&(_, action)| action != assignment.action);
+            if let Some(binding) = assignment.binding {
+                if let Some((_, other)) = hotkeys.bindings.iter().find(|(b, _)| *b == binding) {
+                    return Err(format!(
+                        "{binding} is bound to both {other} and {}",
+                        assignment.action
+                    ));
+                }
+                hotkeys.bindings.push((binding, assignment.action));
+            }
+        }
+        Ok(hotkeys)
+    }
+
+    pub fn bindings(&self) -> &[(KeyBinding, HotkeyAction)] {
+        &self.bindings
+    }
+
+    /// The action registered under `id`, an index into [`Hotkeys::bindings`].
+    pub fn action(&self, id: usize) -> Option<HotkeyAction> {
+        self.bindings.get(id).map(|&(_, action)| action)
+    }
+}
+
+/// What the hotkeys control: whether the overlay is shown, the preset and its strength.
+/// Selecting a preset, by hotkey or profile, also brings its adaptive exposure, LUT and
+/// photosensitive safety mode.
+#[derive(Clone, Debug)]
+pub struct Enhancement {
+    enabled: bool,
+    presets: Vec<Preset>,
+    preset: usize,
+    params: FilterParams,
+    adaptive: bool,
+    flash_safety: bool,
+    lut: Option<Arc<LutStage>>,
+}
+
+impl Enhancement {
+    /// Starts enabled with `params`, cycling through `presets` from the one that matches
+    /// `params`, or the default preset if none does.
+    pub fn new(params: FilterParams, presets: Vec<Preset>) -> Self {
+        assert!(!presets.is_empty(), "at least one preset is required");
+        let preset = presets
+            .iter()
+            .position(|preset| preset.params == params)
+            .or_else(|| {
+                presets
+                    .iter()
+                    .position(|preset| preset.name == presets::DEFAULT_PRESET)
+            })
+            .unwrap_or(0);
+        Self {
+            enabled: true,
+            adaptive: presets[preset].adaptive,
+            flash_safety: presets[preset].flash_safety,
+            lut: presets[preset].lut.clone(),
+            presets,
+            preset,
+            params,
+        }
+    }
+
+    /// Starts over from a reloaded config, staying on or off as before.
+    pub fn reload(&mut self, params: FilterParams, presets: Vec<Preset>) {
+        let enabled = self.enabled;
+        *self = Self::new(params, presets);
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn params(&self) -> FilterParams {
+        self.params
+    }
+
+    /// Whether the curve follows the scene, with [`Self::params`] as the strongest lift.
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Overrides the current preset's choice until another preset is selected.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    /// Whether the photosensitive safety mode softens rapid flashing.
+    pub fn flash_safety(&self) -> bool {
+        self.flash_safety
+    }
+
+    /// Overrides the current preset's choice until another preset is selected.
+    pub fn set_flash_safety(&mut self, flash_safety: bool) {
+        self.flash_safety = flash_safety;
+    }
+
+    /// The colour grade applied with the curve, if any.
+    pub fn lut(&self) -> Option<&Arc<LutStage>> {
+        self.lut.as_ref()
+    }
+
+    /// Overrides the current preset's LUT until another preset is selected.
+    pub fn set_lut(&mut self, lut: Option<Arc<LutStage>>) {
+        self.lut = lut;
+    }
+
+    pub fn preset(&self) -> &Preset {
+        &self.presets[self.preset]
+    }
+
+    /// Switches to the preset called `name`, ignoring ASCII case, and returns whether
+    /// anything changed. Unknown names change nothing.
+    pub fn select_named(&mut self, name: &str) -> bool {
+        match self
+            .presets
+            .iter()
+            .position(|preset| preset.name.eq_ignore_ascii_case(name))
+        {
+            Some(index) => self.select_preset(index),
+            None => false,
+        }
+    }
+
+    /// Applies `action` and returns whether anything changed.
+    pub fn apply(&mut self, action: HotkeyAction) -> bool {
+        match action {
+            HotkeyAction::Toggle => {
+                self.enabled = !self.enabled;
+                true
+            }
+            HotkeyAction::NextPreset => self.select_preset((self.preset + 1) % self.presets.len()),
+            HotkeyAction::PreviousPreset => {
+                let count = self.presets.len();
+                self.select_preset((self.preset + count - 1) % count)
+            }
+            HotkeyAction::StrengthUp => self.set_gamma(self.params.gamma - STRENGTH_STEP),
+            HotkeyAction::StrengthDown => self.set_gamma(self.params.gamma + STRENGTH_STEP),
+        }
+    }
+
+    fn select_preset(&mut self, index: usize) -> bool {
+        let preset = &self.presets[index];
+        let changed = self.preset != index
+            || self.params != preset.params
+            || self.adaptive != preset.adaptive
+            || self.flash_safety != preset.flash_safety
+            || self.lut != preset.lut;
+        self.preset = index;
+        self.params = preset.params;
+        self.adaptive = preset.adaptive;
+        self.flash_safety = preset.flash_safety;
+        self.lut = preset.lut.clone();
+        changed
+    }
+
+    fn set_gamma(&mut self, gamma: f32) -> bool {
+        // Round to whole steps so repeated presses do not accumulate float error.
+        let gamma = (gamma / STRENGTH_STEP).round() * STRENGTH_STEP;
+        let gamma = gamma.clamp(*GAMMA_RANGE.start(), *GAMMA_RANGE.end());
+        let changed = gamma != self.params.gamma;
+        self.params = FilterParams {
+            gamma,
+            ..self.params
+        }
+        .clamped();
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lut::{Interpolation, Lut3d};
+
+    fn builtin() -> Enhancement {
+        Enhancement::new(FilterParams::default(), presets::BUILTIN.to_vec())
+    }
+
+    #[test]
+    fn parses_chords_case_insensitively() {
+        let binding: KeyBinding = "ctrl + ALT + f9".parse().unwrap();
+        assert_eq!(
+            binding,
+            KeyBinding::new(Modifiers::CTRL.with(Modifiers::ALT), Key(0x78))
+        );
+        assert_eq!(binding.to_string(), "Ctrl+Alt+F9");
+        assert_eq!(
+            "Shift+Win+pageup"
+                .parse::<KeyBinding>()
+                .unwrap()
+                .to_string(),
+            "Shift+Win+PageUp"
+        );
+        assert_eq!("Alt+b".parse::<KeyBinding>().unwrap().key, Key(0x42));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for (binding, _) in Hotkeys::default().bindings() {
+            assert_eq!(binding.to_string().parse::<KeyBinding>(), Ok(*binding));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_chords() {
+        assert!("Ctrl+".parse::<KeyBinding>().is_err());
+        assert!("Hyper+F1".parse::<KeyBinding>().is_err());
+        assert!("Ctrl+Ctrl+F1".parse::<KeyBinding>().is_err());
+        assert!("Ctrl+F25".parse::<KeyBinding>().is_err());
+        assert!("Ctrl+Enterr".parse::<KeyBinding>().is_err());
+    }
+
+    #[test]
+    fn typing_keys_need_a_modifier() {
+        assert!("B".parse::<KeyBinding>().is_err());
+        assert!("Space".parse::<KeyBinding>().is_err());
+        assert!("F9".parse::<KeyBinding>().is_ok());
+        assert!("Pause".parse::<KeyBinding>().is_ok());
+    }
+
+    #[test]
+    fn assignments_replace_and_unbind_defaults() {
+        let hotkeys = Hotkeys::with_assignments(&[
+            "toggle=Alt+B".parse().unwrap(),
+            "strength-down=none".parse().unwrap(),
+        ])
+        .unwrap();
+        let toggle = hotkeys
+            .bindings()
+            .iter()
+            .find(|(_, action)| *action == HotkeyAction::Toggle)
+            .unwrap();
+        assert_eq!(toggle.0.to_string(), "Alt+B");
+        assert!(
+            hotkeys
+                .bindings()
+                .iter()
+                .all(|(_, action)| *action != HotkeyAction::StrengthDown)
+        );
+        assert_eq!(hotkeys.bindings().len(), 4);
+    }
+
+    #[test]
+    fn conflicting_assignments_are_rejected() {
+        let err = Hotkeys::with_assignments(&["toggle=Ctrl+Alt+F10".parse().unwrap()]).unwrap_err();
+        assert!(err.contains("next-preset"), "{err}");
+    }
+
+    #[test]
+    fn dispatches_by_registration_id() {
+        let hotkeys = Hotkeys::default();
+        assert_eq!(hotkeys.action(0), Some(HotkeyAction::Toggle));
+        assert_eq!(hotkeys.action(hotkeys.bindings().len()), None);
+    }
+
+    #[test]
+    fn toggle_flips_enabled() {
+        let mut enhancement = builtin();
+        assert!(enhancement.enabled());
+        enhancement.apply(HotkeyAction::Toggle);
+        assert!(!enhancement.enabled());
+        enhancement.apply(HotkeyAction::Toggle);
+        assert!(enhancement.enabled());
+    }
+
+    #[test]
+    fn reload_keeps_enabled_and_picks_the_new_params() {
+        let mut enhancement = builtin();
+        enhancement.apply(HotkeyAction::Toggle);
+        let strong = presets::find("strong").unwrap().params;
+        enhancement.reload(strong, presets::BUILTIN.to_vec());
+        assert!(!enhancement.enabled());
+        assert_eq!(enhancement.preset().name, "strong");
+        assert_eq!(enhancement.params(), strong);
+    }
+
+    #[test]
+    fn selecting_a_preset_follows_its_adaptive_setting() {
+        let mut presets = presets::BUILTIN.to_vec();
+        presets[2].adaptive = true;
+        let mut enhancement = Enhancement::new(FilterParams::default(), presets);
+        enhancement.set_adaptive(true);
+        assert!(enhancement.adaptive());
+        assert!(enhancement.select_named("subtle"));
+        assert!(!enhancement.adaptive());
+        assert!(enhancement.select_named("strong"));
+        assert!(enhancement.adaptive());
+    }
+
+    #[test]
+    fn selecting_a_preset_follows_its_safety_mode() {
+        let mut presets = presets::BUILTIN.to_vec();
+        presets[2].flash_safety = true;
+        let mut enhancement = Enhancement::new(FilterParams::default(), presets);
+        assert!(!enhancement.flash_safety());
+        assert!(enhancement.select_named("strong"));
+        assert!(enhancement.flash_safety());
+        enhancement.set_flash_safety(false);
+        assert!(enhancement.select_named("subtle"));
+        assert!(!enhancement.flash_safety());
+    }
+
+    #[test]
+    fn selecting_a_preset_follows_its_lut() {
+        let stage = Arc::new(LutStage {
+            lut: Lut3d::identity(2),
+            interpolation: Interpolation::default(),
+            replaces_curve: false,
+        });
+        let mut presets = presets::BUILTIN.to_vec();
+        presets[2].lut = Some(stage.clone());
+        let mut enhancement = Enhancement::new(FilterParams::default(), presets);
+        assert!(enhancement.lut().is_none());
+        assert!(enhancement.select_named("strong"));
+        assert_eq!(enhancement.lut(), Some(&stage));
+        enhancement.set_lut(None);
+        assert!(enhancement.select_named("strong"));
+        assert_eq!(enhancement.lut(), Some(&stage));
+    }
+
+    #[test]
+    fn selects_presets_by_name() {
+        let mut enhancement = builtin();
+        assert!(enhancement.select_named("STRONG"));
+        assert_eq!(enhancement.preset().name, "strong");
+        assert!(!enhancement.select_named("strong"));
+        assert!(!enhancement.select_named("missing"));
+        assert_eq!(enhancement.preset().name, "strong");
+    }
+
+    #[test]
+    fn presets_cycle_in_both_directions() {
+        let mut enhancement = builtin();
+        assert_eq!(enhancement.preset().name, presets::DEFAULT_PRESET);
+        enhancement.apply(HotkeyAction::NextPreset);
+        assert_eq!(enhancement.preset().name, "strong");
+        assert_eq!(
+            enhancement.params(),
+            presets::find("strong").unwrap().params
+        );
+        enhancement.apply(HotkeyAction::NextPreset);
+        assert_eq!(enhancement.preset().name, "subtle");
+        enhancement.apply(HotkeyAction::PreviousPreset);
+        assert_eq!(enhancement.preset().name, "strong");
+    }
+
+    #[test]
+    fn strength_steps_gamma_within_range() {
+        let mut enhancement = builtin();
+        assert!(enhancement.apply(HotkeyAction::StrengthUp));
+        assert!((enhancement.params().gamma - 0.7).abs() < 1e-6);
+        assert!(enhancement.apply(HotkeyAction::StrengthDown));
+        assert!(enhancement.apply(HotkeyAction::StrengthDown));
+        assert!((enhancement.params().gamma - 0.8).abs() < 1e-6);
+
+        for _ in 0..10 {
+            enhancement.apply(HotkeyAction::StrengthDown);
+        }
+        assert_eq!(enhancement.params().gamma, *GAMMA_RANGE.end());
+        assert!(!enhancement.apply(HotkeyAction::StrengthDown));
+    }
+
+    #[test]
+    fn unknown_actions_list_the_choices() {
+        let err = "explode".parse::<HotkeyAction>().unwrap_err();
+        assert!(err.contains("strength-up"), "{err}");
+    }
+}
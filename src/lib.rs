@@ -0,0 +1,46 @@
+//! Core of ban-shadow: the shadow-lift filter, its parameters and presets.
+//!
+//! Everything outside the Windows, X11 and Wayland frontends builds on every platform, so other
+//! tools can embed the enhancement pipeline or test it without a GPU.
+
+pub mod config;
+pub mod config_watch;
+pub mod custom_shader;
+pub mod error;
+pub mod exposure;
+pub mod filter;
+pub mod flash;
+pub mod flash_safety;
+pub mod hotkeys;
+pub mod lut;
+pub mod mailbox;
+pub mod options;
+pub mod params;
+pub mod passes;
+pub mod presets;
+pub mod process;
+pub mod profiles;
+pub mod render;
+pub mod source;
+pub mod target;
+
+#[cfg(target_os = "windows")]
+pub mod app;
+#[cfg(target_os = "windows")]
+pub mod capture;
+#[cfg(target_os = "windows")]
+pub mod foreground;
+#[cfg(target_os = "windows")]
+pub mod global_hotkeys;
+#[cfg(target_os = "windows")]
+mod luma_probe;
+#[cfg(target_os = "windows")]
+mod pass_graph;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+#[cfg(target_os = "linux")]
+pub mod wayland;
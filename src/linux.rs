@@ -0,0 +1,174 @@
+//! What the X11 and Wayland frontends share: the capture thread, the events that wake
+//! the event loop, config reloading and shared memory.
+
+mod memfd;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::error::OverlayError;
+use crate::hotkeys::Enhancement;
+use crate::options::OverlayOptions;
+use crate::source::CpuFrames;
+
+pub(crate) use memfd::SharedMemory;
+
+/// How long an overlay goes without a newer frame before it waits for the renderer to
+/// finish the one it has, rather than only polling it.
+pub(crate) const FLUSH_DELAY: Duration = Duration::from_millis(8);
+
+/// Wakes the event loop from other threads.
+pub(crate) enum OverlayEvent {
+    /// A capture thread published a frame.
+    FrameReady,
+    /// A capture thread stopped for good.
+    CaptureFailed(anyhow::Error),
+    /// The config file was edited and still loads.
+    ConfigChanged(Box<Config>),
+}
+
+/// A running capture thread; stopped when dropped.
+pub(crate) struct CaptureThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CaptureThread {
+    /// Calls `grab` up to `fps` times a second and publishes what it returns. `grab`
+    /// fills the buffer and returns the frame size, or `None` when there is nothing to
+    /// show; it gets the stop flag to check while it waits. Calls `on_frame` after
+    /// publishing each frame and `on_error` once if `grab` fails.
+    pub fn spawn(
+        name: &str,
+        fps: u32,
+        frames: CpuFrames,
+        mut grab: impl FnMut(&AtomicBool, &mut Vec<u8>) -> anyhow::Result<Option<(u32, u32)>>
+        + Send
+        + 'static,
+        on_frame: impl Fn() + Send + 'static,
+        on_error: impl FnOnce(anyhow::Error) + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let interval = Duration::from_secs(1) / fps.max(1);
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut pixels = Vec::new();
+                while !stopped.load(Ordering::Relaxed) {
+                    let started = Instant::now();
+                    match grab(&stopped, &mut pixels) {
+                        Ok(Some(size)) => {
+                            frames.publish(&mut pixels, size);
+                            on_frame();
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            on_error(err);
+                            return;
+                        }
+                    }
+                    thread::sleep(interval.saturating_sub(started.elapsed()));
+                }
+            })?;
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for CaptureThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Checks `options` before `overlay` starts: refuses the photosensitive safety mode and
+/// reports the features it ignores.
+pub(crate) fn check_options(overlay: &str, options: &OverlayOptions) -> Result<(), OverlayError> {
+    options
+        .settings
+        .check_flash_safety(overlay)
+        .map_err(OverlayError::Config)?;
+    for feature in options.unsupported_features(true) {
+        eprintln!("{overlay} does not support {feature} yet; ignoring it");
+    }
+    Ok(())
+}
+
+/// A frontend whose settings follow the config file.
+pub(crate) trait ReloadConfig {
+    /// What messages call the frontend, e.g. "The X11 overlay".
+    const NAME: &'static str;
+
+    fn options(&mut self) -> &mut OverlayOptions;
+    fn enhancement(&mut self) -> &mut Enhancement;
+    fn stop_capture(&mut self);
+    /// Captures what `options().settings` ask for.
+    fn start_capture(&mut self) -> anyhow::Result<()>;
+
+    /// Applies an edited config on top of the command line. The curve changes in place;
+    /// the capture restarts only when the target, monitors or FPS cap changed, and goes
+    /// back to the previous ones if the new ones cannot be captured. A config that turns
+    /// the photosensitive safety mode on is rejected like any other that does not load.
+    fn reload_config(&mut self, config: &Config) -> anyhow::Result<()> {
+        let options = self.options();
+        let settings = match options.overrides.resolve(config) {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("Keeping the previous config: {err}");
+                return Ok(());
+            }
+        };
+        if let Err(err) = settings.check_flash_safety(Self::NAME) {
+            eprintln!("Keeping the previous config: {err}");
+            return Ok(());
+        }
+        let restart = options.settings.needs_capture_restart(&settings);
+        let ignored = options.unsupported_features(true);
+        let previous = std::mem::replace(&mut options.settings, settings);
+        for feature in options.unsupported_features(true) {
+            if !ignored.contains(&feature) {
+                eprintln!("{} does not support {feature} yet; ignoring it", Self::NAME);
+            }
+        }
+        let (params, presets, lut) = (
+            options.settings.params.clamped(),
+            options.settings.cycled_presets(),
+            options.settings.lut.clone(),
+        );
+        let enhancement = self.enhancement();
+        enhancement.reload(params, presets);
+        enhancement.set_lut(lut);
+        eprintln!(
+            "Config reloaded: preset {}, gamma {:.2}",
+            enhancement.preset().name,
+            enhancement.params().gamma
+        );
+        if !restart {
+            return Ok(());
+        }
+
+        eprintln!("Capture settings changed; restarting capture");
+        self.stop_capture();
+        if let Err(err) = self.start_capture() {
+            eprintln!("Keeping the previous capture settings: {err:?}");
+            self.stop_capture();
+            let settings = &mut self.options().settings;
+            settings.target = previous.target;
+            settings.monitors = previous.monitors;
+            settings.fps_cap = previous.fps_cap;
+            self.start_capture().context("Failed to restart capture")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+//! Anonymous shared memory the X server or the compositor maps too.
+
+use std::ffi::c_void;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+use rustix::mm::{MapFlags, ProtFlags, mmap, munmap};
+
+/// A memfd mapped read-write; unmapped when dropped.
+pub(crate) struct SharedMemory {
+    fd: OwnedFd,
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is owned by this value alone and only reached through `&self`/`&mut self`.
+unsafe impl Send for SharedMemory {}
+
+impl SharedMemory {
+    /// Maps at least one byte, so the other side always has something to map.
+    pub fn new(len: usize) -> anyhow::Result<Self> {
+        let len = len.max(1);
+        let fd = memfd_create("ban-shadow", MemfdFlags::CLOEXEC)?;
+        ftruncate(&fd, len as u64)?;
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::SHARED,
+                &fd,
+                0,
+            )?
+        };
+        Ok(Self { fd, ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.cast(), self.len) }
+    }
+}
+
+impl AsFd for SharedMemory {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
@@ -0,0 +1,200 @@
+//! Measures captured frames on the GPU for adaptive exposure and the flash modes.
+//!
+//! Each frame is drawn into a small luma-only render target, copied to a staging texture
+//! and read back a frame or two later, once the GPU is done with it, so measuring never
+//! stalls the renderer. Downscaling with a bilinear sampler only sees a few texels per
+//! output pixel, which is plenty for a histogram.
+
+use windows::Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_BIND_RENDER_TARGET, D3D11_CPU_ACCESS_READ, D3D11_MAP_FLAG_DO_NOT_WAIT, D3D11_MAP_READ,
+    D3D11_MAPPED_SUBRESOURCE, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+    D3D11_VIEWPORT, ID3D11Buffer, ID3D11Device, ID3D11DeviceContext, ID3D11PixelShader,
+    ID3D11RenderTargetView, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D,
+    ID3D11VertexShader,
+};
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R8_UNORM, DXGI_SAMPLE_DESC};
+
+use crate::app::{SHADER_SOURCE, blob_bytes, compile_shader};
+use crate::exposure::LumaGrid;
+use crate::filter::unorm_to_f32;
+
+const PROBE_WIDTH: u32 = 64;
+const PROBE_HEIGHT: u32 = 36;
+/// Copies in flight; the oldest is read while the newest is still being drawn.
+const STAGING_COUNT: usize = 3;
+
+pub(crate) struct LumaProbe {
+    ps: ID3D11PixelShader,
+    target: ID3D11Texture2D,
+    rtv: ID3D11RenderTargetView,
+    staging: Vec<Staging>,
+    next: usize,
+    /// Counts the fresh frames measured, as opposed to repaints of the same frame.
+    latest_frame: u64,
+    read_frame: Option<u64>,
+}
+
+struct Staging {
+    texture: ID3D11Texture2D,
+    /// The frame whose copy has not been read yet.
+    pending: Option<u64>,
+}
+
+/// What the probe draws with; the renderer's own pipeline objects.
+pub(crate) struct ProbeInput<'a> {
+    pub vs: &'a ID3D11VertexShader,
+    pub sampler: &'a ID3D11SamplerState,
+    pub params: &'a ID3D11Buffer,
+    pub frame: &'a ID3D11ShaderResourceView,
+}
+
+impl LumaProbe {
+    pub fn new(device: &ID3D11Device) -> anyhow::Result<Self> {
+        let ps_blob = compile_shader(SHADER_SOURCE, "ps_luma", "ps_5_0")?;
+        let mut ps = None;
+        unsafe { device.CreatePixelShader(blob_bytes(&ps_blob), None, Some(&mut ps))? };
+        let ps = ps.ok_or_else(|| anyhow::anyhow!("Failed to create luma shader"))?;
+
+        let target = create_texture(device, D3D11_USAGE_DEFAULT, D3D11_BIND_RENDER_TARGET.0, 0)?;
+        let mut rtv = None;
+        unsafe { device.CreateRenderTargetView(&target, None, Some(&mut rtv))? };
+        let rtv = rtv.ok_or_else(|| anyhow::anyhow!("Failed to create luma target view"))?;
+
+        let staging = (0..STAGING_COUNT)
+            .map(|_| {
+                let texture =
+                    create_texture(device, D3D11_USAGE_STAGING, 0, D3D11_CPU_ACCESS_READ.0)?;
+                Ok(Staging {
+                    texture,
+                    pending: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            ps,
+            target,
+            rtv,
+            staging,
+            next: 0,
+            latest_frame: 0,
+            read_frame: None,
+        })
+    }
+
+    /// Whether the last grid handed out measured the newest fresh frame.
+    pub fn is_up_to_date(&self) -> bool {
+        self.read_frame == Some(self.latest_frame)
+    }
+
+    /// Queues a measurement of `input.frame` and returns the lumas of an earlier
+    /// frame if the GPU has finished it. `fresh` tells a new frame from a repaint.
+    /// Leaves the probe's render target and viewport bound; the caller restores its own.
+    pub fn measure(
+        &mut self,
+        context: &ID3D11DeviceContext,
+        input: &ProbeInput<'_>,
+        fresh: bool,
+    ) -> Option<LumaGrid> {
+        if fresh {
+            self.latest_frame += 1;
+        }
+        let slot = self.next;
+        let grid = match self.staging[slot].pending {
+            Some(frame) => {
+                // Still busy: skip this frame rather than wait, and try the slot again later.
+                let grid = self.read(context, slot)?;
+                self.staging[slot].pending = None;
+                self.read_frame = Some(frame);
+                Some(grid)
+            }
+            None => None,
+        };
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: PROBE_WIDTH as f32,
+            Height: PROBE_HEIGHT as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+        unsafe {
+            context.OMSetRenderTargets(Some(&[Some(self.rtv.clone())]), None);
+            context.RSSetViewports(Some(&[viewport]));
+            context.IASetInputLayout(None);
+            context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            context.VSSetShader(input.vs, None);
+            context.PSSetShader(&self.ps, None);
+            context.PSSetShaderResources(0, Some(&[Some(input.frame.clone())]));
+            context.PSSetSamplers(0, Some(&[Some(input.sampler.clone())]));
+            context.PSSetConstantBuffers(0, Some(&[Some(input.params.clone())]));
+            context.Draw(3, 0);
+            context.CopyResource(&self.staging[slot].texture, &self.target);
+        }
+        self.staging[slot].pending = Some(self.latest_frame);
+        self.next = (slot + 1) % self.staging.len();
+        grid
+    }
+
+    fn read(&self, context: &ID3D11DeviceContext, slot: usize) -> Option<LumaGrid> {
+        let texture = &self.staging[slot].texture;
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        unsafe {
+            context
+                .Map(
+                    texture,
+                    0,
+                    D3D11_MAP_READ,
+                    D3D11_MAP_FLAG_DO_NOT_WAIT.0 as u32,
+                    Some(&mut mapped),
+                )
+                .ok()?;
+        }
+        let mut lumas = Vec::with_capacity((PROBE_WIDTH * PROBE_HEIGHT) as usize);
+        for row in 0..PROBE_HEIGHT as usize {
+            let row = unsafe {
+                std::slice::from_raw_parts(
+                    mapped
+                        .pData
+                        .cast::<u8>()
+                        .add(row * mapped.RowPitch as usize),
+                    PROBE_WIDTH as usize,
+                )
+            };
+            lumas.extend(row.iter().map(|&luma| unorm_to_f32(luma)));
+        }
+        unsafe { context.Unmap(texture, 0) };
+        Some(LumaGrid::new(
+            PROBE_WIDTH as usize,
+            PROBE_HEIGHT as usize,
+            lumas,
+        ))
+    }
+}
+
+fn create_texture(
+    device: &ID3D11Device,
+    usage: windows::Win32::Graphics::Direct3D11::D3D11_USAGE,
+    bind_flags: i32,
+    cpu_access: i32,
+) -> anyhow::Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: PROBE_WIDTH,
+        Height: PROBE_HEIGHT,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_R8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: usage,
+        BindFlags: bind_flags as u32,
+        CPUAccessFlags: cpu_access as u32,
+        MiscFlags: 0,
+    };
+    let mut texture = None;
+    unsafe { device.CreateTexture2D(&desc, None, Some(&mut texture))? };
+    texture.ok_or_else(|| anyhow::anyhow!("Failed to create luma probe texture"))
+}
@@ -0,0 +1,420 @@
+//! 3D colour lookup tables in the Adobe/Resolve `.cube` format.
+//!
+//! A LUT is applied after the built-in curve, or instead of it, as a 3D texture lookup
+//! in `ps_main`. The parser and both interpolations are plain Rust, so graded images
+//! can be checked without a GPU.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+/// Table sizes the `.cube` specification allows; graders export 17, 33 or 65.
+pub const SIZE_RANGE: RangeInclusive<usize> = 2..=256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+    pub title: Option<String>,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// `size³` outputs, red changing fastest, then green, then blue.
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// A table that maps every colour to itself.
+    pub fn identity(size: usize) -> Self {
+        let step = 1.0 / (size - 1) as f32;
+        let table = (0..size * size * size)
+            .map(|i| [i % size, i / size % size, i / (size * size)].map(|c| c as f32 * step))
+            .collect();
+        Self {
+            title: None,
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    /// Reads and parses a `.cube` file, naming it in any error.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read LUT {}", path.display()))?;
+        source
+            .parse()
+            .map_err(|err| anyhow::anyhow!("{}:{err}", path.display()))
+    }
+
+    /// Lattice points along each axis.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn domain_min(&self) -> [f32; 3] {
+        self.domain_min
+    }
+
+    pub fn domain_max(&self) -> [f32; 3] {
+        self.domain_max
+    }
+
+    /// The outputs, red changing fastest, then green, then blue; the layout of the 3D
+    /// texture the overlay uploads.
+    pub fn table(&self) -> &[[f32; 3]] {
+        &self.table
+    }
+
+    /// Looks `rgb` up, clamping it to the domain first, exactly like `ps_main`.
+    pub fn sample(&self, rgb: [f32; 3], interpolation: Interpolation) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        let coords: [f32; 3] = std::array::from_fn(|i| {
+            let t = (rgb[i] - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i]);
+            // NaN lands on the lower edge, like HLSL `saturate`.
+            let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+            t * last
+        });
+        let base = coords.map(|c| c.floor().min(last - 1.0));
+        let f: [f32; 3] = std::array::from_fn(|i| coords[i] - base[i]);
+        let base = base.map(|c| c as usize);
+        let at = |r: usize, g: usize, b: usize| {
+            self.table[base[0] + r + self.size * (base[1] + g + self.size * (base[2] + b))]
+        };
+        match interpolation {
+            Interpolation::Trilinear => {
+                let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+                    std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+                };
+                let g0 = lerp(
+                    lerp(at(0, 0, 0), at(1, 0, 0), f[0]),
+                    lerp(at(0, 1, 0), at(1, 1, 0), f[0]),
+                    f[1],
+                );
+                let g1 = lerp(
+                    lerp(at(0, 0, 1), at(1, 0, 1), f[0]),
+                    lerp(at(0, 1, 1), at(1, 1, 1), f[0]),
+                    f[1],
+                );
+                lerp(g0, g1, f[2])
+            }
+            Interpolation::Tetrahedral => {
+                // The tetrahedron containing the point shares the cube's main diagonal.
+                let [r, g, b] = f;
+                let (c1, c2, weights) = if r > g {
+                    if g > b {
+                        (at(1, 0, 0), at(1, 1, 0), [1.0 - r, r - g, g - b, b])
+                    } else if r > b {
+                        (at(1, 0, 0), at(1, 0, 1), [1.0 - r, r - b, b - g, g])
+                    } else {
+                        (at(0, 0, 1), at(1, 0, 1), [1.0 - b, b - r, r - g, g])
+                    }
+                } else if b > g {
+                    (at(0, 0, 1), at(0, 1, 1), [1.0 - b, b - g, g - r, r])
+                } else if b > r {
+                    (at(0, 1, 0), at(0, 1, 1), [1.0 - g, g - b, b - r, r])
+                } else {
+                    (at(0, 1, 0), at(1, 1, 0), [1.0 - g, g - r, r - b, b])
+                };
+                let corners = [at(0, 0, 0), c1, c2, at(1, 1, 1)];
+                std::array::from_fn(|i| {
+                    corners
+                        .iter()
+                        .zip(weights)
+                        .map(|(corner, weight)| corner[i] * weight)
+                        .sum()
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for Lut3d {
+    type Err = String;
+
+    /// Parses `.cube` text. Errors start with the 1-based line they refer to.
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+        let mut last_line = 0;
+
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            last_line = number;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some(first) = line.chars().next() else {
+                continue;
+            };
+            let error = |message: String| format!("{number}: {message}");
+
+            if !first.is_ascii_alphabetic() {
+                if size.is_none() {
+                    return Err(error("table data before `LUT_3D_SIZE`".to_string()));
+                }
+                table.push(triple(line).map_err(error)?);
+                continue;
+            }
+            let (keyword, rest) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(keyword, rest)| (keyword, rest.trim()));
+            if !table.is_empty() {
+                return Err(error(format!("`{keyword}` must come before the table")));
+            }
+            match keyword {
+                "TITLE" => title = Some(rest.trim_matches('"').to_string()),
+                "LUT_3D_SIZE" => {
+                    let value = rest
+                        .parse()
+                        .ok()
+                        .filter(|value| SIZE_RANGE.contains(value))
+                        .ok_or_else(|| {
+                            error(format!(
+                                "`LUT_3D_SIZE` must be between {} and {}",
+                                SIZE_RANGE.start(),
+                                SIZE_RANGE.end()
+                            ))
+                        })?;
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = triple(rest).map_err(error)?,
+                "DOMAIN_MAX" => domain_max = triple(rest).map_err(error)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let range: Vec<f32> = numbers(rest).map_err(error)?;
+                    let [min, max] = range[..] else {
+                        return Err(error("expected two numbers".to_string()));
+                    };
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }
+                "LUT_1D_SIZE" => {
+                    return Err(error(
+                        "1D LUTs are not supported; export a 3D LUT".to_string(),
+                    ));
+                }
+                // Other keywords, such as Resolve's `LUT_1D_INPUT_RANGE`, do not affect a
+                // 3D table.
+                _ => {}
+            }
+        }
+
+        let size = size.ok_or_else(|| format!("{last_line}: missing `LUT_3D_SIZE`"))?;
+        if table.len() != size * size * size {
+            return Err(format!(
+                "{last_line}: expected {} table entries for size {size}, found {}",
+                size * size * size,
+                table.len()
+            ));
+        }
+        if (0..3).any(|i| domain_min[i] >= domain_max[i]) {
+            return Err(format!(
+                "{last_line}: `DOMAIN_MIN` must be below `DOMAIN_MAX` in every channel"
+            ));
+        }
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+}
+
+fn numbers(text: &str) -> Result<Vec<f32>, String> {
+    text.split_whitespace()
+        .map(|value| match value.parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(format!("`{value}` is not a finite number")),
+        })
+        .collect()
+}
+
+fn triple(text: &str) -> Result<[f32; 3], String> {
+    numbers(text)?
+        .try_into()
+        .map_err(|_| "expected three numbers".to_string())
+}
+
+/// How a colour between lattice points is looked up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Blends the eight surrounding points; the GPU's own filtering.
+    Trilinear,
+    /// Blends the four points of the enclosing tetrahedron, which keeps greys grey and
+    /// follows saturated edges more closely.
+    #[default]
+    Tetrahedral,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "trilinear" => Ok(Self::Trilinear),
+            "tetrahedral" => Ok(Self::Tetrahedral),
+            _ => Err(format!(
+                "unknown interpolation `{value}`; expected `trilinear` or `tetrahedral`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Trilinear => "trilinear",
+            Self::Tetrahedral => "tetrahedral",
+        })
+    }
+}
+
+/// A LUT as a stage of the filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LutStage {
+    pub lut: Lut3d,
+    pub interpolation: Interpolation,
+    /// Look the captured colours up directly instead of after the curve.
+    pub replaces_curve: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `.cube` text for a table of `size` whose entries `output` computes from the
+    /// lattice point.
+    fn cube_source(size: usize, output: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let mut source = format!("# generated\nTITLE \"test\"\nLUT_3D_SIZE {size}\n\n");
+        let last = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [r, g, b] = output([r, g, b].map(|c| c as f32 / last));
+                    source.push_str(&format!("{r} {g} {b}\n"));
+                }
+            }
+        }
+        source
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    const BOTH: [Interpolation; 2] = [Interpolation::Trilinear, Interpolation::Tetrahedral];
+
+    #[test]
+    fn parses_the_usual_sizes() {
+        for size in [17, 33, 65] {
+            let lut: Lut3d = cube_source(size, |rgb| rgb).parse().unwrap();
+            assert_eq!(lut.size(), size);
+            assert_eq!(lut.title.as_deref(), Some("test"));
+            assert!(close(lut.table()[size * size * size - 1], [1.0; 3]));
+            assert!(
+                lut.table()
+                    .iter()
+                    .zip(Lut3d::identity(size).table())
+                    .all(|(a, b)| close(*a, *b))
+            );
+        }
+    }
+
+    #[test]
+    fn identity_maps_colours_to_themselves() {
+        let lut = Lut3d::identity(17);
+        for rgb in [[0.0, 0.5, 1.0], [0.123, 0.456, 0.789], [0.99, 0.01, 0.3]] {
+            for interpolation in BOTH {
+                assert!(
+                    close(lut.sample(rgb, interpolation), rgb),
+                    "{rgb:?} {interpolation}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lattice_points_are_exact_and_linear_grades_are_reproduced() {
+        let grade = |[r, g, b]: [f32; 3]| [0.8 * r + 0.2 * g, g, 0.5 * b + 0.1];
+        let lut: Lut3d = cube_source(5, grade).parse().unwrap();
+        for rgb in [[0.25, 0.5, 0.75], [0.3, 0.61, 0.07], [1.0, 0.0, 0.9]] {
+            for interpolation in BOTH {
+                assert!(close(lut.sample(rgb, interpolation), grade(rgb)), "{rgb:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn tetrahedral_keeps_greys_on_the_diagonal() {
+        // Greys stay grey, everything else is pushed towards red.
+        let lut: Lut3d = cube_source(3, |[r, g, b]| {
+            if r == g && g == b {
+                [r, g, b]
+            } else {
+                [1.0, 0.0, 0.0]
+            }
+        })
+        .parse()
+        .unwrap();
+        let grey = [0.3; 3];
+        assert!(close(lut.sample(grey, Interpolation::Tetrahedral), grey));
+        assert!(!close(lut.sample(grey, Interpolation::Trilinear), grey));
+    }
+
+    #[test]
+    fn inputs_are_scaled_to_the_domain_and_clamped() {
+        let source = format!(
+            "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{}",
+            cube_source(2, |rgb| rgb)
+        );
+        let lut: Lut3d = source.parse().unwrap();
+        assert!(close(
+            lut.sample([1.0; 3], Interpolation::Trilinear),
+            [0.5; 3]
+        ));
+        assert!(close(
+            lut.sample([-1.0, 5.0, f32::NAN], Interpolation::Tetrahedral),
+            [0.0, 1.0, 0.0]
+        ));
+    }
+
+    #[test]
+    fn reports_bad_files_with_line_numbers() {
+        for (source, message) in [
+            ("LUT_3D_SIZE 2\n0 0 0\n", "expected 8 table entries"),
+            ("0 0 0\n", "1: table data before"),
+            ("LUT_3D_SIZE 1\n", "1: `LUT_3D_SIZE` must be between"),
+            ("LUT_1D_SIZE 1024\n", "1: 1D LUTs are not supported"),
+            ("LUT_3D_SIZE 2\n0 0 x\n", "2: `x` is not a finite number"),
+            ("LUT_3D_SIZE 2\n0 0\n", "2: expected three numbers"),
+            ("TITLE \"x\"\n", "missing `LUT_3D_SIZE`"),
+            (
+                "LUT_3D_SIZE 2\n0 0 0\nDOMAIN_MIN 0 0 0\n",
+                "3: `DOMAIN_MIN` must come before",
+            ),
+        ] {
+            let err = source.parse::<Lut3d>().unwrap_err();
+            assert!(err.contains(message), "{source:?}: {err}");
+        }
+        let inverted = format!("DOMAIN_MIN 1 0 0\n{}", cube_source(2, |rgb| rgb));
+        assert!(
+            inverted
+                .parse::<Lut3d>()
+                .unwrap_err()
+                .contains("DOMAIN_MIN")
+        );
+    }
+
+    #[test]
+    fn interpolation_names_round_trip() {
+        for interpolation in BOTH {
+            assert_eq!(interpolation.to_string().parse(), Ok(interpolation));
+        }
+        assert!("cubic".parse::<Interpolation>().is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Slot bookkeeping for handing frames from the capture thread to the renderer.
+//!
+//! The capturer writes into one of a small ring of textures while the renderer reads
+//! another, so neither side ever waits on the other. Only the newest published frame
+//! is offered to the renderer; anything it replaces before being read counts as dropped.
+//! The mailbox tracks slot indices only, the textures themselves live with the caller.
+
+/// Three slots are enough for one being written, one being read and one published.
+pub const DEFAULT_SLOT_COUNT: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MailboxStats {
+    /// Frames the writer finished.
+    pub published: u64,
+    /// Fresh frames handed to the reader.
+    pub consumed: u64,
+    /// Frames replaced by a newer one before the reader got to them.
+    pub dropped: u64,
+    /// Times the reader was handed a frame it had already seen, e.g. to repaint.
+    pub reused: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Published {
+    slot: usize,
+    frame_id: u64,
+    read: bool,
+}
+
+/// A frame the reader may use until it calls [`Mailbox::end_read`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadSlot {
+    pub slot: usize,
+    pub frame_id: u64,
+    pub generation: u64,
+}
+
+#[derive(Debug)]
+pub struct Mailbox {
+    slot_count: usize,
+    next_write: usize,
+    latest: Option<Published>,
+    reading: Option<usize>,
+    next_frame_id: u64,
+    generation: u64,
+    stats: MailboxStats,
+}
+
+impl Default for Mailbox {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOT_COUNT)
+    }
+}
+
+impl Mailbox {
+    pub fn new(slot_count: usize) -> Self {
+        assert!(slot_count >= 3, "a mailbox needs at least three slots");
+        Self {
+            slot_count,
+            next_write: 0,
+            latest: None,
+            reading: None,
+            next_frame_id: 1,
+            generation: 0,
+            stats: MailboxStats::default(),
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    pub fn stats(&self) -> MailboxStats {
+        self.stats
+    }
+
+    /// Forgets every slot, e.g. after the textures were recreated at a new size.
+    ///
+    /// Statistics and frame ids carry on, and reads begun before the reset are ignored
+    /// when they end.
+    pub fn reset(&mut self) {
+        self.latest = None;
+        self.reading = None;
+        self.next_write = 0;
+        self.generation += 1;
+    }
+
+    /// Picks a slot that is neither being read nor holding the newest frame.
+    pub fn begin_write(&mut self) -> Option<usize> {
+        let latest = self.latest.map(|latest| latest.slot);
+        let slot = (0..self.slot_count)
+            .map(|offset| (self.next_write + offset) % self.slot_count)
+            .find(|&slot| Some(slot) != latest && Some(slot) != self.reading)?;
+        self.next_write = (slot + 1) % self.slot_count;
+        Some(slot)
+    }
+
+    /// Gives up on the frame being written after [`Self::begin_write`], e.g. because the
+    /// copy could not start; it counts as dropped and the slot stays free.
+    pub fn discard(&mut self) {
+        self.stats.dropped += 1;
+    }
+
+    /// Makes `slot` the newest frame and returns its frame id.
+    pub fn publish(&mut self, slot: usize) -> u64 {
+        debug_assert!(slot < self.slot_count);
+        debug_assert_ne!(Some(slot), self.reading);
+        if self.latest.is_some_and(|latest| !latest.read) {
+            self.stats.dropped += 1;
+        }
+        let frame_id = self.next_frame_id;
+        self.next_frame_id += 1;
+        self.latest = Some(Published {
+            slot,
+            frame_id,
+            read: false,
+        });
+        self.stats.published += 1;
+        frame_id
+    }
+
+    /// Hands out the newest frame if the reader has not seen it yet.
+    pub fn begin_read(&mut self) -> Option<ReadSlot> {
+        self.read(false)
+    }
+
+    /// Hands out the newest frame even if it was read before.
+    pub fn begin_reread(&mut self) -> Option<ReadSlot> {
+        self.read(true)
+    }
+
+    fn read(&mut self, allow_seen: bool) -> Option<ReadSlot> {
+        if self.reading.is_some() {
+            return None;
+        }
+        let latest = self.latest.as_mut()?;
+        if latest.read {
+            if !allow_seen {
+                return None;
+            }
+            self.stats.reused += 1;
+        } else {
+            latest.read = true;
+            self.stats.consumed += 1;
+        }
+        self.reading = Some(latest.slot);
+        Some(ReadSlot {
+            slot: latest.slot,
+            frame_id: latest.frame_id,
+            generation: self.generation,
+        })
+    }
+
+    pub fn end_read(&mut self, read: ReadSlot) {
+        if read.generation == self.generation && self.reading == Some(read.slot) {
+            self.reading = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(mailbox: &mut Mailbox) -> (usize, u64) {
+        let slot = mailbox.begin_write().expect("no free slot");
+        (slot, mailbox.publish(slot))
+    }
+
+    #[test]
+    fn reader_gets_each_frame_once() {
+        let mut mailbox = Mailbox::default();
+        assert_eq!(mailbox.begin_read(), None);
+
+        let (slot, frame_id) = write(&mut mailbox);
+        let read = mailbox.begin_read().unwrap();
+        assert_eq!((read.slot, read.frame_id), (slot, frame_id));
+        mailbox.end_read(read);
+        assert_eq!(mailbox.begin_read(), None);
+    }
+
+    #[test]
+    fn reader_always_gets_newest_and_older_frames_count_as_dropped() {
+        let mut mailbox = Mailbox::default();
+        write(&mut mailbox);
+        write(&mut mailbox);
+        let (slot, frame_id) = write(&mut mailbox);
+
+        let read = mailbox.begin_read().unwrap();
+        assert_eq!((read.slot, read.frame_id), (slot, frame_id));
+        let stats = mailbox.stats();
+        assert_eq!((stats.published, stats.consumed, stats.dropped), (3, 1, 2));
+    }
+
+    #[test]
+    fn discarded_writes_count_as_dropped() {
+        let mut mailbox = Mailbox::default();
+        let (slot, _) = write(&mut mailbox);
+        assert_ne!(mailbox.begin_write(), Some(slot));
+        mailbox.discard();
+        let read = mailbox.begin_read().unwrap();
+        assert_eq!(read.slot, slot);
+        let stats = mailbox.stats();
+        assert_eq!((stats.published, stats.consumed, stats.dropped), (1, 1, 1));
+    }
+
+    #[test]
+    fn writer_never_touches_slot_being_read_or_newest() {
+        let mut mailbox = Mailbox::default();
+        write(&mut mailbox);
+        let read = mailbox.begin_read().unwrap();
+        for _ in 0..10 {
+            let (slot, _) = write(&mut mailbox);
+            assert_ne!(slot, read.slot);
+            let newest = mailbox.latest.unwrap().slot;
+            let next = mailbox.begin_write().unwrap();
+            assert_ne!(next, read.slot);
+            assert_ne!(next, newest);
+            mailbox.publish(next);
+        }
+        mailbox.end_read(read);
+        let read = mailbox.begin_read().unwrap();
+        assert_eq!(read.frame_id, 21);
+    }
+
+    #[test]
+    fn only_one_read_at_a_time() {
+        let mut mailbox = Mailbox::default();
+        write(&mut mailbox);
+        let read = mailbox.begin_read().unwrap();
+        write(&mut mailbox);
+        assert_eq!(mailbox.begin_read(), None);
+        mailbox.end_read(read);
+        assert!(mailbox.begin_read().is_some());
+    }
+
+    #[test]
+    fn reread_counts_as_reuse() {
+        let mut mailbox = Mailbox::default();
+        write(&mut mailbox);
+        let first = mailbox.begin_read().unwrap();
+        mailbox.end_read(first);
+
+        let again = mailbox.begin_reread().unwrap();
+        assert_eq!(again.frame_id, first.frame_id);
+        mailbox.end_read(again);
+        let stats = mailbox.stats();
+        assert_eq!((stats.consumed, stats.reused), (1, 1));
+    }
+
+    #[test]
+    fn reset_ignores_stale_reads_and_keeps_stats() {
+        let mut mailbox = Mailbox::default();
+        write(&mut mailbox);
+        let stale = mailbox.begin_read().unwrap();
+        mailbox.reset();
+        assert_eq!(mailbox.begin_read(), None);
+
+        let (_, frame_id) = write(&mut mailbox);
+        let read = mailbox.begin_read().unwrap();
+        mailbox.end_read(stale);
+        assert_eq!(
+            mailbox.begin_read(),
+            None,
+            "stale end_read released the new read"
+        );
+        mailbox.end_read(read);
+        assert_eq!(frame_id, 2);
+        assert_eq!(mailbox.stats().published, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "three slots")]
+    fn rejects_too_few_slots() {
+        Mailbox::new(2);
+    }
+}
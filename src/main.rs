@@ -1,21 +1,288 @@
-#[cfg(not(target_os = "windows"))]
-compile_error!("Only supports Windows for now.");
-
-mod app;
-mod capture;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
+use ban_shadow::{
+    config::{self, Config},
+    error::OverlayError,
+    hotkeys::{HotkeyAssignment, Hotkeys},
+    lut::{Interpolation, Lut3d, LutStage},
+    options::{OverlayOptions, Overrides},
+    process,
+    render::RendererKind,
+    target::{CaptureTarget, MonitorSelection},
+};
 use clap::Parser;
-use winit::event_loop::EventLoop;
 
-use crate::app::AppHandler;
+#[cfg(target_os = "windows")]
+use ban_shadow::app::monitor_infos;
 
 #[derive(clap::Parser)]
-struct Args {}
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Config file with named presets [default: ban-shadow.toml next to the executable,
+    /// then the user config directory]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Preset to start with [default: the config's `default_preset`, or `default`]
+    #[arg(long, global = true, value_name = "NAME")]
+    preset: Option<String>,
+    /// What to enhance: `monitor`, `title:<text>`, `process:<name.exe>` or `hwnd:<handle>`
+    /// [default: the preset's target, or `monitor`]
+    #[arg(long)]
+    target: Option<CaptureTarget>,
+    /// Monitors to enhance: `primary`, `all`, a one-based index or a name; repeatable
+    #[arg(long = "monitor", value_name = "MONITOR", value_delimiter = ',')]
+    monitors: Vec<MonitorSelection>,
+    /// Print the monitors `--monitor` can select and exit
+    #[arg(long)]
+    list_monitors: bool,
+    /// Print how many captured frames were rendered, dropped or reused every few seconds
+    #[arg(long)]
+    frame_stats: bool,
+    /// Render on a device of our own and share frames across devices, as older builds did
+    #[arg(long)]
+    separate_devices: bool,
+    /// What filters the frames: `auto`, `d3d11`, `wgpu` or `cpu`; `auto` is Direct3D 11 on
+    /// Windows and wgpu elsewhere as long as there is a hardware device, or else the CPU
+    #[arg(long, value_name = "RENDERER", default_value_t = RendererKind::default())]
+    renderer: RendererKind,
+    /// Rebind a global hotkey, e.g. `toggle=Ctrl+Alt+B` or `strength-down=none`; repeatable
+    #[arg(long = "hotkey", value_name = "ACTION=CHORD")]
+    hotkeys: Vec<HotkeyAssignment>,
+    /// Seconds a sudden flash of brightness stays damped; 0 turns dampening off
+    /// [default: the preset's `flash_decay`, or 0]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_flash_decay)]
+    flash_decay: Option<Duration>,
+    /// Soften the image while it flashes more than three times a second, for
+    /// photosensitive players
+    #[arg(long)]
+    flash_safety: bool,
+    /// HLSL file defining the pixel shader to draw with instead of the built-in one;
+    /// recompiled whenever it changes
+    #[arg(long, value_name = "PATH")]
+    shader: Option<PathBuf>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the filter over an image file instead of starting the overlay
+    Process {
+        /// Image to read, e.g. a saved screenshot
+        input: PathBuf,
+        /// Where to write the filtered PNG
+        output: PathBuf,
+    },
+}
+
+// Curve overrides; anything left out comes from the selected preset. A doc comment here
+// would become the `--help` description.
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Exponent applied to dark pixels; lower values lift shadows more; overrides the preset
+    #[arg(long, global = true, value_parser = parse_finite)]
+    gamma: Option<f32>,
+    /// Luma below which pixels get the full lift; overrides the preset
+    #[arg(long, global = true, value_parser = parse_finite)]
+    protect_low: Option<f32>,
+    /// Luma above which pixels are left untouched; overrides the preset
+    #[arg(long, global = true, value_parser = parse_finite)]
+    protect_high: Option<f32>,
+    /// Red, green and blue weights used to compute luma; overrides the preset
+    #[arg(long, global = true, value_name = "R,G,B", value_parser = parse_weights)]
+    luma_weights: Option<[f32; 3]>,
+    /// Derive the curve from each frame's brightness, lifting at most as much as `gamma`
+    #[arg(long, global = true)]
+    adaptive: bool,
+    /// `.cube` 3D LUT to grade with after the curve; overrides the preset
+    #[arg(long, global = true, value_name = "PATH")]
+    lut: Option<PathBuf>,
+    /// How the LUT is interpolated: `tetrahedral` or `trilinear`
+    #[arg(
+        long,
+        global = true,
+        requires = "lut",
+        value_name = "MODE",
+        default_value_t = Interpolation::default()
+    )]
+    lut_interpolation: Interpolation,
+    /// Apply the LUT to the original colours instead of after the curve
+    #[arg(long, global = true, requires = "lut")]
+    lut_replaces_curve: bool,
+}
+
+fn parse_finite(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|err| format!("{err}"))?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err("value must be a finite number".to_string())
+    }
+}
+
+fn parse_flash_decay(value: &str) -> Result<Duration, String> {
+    let seconds = parse_finite(value)?;
+    if (0.0..=config::MAX_FLASH_DECAY).contains(&seconds) {
+        Ok(Duration::from_secs_f32(seconds))
+    } else {
+        Err(format!(
+            "value must be between 0 and {} seconds",
+            config::MAX_FLASH_DECAY
+        ))
+    }
+}
 
-fn main() -> anyhow::Result<()> {
-    let _args = Args::try_parse()?;
-    let event_loop = EventLoop::new()?;
+fn parse_weights(value: &str) -> Result<[f32; 3], String> {
+    let weights = value
+        .split(',')
+        .map(|weight| parse_finite(weight.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    weights
+        .try_into()
+        .map_err(|_| "expected three comma-separated weights".to_string())
+}
 
-    event_loop.run_app(&mut AppHandler::default())?;
+/// Prints why the run failed with a hint for typed [`OverlayError`]s, and exits with
+/// the error's own code instead of showing a backtrace.
+fn main() -> ExitCode {
+    let err = match run(Args::parse()) {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(err) => err,
+    };
+    match err.downcast_ref::<OverlayError>() {
+        Some(overlay) => {
+            eprintln!("Error: {overlay}");
+            eprintln!("Hint: {}", overlay.hint());
+            ExitCode::from(overlay.exit_code())
+        }
+        None => {
+            eprintln!("Error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    if args.list_monitors {
+        return list_monitors();
+    }
+    // `--config` if given, otherwise the first config file found, otherwise the built-ins.
+    let config_path = args.config.or_else(config::discover);
+    let config = match &config_path {
+        Some(path) => Config::load(path).map_err(OverlayError::Config)?,
+        None => Config::default(),
+    };
+    let lut = match &args.filter.lut {
+        Some(path) => Some(Arc::new(LutStage {
+            lut: Lut3d::load(path).map_err(OverlayError::Config)?,
+            interpolation: args.filter.lut_interpolation,
+            replaces_curve: args.filter.lut_replaces_curve,
+        })),
+        None => None,
+    };
+    let overrides = Overrides {
+        preset: args.preset,
+        gamma: args.filter.gamma,
+        protect_low: args.filter.protect_low,
+        protect_high: args.filter.protect_high,
+        luma_weights: args.filter.luma_weights,
+        adaptive: args.filter.adaptive,
+        flash_decay: args.flash_decay,
+        flash_safety: args.flash_safety,
+        lut,
+        target: args.target,
+        monitors: args.monitors,
+    };
+    let mut settings = overrides
+        .resolve(&config)
+        .map_err(|err| OverlayError::Config(anyhow::Error::msg(err)))?;
+    let params = settings.params.clamped();
+    if params != settings.params {
+        eprintln!("Filter parameters adjusted to {params:?}");
+    }
+    settings.params = params;
+    let hotkeys = Hotkeys::with_assignments(&args.hotkeys)
+        .map_err(|err| OverlayError::Config(anyhow::Error::msg(err)))?;
+    match args.command {
+        Some(Command::Process { input, output }) => process::process_file(
+            &input,
+            &output,
+            &params,
+            settings.adaptive,
+            settings.lut.as_deref(),
+        ),
+        None => run_overlay(OverlayOptions {
+            settings,
+            overrides,
+            config_path,
+            shader_path: args.shader,
+            frame_stats: args.frame_stats,
+            separate_devices: args.separate_devices,
+            renderer: args.renderer,
+            hotkeys,
+        }),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn run_overlay(options: OverlayOptions) -> anyhow::Result<()> {
+    use ban_shadow::app::{AppHandler, OverlayEvent};
+
+    if options.renderer == RendererKind::Wgpu {
+        return Err(OverlayError::Config(anyhow::anyhow!(
+            "The Windows overlay renders with Direct3D 11 or the CPU; `--renderer wgpu` is only available on Linux so far"
+        ))
+        .into());
+    }
+    let event_loop = winit::event_loop::EventLoop::<OverlayEvent>::with_user_event()
+        .build()
+        .map_err(|err| OverlayError::Swapchain(err.into()))?;
+    let mut handler = AppHandler::new(event_loop.create_proxy(), options);
+    event_loop.run_app(&mut handler)?;
+    handler.finish()?;
     Ok(())
 }
+
+#[cfg(target_os = "linux")]
+fn run_overlay(options: OverlayOptions) -> anyhow::Result<()> {
+    if ban_shadow::wayland::is_session() {
+        ban_shadow::wayland::run(options)
+    } else {
+        ban_shadow::x11::run(options)
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn run_overlay(_options: OverlayOptions) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "The overlay only supports Windows, X11 and Wayland for now; use `ban-shadow process` instead"
+    )
+}
+
+fn list_monitors() -> anyhow::Result<()> {
+    for monitor in monitor_infos()? {
+        let primary = if monitor.primary { " (primary)" } else { "" };
+        println!(
+            "{}: {} {}{primary}",
+            monitor.index, monitor.device_name, monitor.name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn monitor_infos() -> anyhow::Result<Vec<ban_shadow::target::MonitorInfo>> {
+    if ban_shadow::wayland::is_session() {
+        return ban_shadow::wayland::monitor_infos();
+    }
+    ban_shadow::x11::monitor_infos()
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn monitor_infos() -> anyhow::Result<Vec<ban_shadow::target::MonitorInfo>> {
+    anyhow::bail!("Listing monitors only supports Windows, X11 and Wayland for now")
+}
@@ -0,0 +1,300 @@
+//! Settings the overlay is started with, independent of the platform frontend.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::hotkeys::{Enhancement, Hotkeys};
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+use crate::passes::Pass;
+use crate::presets::{self, Preset};
+use crate::profiles::Profiles;
+use crate::render::RendererKind;
+use crate::target::{CaptureTarget, MonitorSelection};
+
+/// Everything the command line decides about the overlay.
+#[derive(Clone, Debug, Default)]
+pub struct OverlayOptions {
+    pub settings: Settings,
+    /// Reapplied whenever the config file is reloaded.
+    pub overrides: Overrides,
+    /// Watched for changes while the overlay runs.
+    pub config_path: Option<PathBuf>,
+    /// HLSL file whose `ps_main` replaces the built-in one; also watched for changes.
+    pub shader_path: Option<PathBuf>,
+    /// Print the capture mailbox counters every few seconds.
+    pub frame_stats: bool,
+    /// Render on a device of our own even when the capture device could be shared.
+    pub separate_devices: bool,
+    pub renderer: RendererKind,
+    pub hotkeys: Hotkeys,
+}
+
+impl OverlayOptions {
+    /// What these options ask for that only the Direct3D renderer can do so far, for the
+    /// CPU renderer and the Linux frontends to report. `profiles_and_hotkeys` adds what
+    /// only the Windows overlay itself has. The photosensitive safety mode is not among
+    /// them, since it is refused with [`Settings::check_flash_safety`] instead.
+    pub fn unsupported_features(&self, profiles_and_hotkeys: bool) -> Vec<&'static str> {
+        let settings = &self.settings;
+        [
+            (settings.adaptive, "adaptive exposure"),
+            (!settings.flash_decay.is_zero(), "flash dampening"),
+            (self.shader_path.is_some(), "custom shaders"),
+            (!settings.passes.is_empty(), "passes"),
+            (self.separate_devices, "`--separate-devices`"),
+            (
+                profiles_and_hotkeys && !settings.profiles.is_empty(),
+                "profiles",
+            ),
+            (
+                profiles_and_hotkeys && self.hotkeys != Hotkeys::default(),
+                "hotkeys",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(requested, feature)| requested.then_some(feature))
+        .collect()
+    }
+}
+
+/// What the command line says about the preset; each field set wins over the config.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub preset: Option<String>,
+    pub gamma: Option<f32>,
+    pub protect_low: Option<f32>,
+    pub protect_high: Option<f32>,
+    pub luma_weights: Option<[f32; 3]>,
+    /// Turns adaptive exposure on even if the preset leaves it off.
+    pub adaptive: bool,
+    pub flash_decay: Option<Duration>,
+    /// Turns the photosensitive safety mode on even if the preset leaves it off.
+    pub flash_safety: bool,
+    /// Replaces the preset's LUT.
+    pub lut: Option<Arc<LutStage>>,
+    pub target: Option<CaptureTarget>,
+    pub monitors: Vec<MonitorSelection>,
+}
+
+/// The selected preset with the overrides applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// As requested; callers clamp them before use.
+    pub params: FilterParams,
+    pub adaptive: bool,
+    /// How long sudden bright frames stay damped; zero turns flash dampening off.
+    pub flash_decay: Duration,
+    pub flash_safety: bool,
+    pub lut: Option<Arc<LutStage>>,
+    pub target: CaptureTarget,
+    pub monitors: Vec<MonitorSelection>,
+    /// Most frames per second to capture; `None` follows the monitor refresh rate.
+    pub fps_cap: Option<u32>,
+    /// What the preset hotkeys cycle through; the built-in presets when empty.
+    pub presets: Vec<Preset>,
+    pub profiles: Profiles,
+    /// Passes each frame is drawn through; empty for just the filter.
+    pub passes: Vec<Pass>,
+}
+
+impl Overrides {
+    pub fn resolve(&self, config: &Config) -> Result<Settings, String> {
+        let preset_name = self.preset.as_deref().unwrap_or(&config.default_preset);
+        let preset = config.preset(preset_name).ok_or_else(|| {
+            format!(
+                "Unknown preset `{preset_name}`; expected one of {}",
+                config.preset_names()
+            )
+        })?;
+
+        if self.target.as_ref().is_some_and(CaptureTarget::is_window) && !self.monitors.is_empty() {
+            return Err("`--monitor` only applies to `--target monitor`".to_string());
+        }
+        let target = self
+            .target
+            .clone()
+            .or_else(|| preset.target.clone())
+            .unwrap_or_default();
+        let monitors = if self.monitors.is_empty() && !target.is_window() {
+            preset.monitors.clone()
+        } else {
+            self.monitors.clone()
+        };
+        Ok(Settings {
+            params: FilterParams {
+                gamma: self.gamma.unwrap_or(preset.params.gamma),
+                protect_low: self.protect_low.unwrap_or(preset.params.protect_low),
+                protect_high: self.protect_high.unwrap_or(preset.params.protect_high),
+                luma_weights: self.luma_weights.unwrap_or(preset.params.luma_weights),
+            },
+            adaptive: self.adaptive || preset.adaptive,
+            flash_decay: self
+                .flash_decay
+                .or(preset.flash_decay)
+                .unwrap_or(Duration::ZERO),
+            flash_safety: self.flash_safety || preset.flash_safety,
+            lut: self.lut.clone().or_else(|| preset.lut.clone()),
+            target,
+            monitors,
+            fps_cap: preset.fps_cap,
+            presets: config.presets.clone(),
+            profiles: config.profiles.clone(),
+            passes: config.passes.clone(),
+        })
+    }
+}
+
+impl Settings {
+    /// Whether moving to `new` needs the capture restarted; anything else is applied to
+    /// the running overlay.
+    pub fn needs_capture_restart(&self, new: &Settings) -> bool {
+        self.target != new.target || self.monitors != new.monitors || self.fps_cap != new.fps_cap
+    }
+
+    /// What the preset hotkeys cycle through: the configured presets, else the built-ins.
+    pub fn cycled_presets(&self) -> Vec<Preset> {
+        if self.presets.is_empty() {
+            presets::BUILTIN.to_vec()
+        } else {
+            self.presets.clone()
+        }
+    }
+
+    /// Fails if the photosensitive safety mode is on, for `overlay`s that cannot soften
+    /// flashes: someone relying on it is better off without an overlay than with one
+    /// that silently leaves the flashing in.
+    pub fn check_flash_safety(&self, overlay: &str) -> anyhow::Result<()> {
+        if self.flash_safety {
+            anyhow::bail!(
+                "{overlay} cannot soften flashes, so it does not run with the photosensitive \
+                 safety mode on; turn `flash_safety` off, or use the Windows overlay with \
+                 a Direct3D 11 hardware device"
+            );
+        }
+        Ok(())
+    }
+
+    /// The hotkey state the overlay starts with.
+    pub fn enhancement(&self) -> Enhancement {
+        let mut enhancement = Enhancement::new(self.params, self.cycled_presets());
+        enhancement.set_adaptive(self.adaptive);
+        enhancement.set_flash_safety(self.flash_safety);
+        enhancement.set_lut(self.lut.clone());
+        enhancement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(source: &str) -> Config {
+        Config::parse(source).unwrap()
+    }
+
+    #[test]
+    fn overrides_win_over_the_preset() {
+        let config = config(
+            "[presets.night]\ngamma = 0.6\nprotect_high = 0.4\nflash_decay = 2\ntarget = \"process:game.exe\"\n",
+        );
+        let overrides = Overrides {
+            preset: Some("night".to_string()),
+            gamma: Some(0.8),
+            flash_decay: Some(Duration::from_millis(500)),
+            target: Some(CaptureTarget::Monitors),
+            monitors: vec![MonitorSelection::Primary],
+            ..Overrides::default()
+        };
+        let settings = overrides.resolve(&config).unwrap();
+        assert_eq!(settings.params.gamma, 0.8);
+        assert_eq!(settings.flash_decay, Duration::from_millis(500));
+        assert_eq!(settings.params.protect_high, 0.4);
+        assert_eq!(settings.target, CaptureTarget::Monitors);
+        assert_eq!(settings.monitors, [MonitorSelection::Primary]);
+    }
+
+    #[test]
+    fn resolves_the_default_preset() {
+        let config = config("default_preset = \"night\"\n[presets.night]\nfps_cap = 30\n");
+        let settings = Overrides::default().resolve(&config).unwrap();
+        assert_eq!(settings.fps_cap, Some(30));
+        assert!(!settings.adaptive);
+        let adaptive = Overrides {
+            adaptive: true,
+            ..Overrides::default()
+        };
+        assert!(adaptive.resolve(&config).unwrap().adaptive);
+        assert_eq!(settings.flash_decay, Duration::ZERO);
+        assert!(!settings.flash_safety);
+        assert_eq!(settings.presets, config.presets);
+    }
+
+    #[test]
+    fn rejects_unknown_preset_and_monitors_with_window_target() {
+        let config = Config::default();
+        let unknown = Overrides {
+            preset: Some("nope".to_string()),
+            ..Overrides::default()
+        };
+        assert!(unknown.resolve(&config).unwrap_err().contains("`nope`"));
+
+        let mixed = Overrides {
+            target: Some("title:Game".parse().unwrap()),
+            monitors: vec![MonitorSelection::All],
+            ..Overrides::default()
+        };
+        assert!(mixed.resolve(&config).is_err());
+    }
+
+    #[test]
+    fn only_capture_settings_restart_the_capture() {
+        let old = Overrides::default().resolve(&Config::default()).unwrap();
+
+        let curve = Settings {
+            params: FilterParams {
+                gamma: 0.3,
+                ..old.params
+            },
+            presets: Vec::new(),
+            ..old.clone()
+        };
+        assert!(!old.needs_capture_restart(&curve));
+
+        let fps = Settings {
+            fps_cap: Some(30),
+            ..old.clone()
+        };
+        assert!(old.needs_capture_restart(&fps));
+
+        let target = Settings {
+            target: "process:game.exe".parse().unwrap(),
+            ..old.clone()
+        };
+        assert!(old.needs_capture_restart(&target));
+    }
+
+    #[test]
+    fn reports_rebound_hotkeys() {
+        let mut options = OverlayOptions::default();
+        assert!(options.unsupported_features(true).is_empty());
+        let unbound = "toggle=none".parse().unwrap();
+        options.hotkeys = Hotkeys::with_assignments(&[unbound]).unwrap();
+        assert_eq!(options.unsupported_features(true), ["hotkeys"]);
+        assert!(options.unsupported_features(false).is_empty());
+    }
+
+    #[test]
+    fn refuses_the_safety_mode_where_it_is_missing() {
+        let mut settings = Settings::default();
+        assert!(settings.check_flash_safety("The X11 overlay").is_ok());
+        settings.flash_safety = true;
+        let err = settings.check_flash_safety("The X11 overlay").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The X11 overlay cannot soften flashes")
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Tone-curve parameters shared by the shader and the CPU reference.
+
+use std::ops::RangeInclusive;
+
+use crate::lut::{Interpolation, LutStage};
+
+pub const GAMMA_RANGE: RangeInclusive<f32> = 0.1..=1.0;
+/// Smallest gap kept between the protect thresholds so `smoothstep` never divides by zero.
+pub const MIN_PROTECT_SPAN: f32 = 0.01;
+const WEIGHT_SUM_TOLERANCE: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterParams {
+    /// Exponent applied to dark pixels, `pow(rgb, gamma)`. Lower lifts more.
+    pub gamma: f32,
+    /// Luma below which pixels get the full lift.
+    pub protect_low: f32,
+    /// Luma above which pixels are left untouched.
+    pub protect_high: f32,
+    pub luma_weights: [f32; 3],
+}
+
+impl Default for FilterParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FilterParams {
+    /// The curve `shader.hlsl` originally hard-coded.
+    pub const DEFAULT: Self = Self {
+        gamma: 0.75,
+        protect_low: 0.05,
+        protect_high: 0.3,
+        luma_weights: [0.2126, 0.7152, 0.0722],
+    };
+
+    /// Forces every field into a range the shader can evaluate without producing NaNs.
+    ///
+    /// Non-finite values fall back to the defaults, luma weights are normalized to sum
+    /// to one, and the protect thresholds are kept ordered and at least
+    /// [`MIN_PROTECT_SPAN`] apart.
+    pub fn clamped(self) -> Self {
+        let defaults = Self::default();
+        let finite_or = |value: f32, default: f32| {
+            if value.is_finite() { value } else { default }
+        };
+
+        let gamma =
+            finite_or(self.gamma, defaults.gamma).clamp(*GAMMA_RANGE.start(), *GAMMA_RANGE.end());
+
+        let low = finite_or(self.protect_low, defaults.protect_low).clamp(0.0, 1.0);
+        let high = finite_or(self.protect_high, defaults.protect_high).clamp(0.0, 1.0);
+        let (low, high) = if low <= high {
+            (low, high)
+        } else {
+            (high, low)
+        };
+        let protect_low = low.min(1.0 - MIN_PROTECT_SPAN);
+        let protect_high = high.max(protect_low + MIN_PROTECT_SPAN).min(1.0);
+
+        let weights = self.luma_weights.map(|w| finite_or(w, 0.0).max(0.0));
+        let sum: f32 = weights.iter().sum();
+        let luma_weights = if sum <= 0.0 {
+            defaults.luma_weights
+        } else if (sum - 1.0).abs() > WEIGHT_SUM_TOLERANCE {
+            weights.map(|w| w / sum)
+        } else {
+            weights
+        };
+
+        Self {
+            gamma,
+            protect_low,
+            protect_high,
+            luma_weights,
+        }
+    }
+
+    pub fn to_shader(self) -> ShaderParams {
+        ShaderParams {
+            luma_weights: self.luma_weights,
+            gamma: self.gamma,
+            protect_low: self.protect_low,
+            protect_high: self.protect_high,
+            flash_damping: 0.0,
+            safety: 0.0,
+            safety_level: 0.0,
+            lut_mode: LUT_OFF,
+            lut_size: 0.0,
+            lut_replaces_curve: 0,
+            lut_domain_min: [0.0; 3],
+            _padding0: 0.0,
+            lut_domain_max: [1.0; 3],
+            _padding1: 0.0,
+        }
+    }
+}
+
+/// Layout of `cbuffer FilterParams` in `shader_interface.hlsl` and of the uniform in
+/// `shader.wgsl`, padded to a 16-byte multiple.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShaderParams {
+    pub luma_weights: [f32; 3],
+    pub gamma: f32,
+    pub protect_low: f32,
+    pub protect_high: f32,
+    /// How strongly a detected flash is held back, see [`crate::flash`]. Zero draws the
+    /// plain curve.
+    pub flash_damping: f32,
+    /// How strongly flashing is softened, see [`crate::flash_safety`].
+    pub safety: f32,
+    /// The luma softened pixels are pulled towards.
+    pub safety_level: f32,
+    /// [`LUT_OFF`], [`LUT_TRILINEAR`] or [`LUT_TETRAHEDRAL`].
+    pub lut_mode: u32,
+    pub lut_size: f32,
+    /// Non-zero to look the captured colours up instead of the curve's output.
+    pub lut_replaces_curve: u32,
+    pub lut_domain_min: [f32; 3],
+    pub _padding0: f32,
+    pub lut_domain_max: [f32; 3],
+    pub _padding1: f32,
+}
+
+/// Values of [`ShaderParams::lut_mode`], repeated in `shader_interface.hlsl`.
+pub const LUT_OFF: u32 = 0;
+pub const LUT_TRILINEAR: u32 = 1;
+pub const LUT_TETRAHEDRAL: u32 = 2;
+
+impl ShaderParams {
+    /// Adds `stage`'s lookup; the table itself is bound as a texture.
+    pub fn with_lut(self, stage: &LutStage) -> Self {
+        Self {
+            lut_mode: match stage.interpolation {
+                Interpolation::Trilinear => LUT_TRILINEAR,
+                Interpolation::Tetrahedral => LUT_TETRAHEDRAL,
+            },
+            lut_size: stage.lut.size() as f32,
+            lut_replaces_curve: stage.replaces_curve.into(),
+            lut_domain_min: stage.lut.domain_min(),
+            lut_domain_max: stage.lut.domain_max(),
+            ..self
+        }
+    }
+
+    /// The raw uniform contents, for APIs that upload bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        // `repr(C)` with only 4-byte fields, so there are no padding bytes to read.
+        unsafe { std::slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_unchanged_by_clamping() {
+        let defaults = FilterParams::default();
+        assert_eq!(defaults.clamped(), defaults);
+    }
+
+    #[test]
+    fn non_finite_values_fall_back_to_defaults() {
+        let params = FilterParams {
+            gamma: f32::NAN,
+            protect_low: f32::INFINITY,
+            protect_high: f32::NEG_INFINITY,
+            luma_weights: [f32::NAN; 3],
+        }
+        .clamped();
+        assert_eq!(params.gamma, 0.75);
+        assert_eq!(params.protect_low, 0.05);
+        assert_eq!(params.protect_high, 0.3);
+        assert_eq!(params.luma_weights, FilterParams::default().luma_weights);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let params = FilterParams {
+            gamma: 5.0,
+            protect_low: -1.0,
+            protect_high: 2.0,
+            luma_weights: [2.0, -1.0, 2.0],
+        }
+        .clamped();
+        assert_eq!(params.gamma, 1.0);
+        assert_eq!(params.protect_low, 0.0);
+        assert_eq!(params.protect_high, 1.0);
+        assert_eq!(params.luma_weights, [0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn protect_thresholds_stay_ordered_and_apart() {
+        let swapped = FilterParams {
+            protect_low: 0.4,
+            protect_high: 0.1,
+            ..Default::default()
+        }
+        .clamped();
+        assert_eq!((swapped.protect_low, swapped.protect_high), (0.1, 0.4));
+
+        let equal = FilterParams {
+            protect_low: 1.0,
+            protect_high: 1.0,
+            ..Default::default()
+        }
+        .clamped();
+        assert!(equal.protect_high - equal.protect_low >= MIN_PROTECT_SPAN - f32::EPSILON);
+        assert!(equal.protect_high <= 1.0);
+    }
+
+    #[test]
+    fn shader_params_match_cbuffer_layout() {
+        assert_eq!(std::mem::size_of::<ShaderParams>(), 80);
+        assert_eq!(std::mem::size_of::<ShaderParams>() % 16, 0);
+    }
+}
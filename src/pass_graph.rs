@@ -0,0 +1,229 @@
+//! Draws frames through the configured chain of passes.
+//!
+//! Every pass but the last renders into a half-float target of its own, so chaining
+//! stages does not band, sized relative to the captured frame and recreated whenever the
+//! capture size changes. The last pass draws into the overlay.
+
+use windows::Win32::Graphics::Direct3D::{D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST, ID3DBlob};
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_TEXTURE2D_DESC,
+    D3D11_USAGE_DEFAULT, D3D11_VIEWPORT, ID3D11Buffer, ID3D11Device, ID3D11DeviceContext,
+    ID3D11PixelShader, ID3D11RenderTargetView, ID3D11SamplerState, ID3D11ShaderResourceView,
+    ID3D11VertexShader,
+};
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_SAMPLE_DESC};
+
+use crate::app::{blob_bytes, compile_shader};
+use crate::custom_shader;
+use crate::passes::{self, Pass, PassShader};
+
+/// A pass with its shader compiled, ready to be created on any device.
+#[derive(Clone)]
+pub(crate) struct CompiledPass {
+    /// `None` for the overlay's own filter.
+    blob: Option<ID3DBlob>,
+    scale: f32,
+}
+
+/// Compiles the shader files of `passes`, naming the file in any error.
+pub(crate) fn compile(passes: &[Pass]) -> anyhow::Result<Vec<CompiledPass>> {
+    passes::chain(passes)
+        .iter()
+        .map(|pass| {
+            let blob = match &pass.shader {
+                PassShader::Filter => None,
+                PassShader::File { path, source } => {
+                    let source = custom_shader::with_interface(path, source);
+                    let blob = compile_shader(&source, custom_shader::ENTRY_POINT, "ps_5_0")
+                        .map_err(|err| {
+                            anyhow::anyhow!("Failed to compile {}: {err}", path.display())
+                        })?;
+                    Some(blob)
+                }
+            };
+            Ok(CompiledPass {
+                blob,
+                scale: pass.scale,
+            })
+        })
+        .collect()
+}
+
+pub(crate) struct PassGraph {
+    passes: Vec<GpuPass>,
+    /// Capture size the targets were created for.
+    frame_size: Option<(u32, u32)>,
+}
+
+struct GpuPass {
+    /// `None` draws with the overlay's own filter.
+    ps: Option<ID3D11PixelShader>,
+    scale: f32,
+    /// Where the pass draws; `None` for the last pass, which draws into the overlay.
+    target: Option<PassTarget>,
+}
+
+struct PassTarget {
+    srv: ID3D11ShaderResourceView,
+    rtv: ID3D11RenderTargetView,
+    size: (u32, u32),
+}
+
+/// The renderer's own pipeline objects, and where the frame comes from and goes.
+pub(crate) struct GraphInput<'a> {
+    pub vs: &'a ID3D11VertexShader,
+    pub filter_ps: &'a ID3D11PixelShader,
+    pub sampler: &'a ID3D11SamplerState,
+    pub params: &'a ID3D11Buffer,
+    pub lut: Option<&'a ID3D11ShaderResourceView>,
+    pub frame: &'a ID3D11ShaderResourceView,
+    pub frame_size: (u32, u32),
+    pub output: &'a ID3D11RenderTargetView,
+    pub viewport: D3D11_VIEWPORT,
+}
+
+impl PassGraph {
+    /// Just the filter, drawn straight into the overlay.
+    pub fn filter_only() -> Self {
+        Self {
+            passes: vec![GpuPass {
+                ps: None,
+                scale: 1.0,
+                target: None,
+            }],
+            frame_size: None,
+        }
+    }
+
+    pub fn new(device: &ID3D11Device, passes: &[CompiledPass]) -> anyhow::Result<Self> {
+        if passes.is_empty() {
+            return Ok(Self::filter_only());
+        }
+        let passes = passes
+            .iter()
+            .map(|pass| {
+                let ps = match &pass.blob {
+                    Some(blob) => {
+                        let mut ps = None;
+                        unsafe { device.CreatePixelShader(blob_bytes(blob), None, Some(&mut ps))? };
+                        Some(ps.ok_or_else(|| anyhow::anyhow!("Failed to create pass shader"))?)
+                    }
+                    None => None,
+                };
+                Ok(GpuPass {
+                    ps,
+                    scale: pass.scale,
+                    target: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            passes,
+            frame_size: None,
+        })
+    }
+
+    /// Recreates the intermediate targets when the capture size changes.
+    fn resize(&mut self, device: &ID3D11Device, frame_size: (u32, u32)) -> anyhow::Result<()> {
+        if self.frame_size == Some(frame_size) {
+            return Ok(());
+        }
+        self.frame_size = None;
+        let last = self.passes.len() - 1;
+        for pass in &mut self.passes[..last] {
+            pass.target = None;
+            let size = passes::target_size(frame_size, pass.scale);
+            pass.target = Some(create_target(device, size)?);
+        }
+        self.frame_size = Some(frame_size);
+        Ok(())
+    }
+
+    /// Draws `input.frame` through every pass, the last one into `input.output`. Each
+    /// pass samples the previous output at t0, the LUT at t1 and the captured frame at t2.
+    pub fn draw(
+        &mut self,
+        device: &ID3D11Device,
+        context: &ID3D11DeviceContext,
+        input: &GraphInput<'_>,
+    ) -> anyhow::Result<()> {
+        self.resize(device, input.frame_size)?;
+        unsafe {
+            context.IASetInputLayout(None);
+            context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            context.VSSetShader(input.vs, None);
+            context.PSSetSamplers(0, Some(&[Some(input.sampler.clone())]));
+            context.PSSetConstantBuffers(0, Some(&[Some(input.params.clone())]));
+        }
+        let mut source = input.frame.clone();
+        for pass in &self.passes {
+            let (rtv, viewport) = match &pass.target {
+                Some(target) => (&target.rtv, viewport(target.size)),
+                None => (input.output, input.viewport),
+            };
+            unsafe {
+                context.OMSetRenderTargets(Some(&[Some(rtv.clone())]), None);
+                context.RSSetViewports(Some(&[viewport]));
+                context.PSSetShader(pass.ps.as_ref().unwrap_or(input.filter_ps), None);
+                context.PSSetShaderResources(
+                    0,
+                    Some(&[
+                        Some(source.clone()),
+                        input.lut.cloned(),
+                        Some(input.frame.clone()),
+                    ]),
+                );
+                context.Draw(3, 0);
+            }
+            if let Some(target) = &pass.target {
+                source = target.srv.clone();
+            }
+        }
+        // Unbound, the targets can be drawn into again on the next frame.
+        unsafe { context.PSSetShaderResources(0, Some(&[None, None, None])) };
+        Ok(())
+    }
+}
+
+fn viewport((width, height): (u32, u32)) -> D3D11_VIEWPORT {
+    D3D11_VIEWPORT {
+        TopLeftX: 0.0,
+        TopLeftY: 0.0,
+        Width: width as f32,
+        Height: height as f32,
+        MinDepth: 0.0,
+        MaxDepth: 1.0,
+    }
+}
+
+fn create_target(device: &ID3D11Device, size: (u32, u32)) -> anyhow::Result<PassTarget> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: size.0,
+        Height: size.1,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_R16G16B16A16_FLOAT,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+        CPUAccessFlags: 0,
+        MiscFlags: 0,
+    };
+    let mut texture = None;
+    let mut srv = None;
+    let mut rtv = None;
+    unsafe { device.CreateTexture2D(&desc, None, Some(&mut texture))? };
+    let texture = texture.ok_or_else(|| anyhow::anyhow!("Failed to create pass target"))?;
+    unsafe {
+        device.CreateShaderResourceView(&texture, None, Some(&mut srv))?;
+        device.CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+    }
+    Ok(PassTarget {
+        srv: srv.ok_or_else(|| anyhow::anyhow!("Failed to create pass target view"))?,
+        rtv: rtv.ok_or_else(|| anyhow::anyhow!("Failed to create pass target view"))?,
+        size,
+    })
+}
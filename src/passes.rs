@@ -0,0 +1,101 @@
+//! The chain of full-screen passes a frame is drawn through, from the `[[passes]]`
+//! tables of the config file.
+//!
+//! Each pass samples the output of the one before it, so stages such as a denoise, the
+//! shadow lift and a sharpen can be chained, the early ones at reduced resolution. The
+//! last pass draws into the overlay itself.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// Smallest `scale` a pass may render at.
+pub const MIN_SCALE: f32 = 0.125;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pass {
+    pub shader: PassShader,
+    /// Size of the pass's render target relative to the captured frame, up to `1`.
+    pub scale: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PassShader {
+    /// The overlay's own filter: the curve, the LUT and the flash modes, or `--shader`.
+    Filter,
+    /// An HLSL file defining `ps_main`, compiled like `--shader`.
+    File { path: PathBuf, source: String },
+}
+
+/// What an empty chain draws with.
+static FILTER_ONLY: [Pass; 1] = [Pass::FILTER];
+
+impl Pass {
+    pub const FILTER: Pass = Pass {
+        shader: PassShader::Filter,
+        scale: 1.0,
+    };
+}
+
+/// Render target size of a pass at `scale` for a capture of `frame` pixels; never empty.
+pub fn target_size(frame: (u32, u32), scale: f32) -> (u32, u32) {
+    let scaled = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+    (scaled(frame.0), scaled(frame.1))
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.shader {
+            PassShader::Filter => f.write_str("filter")?,
+            PassShader::File { path, .. } => write!(f, "{}", path.display())?,
+        }
+        if self.scale != 1.0 {
+            write!(f, " at {}x", self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+/// The passes to draw with: `passes`, or just the filter when none are configured.
+pub fn chain(passes: &[Pass]) -> &[Pass] {
+    if passes.is_empty() {
+        &FILTER_ONLY
+    } else {
+        passes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_pass(scale: f32) -> Pass {
+        Pass {
+            shader: PassShader::File {
+                path: PathBuf::from("denoise.hlsl"),
+                source: String::new(),
+            },
+            scale,
+        }
+    }
+
+    #[test]
+    fn target_size_follows_the_scale() {
+        assert_eq!(target_size((1920, 1080), 1.0), (1920, 1080));
+        assert_eq!(target_size((1920, 1080), 0.5), (960, 540));
+        assert_eq!(target_size((1366, 767), 0.25), (342, 192));
+        assert_eq!(target_size((3, 2), MIN_SCALE), (1, 1));
+    }
+
+    #[test]
+    fn empty_chain_is_the_filter() {
+        assert_eq!(chain(&[]), [Pass::FILTER]);
+        let passes = [file_pass(0.5), Pass::FILTER];
+        assert_eq!(chain(&passes), passes);
+    }
+
+    #[test]
+    fn passes_are_named_by_their_shader() {
+        assert_eq!(Pass::FILTER.to_string(), "filter");
+        assert_eq!(file_pass(0.5).to_string(), "denoise.hlsl at 0.5x");
+    }
+}
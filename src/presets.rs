@@ -0,0 +1,104 @@
+//! Named filter presets that ship with the binary.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+use crate::target::{CaptureTarget, MonitorSelection};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: Cow<'static, str>,
+    pub params: FilterParams,
+    /// What to capture; `None` leaves it to `--target`.
+    pub target: Option<CaptureTarget>,
+    /// Monitors to enhance; empty leaves it to `--monitor`.
+    pub monitors: Vec<MonitorSelection>,
+    /// Most frames per second to capture; `None` follows the monitor refresh rate.
+    pub fps_cap: Option<u32>,
+    /// Derive the curve from each frame, with `params.gamma` as the strongest lift.
+    pub adaptive: bool,
+    /// How long sudden bright frames stay damped; `None` leaves flash dampening off.
+    pub flash_decay: Option<Duration>,
+    /// Soften the image while it flashes more often than is safe for photosensitive
+    /// players.
+    pub flash_safety: bool,
+    /// A colour grade applied after the curve, or instead of it.
+    pub lut: Option<Arc<LutStage>>,
+}
+
+impl Preset {
+    /// A preset that only sets the curve.
+    pub const fn curve(name: &'static str, params: FilterParams) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            params,
+            target: None,
+            monitors: Vec::new(),
+            fps_cap: None,
+            adaptive: false,
+            flash_decay: None,
+            flash_safety: false,
+            lut: None,
+        }
+    }
+}
+
+pub const DEFAULT_PRESET: &str = "default";
+
+pub const BUILTIN: &[Preset] = &[
+    Preset::curve(
+        "subtle",
+        FilterParams {
+            gamma: 0.85,
+            protect_low: 0.04,
+            protect_high: 0.25,
+            ..FilterParams::DEFAULT
+        },
+    ),
+    Preset::curve(DEFAULT_PRESET, FilterParams::DEFAULT),
+    Preset::curve(
+        "strong",
+        FilterParams {
+            gamma: 0.6,
+            protect_low: 0.06,
+            protect_high: 0.35,
+            ..FilterParams::DEFAULT
+        },
+    ),
+];
+
+/// Looks up a built-in preset by name, ignoring ASCII case.
+pub fn find(name: &str) -> Option<&'static Preset> {
+    BUILTIN
+        .iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_preset_matches_default_params() {
+        assert_eq!(
+            find(DEFAULT_PRESET).unwrap().params,
+            FilterParams::default()
+        );
+    }
+
+    #[test]
+    fn builtins_are_already_clamped() {
+        for preset in BUILTIN {
+            assert_eq!(preset.params.clamped(), preset.params, "{}", preset.name);
+        }
+    }
+
+    #[test]
+    fn lookup_ignores_case() {
+        assert_eq!(find("Strong").unwrap().name, "strong");
+        assert!(find("missing").is_none());
+    }
+}
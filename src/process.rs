@@ -0,0 +1,133 @@
+//! Offline processing of saved images, without capture or D3D11.
+
+use std::path::Path;
+
+use anyhow::Context;
+use image::{ImageFormat, RgbaImage};
+
+use crate::exposure::{self, ExposureSettings, Histogram};
+use crate::filter::{self, PixelFormat};
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+
+/// Filters `input` and writes the result to `output` as PNG. With `adaptive`, the curve
+/// is derived from the image the way the overlay derives it from a steady scene; `lut`
+/// grades the result.
+pub fn process_file(
+    input: &Path,
+    output: &Path,
+    params: &FilterParams,
+    adaptive: bool,
+    lut: Option<&LutStage>,
+) -> anyhow::Result<()> {
+    let image = image::open(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?
+        .into_rgba8();
+    let params = if adaptive {
+        adaptive_params(&image, params)?
+    } else {
+        *params
+    };
+    let image = process_image(image, &params, lut)?;
+    image
+        .save_with_format(output, ImageFormat::Png)
+        .with_context(|| format!("Failed to write {}", output.display()))
+}
+
+/// The curve adaptive exposure settles on for `image`, with `base.gamma` as the strongest
+/// lift.
+pub fn adaptive_params(image: &RgbaImage, base: &FilterParams) -> anyhow::Result<FilterParams> {
+    let histogram = Histogram::from_pixels(image, PixelFormat::Rgba8, base.luma_weights)?;
+    Ok(exposure::target_params(&histogram, *base, &ExposureSettings::default()).unwrap_or(*base))
+}
+
+pub fn process_image(
+    mut image: RgbaImage,
+    params: &FilterParams,
+    lut: Option<&LutStage>,
+) -> anyhow::Result<RgbaImage> {
+    filter::apply_graded(&mut image, PixelFormat::Rgba8, params, lut)?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::lut::{Interpolation, Lut3d};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ban-shadow-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn writes_filtered_png() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("before.png");
+        let output = temp_path("after.png");
+        let params = FilterParams::default();
+        process_file(&input, &output, &params, false, None).unwrap();
+
+        let written = image::open(&output).unwrap().into_rgba8();
+        std::fs::remove_file(&output).unwrap();
+        let expected =
+            process_image(image::open(&input).unwrap().into_rgba8(), &params, None).unwrap();
+        assert!(written == expected);
+    }
+
+    #[test]
+    fn luts_grade_the_filtered_image() {
+        let image = image::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("before.png"))
+            .unwrap()
+            .into_rgba8();
+        let params = FilterParams::default();
+        let plain = process_image(image.clone(), &params, None).unwrap();
+        let identity = LutStage {
+            lut: Lut3d::identity(33),
+            interpolation: Interpolation::Tetrahedral,
+            replaces_curve: false,
+        };
+        let graded = process_image(image.clone(), &params, Some(&identity)).unwrap();
+        assert!(
+            plain
+                .pixels()
+                .zip(graded.pixels())
+                .all(|(a, b)| { a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= 1) })
+        );
+
+        let replaced = LutStage {
+            replaces_curve: true,
+            ..identity
+        };
+        let graded = process_image(image.clone(), &params, Some(&replaced)).unwrap();
+        assert!(image.pixels().zip(graded.pixels()).all(|(a, b)| {
+            a.0[..3]
+                .iter()
+                .zip(&b.0[..3])
+                .all(|(a, b)| a.abs_diff(*b) <= 1)
+        }));
+    }
+
+    #[test]
+    fn adaptive_params_stay_within_the_base_strength() {
+        let dark = RgbaImage::from_pixel(4, 4, image::Rgba([5, 5, 5, 255]));
+        let bright = RgbaImage::from_pixel(4, 4, image::Rgba([200, 200, 200, 255]));
+        let base = FilterParams::default();
+        assert_eq!(adaptive_params(&dark, &base).unwrap().gamma, base.gamma);
+        assert_eq!(adaptive_params(&bright, &base).unwrap().gamma, 1.0);
+    }
+
+    #[test]
+    fn missing_input_names_the_path() {
+        let input = temp_path("missing.png");
+        let err = process_file(
+            &input,
+            &temp_path("out.png"),
+            &FilterParams::default(),
+            false,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains(&*input.to_string_lossy()));
+    }
+}
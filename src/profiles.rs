@@ -0,0 +1,293 @@
+//! Per-game profiles: which preset to use, or whether to hide the overlay, depending on
+//! the application in the foreground.
+//!
+//! Matching is kept free of Win32 calls; the Windows frontend reports the foreground
+//! window's executable and class as a [`ForegroundApp`].
+
+use std::fmt;
+use std::str::FromStr;
+
+use regex::{Regex, RegexBuilder};
+
+/// The profile used when no other profile matches.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A case-insensitive glob (`*` and `?`), or a regular expression after `re:`.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Whether the whole of `text` matches; regular expressions may match any part.
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob_matches(glob, &text.to_lowercase()),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(regex) = value.strip_prefix("re:") {
+            return RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .map(Self::Regex)
+                .map_err(|err| format!("invalid regular expression `{regex}`: {err}"));
+        }
+        if value.is_empty() {
+            return Err("pattern must not be empty".to_string());
+        }
+        Ok(Self::Glob(value.to_lowercase()))
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(glob) => f.write_str(glob),
+            Self::Regex(regex) => write!(f, "re:{}", regex.as_str()),
+        }
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Where the last `*` was and how much text it has swallowed so far.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut g, mut t) = (0, 0);
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    star = Some((star_g, star_t + 1));
+                    g = star_g + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// What a matching profile does to the overlay.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProfileAction {
+    /// Switch to the named preset's curve.
+    Preset(String),
+    /// Hide the overlay while the application is in the foreground.
+    Hide,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// Matched against the executable's file name, with or without `.exe`.
+    pub exe: Option<Pattern>,
+    /// Matched against the foreground window's class name.
+    pub class: Option<Pattern>,
+    /// Higher wins when several profiles match; ties go to the one listed first.
+    pub priority: i32,
+    pub action: ProfileAction,
+}
+
+impl Profile {
+    /// Whether every pattern the profile sets matches `app`. A profile without patterns
+    /// matches nothing, it can only serve as the default.
+    pub fn matches(&self, app: &ForegroundApp) -> bool {
+        if self.exe.is_none() && self.class.is_none() {
+            return false;
+        }
+        let exe_matches = self.exe.as_ref().is_none_or(|exe| {
+            let name = app.exe.to_ascii_lowercase();
+            exe.matches(&name)
+                || name
+                    .strip_suffix(".exe")
+                    .is_some_and(|stem| exe.matches(stem))
+        });
+        exe_matches
+            && self
+                .class
+                .as_ref()
+                .is_none_or(|class| class.matches(&app.class))
+    }
+}
+
+/// The application owning the foreground window.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForegroundApp {
+    /// Executable file name, e.g. `eldenring.exe`.
+    pub exe: String,
+    pub class: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profiles {
+    rules: Vec<Profile>,
+    default: Option<Profile>,
+}
+
+impl Profiles {
+    /// Profiles in the order they were written; the one named [`DEFAULT_PROFILE`] is
+    /// used when nothing else matches.
+    pub fn new(profiles: Vec<Profile>) -> Self {
+        let (default, rules): (Vec<_>, Vec<_>) = profiles
+            .into_iter()
+            .partition(|profile| profile.name.eq_ignore_ascii_case(DEFAULT_PROFILE));
+        Self {
+            rules,
+            default: default.into_iter().next_back(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.default.is_none()
+    }
+
+    /// The profile for `app`: the highest-priority match, else the default profile.
+    /// `None` leaves the overlay as it is.
+    pub fn select(&self, app: &ForegroundApp) -> Option<&Profile> {
+        let mut best: Option<&Profile> = None;
+        for profile in self.rules.iter().filter(|profile| profile.matches(app)) {
+            if best.is_none_or(|best| profile.priority > best.priority) {
+                best = Some(profile);
+            }
+        }
+        best.or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(exe: &str, class: &str) -> ForegroundApp {
+        ForegroundApp {
+            exe: exe.to_string(),
+            class: class.to_string(),
+        }
+    }
+
+    fn profile(name: &str, exe: Option<&str>, class: Option<&str>, priority: i32) -> Profile {
+        Profile {
+            name: name.to_string(),
+            exe: exe.map(|exe| exe.parse().unwrap()),
+            class: class.map(|class| class.parse().unwrap()),
+            priority,
+            action: ProfileAction::Preset(name.to_string()),
+        }
+    }
+
+    fn selected(profiles: &Profiles, exe: &str, class: &str) -> Option<String> {
+        profiles
+            .select(&app(exe, class))
+            .map(|profile| profile.name.clone())
+    }
+
+    #[test]
+    fn globs_match_whole_names_case_insensitively() {
+        let pattern: Pattern = "Elden*.EXE".parse().unwrap();
+        assert!(pattern.matches("eldenring.exe"));
+        assert!(!pattern.matches("xeldenring.exe"));
+
+        let pattern: Pattern = "h?nt*".parse().unwrap();
+        assert!(pattern.matches("Hunt.exe"));
+        assert!(!pattern.matches("ht.exe"));
+        assert!("*a*b".parse::<Pattern>().unwrap().matches("xaxxab"));
+        assert!(!"*a*b".parse::<Pattern>().unwrap().matches("xaxxa"));
+    }
+
+    #[test]
+    fn regexes_are_case_insensitive_and_reject_bad_syntax() {
+        let pattern: Pattern = "re:^(hunt|tarkov).*".parse().unwrap();
+        assert!(!pattern.matches("EscapeFromTarkov.exe"));
+        assert!(pattern.matches("Tarkov.exe"));
+        assert_eq!(pattern.to_string(), "re:^(hunt|tarkov).*");
+        assert!("re:(".parse::<Pattern>().is_err());
+        assert!("".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn exe_matches_with_or_without_suffix() {
+        let profiles = Profiles::new(vec![profile("elden", Some("eldenring"), None, 0)]);
+        assert_eq!(
+            selected(&profiles, "EldenRing.exe", "ELDEN RING"),
+            Some("elden".to_string())
+        );
+        assert_eq!(selected(&profiles, "eldenring2.exe", ""), None);
+    }
+
+    #[test]
+    fn every_set_pattern_has_to_match() {
+        let profiles = Profiles::new(vec![profile(
+            "unreal",
+            Some("*.exe"),
+            Some("UnrealWindow"),
+            0,
+        )]);
+        assert_eq!(
+            selected(&profiles, "game.exe", "UnrealWindow"),
+            Some("unreal".to_string())
+        );
+        assert_eq!(selected(&profiles, "game.exe", "Chrome_WidgetWin_1"), None);
+    }
+
+    #[test]
+    fn highest_priority_wins_and_ties_go_to_the_first() {
+        let profiles = Profiles::new(vec![
+            profile("any-game", Some("*"), None, 0),
+            profile("unreal", None, Some("UnrealWindow"), 5),
+            profile("also-any", Some("*"), None, 0),
+        ]);
+        assert_eq!(
+            selected(&profiles, "game.exe", "UnrealWindow"),
+            Some("unreal".to_string())
+        );
+        assert_eq!(
+            selected(&profiles, "game.exe", "Other"),
+            Some("any-game".to_string())
+        );
+    }
+
+    #[test]
+    fn default_profile_catches_everything_else() {
+        let profiles = Profiles::new(vec![
+            Profile {
+                action: ProfileAction::Hide,
+                ..profile(DEFAULT_PROFILE, None, None, 0)
+            },
+            profile("elden", Some("eldenring.exe"), None, 0),
+        ]);
+        assert!(!profiles.is_empty());
+        assert_eq!(
+            selected(&profiles, "eldenring.exe", ""),
+            Some("elden".to_string())
+        );
+        let other = profiles.select(&app("explorer.exe", "CabinetWClass"));
+        assert_eq!(other.unwrap().action, ProfileAction::Hide);
+
+        assert!(Profiles::default().is_empty());
+        assert_eq!(selected(&Profiles::default(), "explorer.exe", ""), None);
+    }
+}
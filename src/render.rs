@@ -0,0 +1,136 @@
+//! Renderers for the frontends that capture into CPU memory: they filter a frame and hand
+//! the pixels back for the overlay to present.
+//!
+//! [`GpuRenderer`] draws `shader.wgsl`, the WGSL port of `ps_main`, with wgpu on Vulkan,
+//! GL or Metal. [`CpuRenderer`] runs the reference in [`crate::filter`] on every core,
+//! with SIMD where it can. Both give the same pixels give or take rounding, which the
+//! wgpu tests check on a software adapter.
+
+mod cpu;
+mod gpu;
+
+pub use cpu::{CpuRenderer, apply_parallel};
+pub use gpu::GpuRenderer;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+use crate::source::Frame;
+
+/// Which renderer filters the frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RendererKind {
+    /// Direct3D 11 on Windows and wgpu elsewhere, as long as there is a hardware device;
+    /// else the CPU.
+    #[default]
+    Auto,
+    D3d11,
+    /// wgpu on the best adapter there is, a software one included.
+    Wgpu,
+    Cpu,
+}
+
+impl FromStr for RendererKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "d3d11" => Ok(Self::D3d11),
+            "wgpu" => Ok(Self::Wgpu),
+            "cpu" => Ok(Self::Cpu),
+            _ => Err(format!(
+                "unknown renderer `{value}`; expected `auto`, `d3d11`, `wgpu` or `cpu`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RendererKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::D3d11 => "d3d11",
+            Self::Wgpu => "wgpu",
+            Self::Cpu => "cpu",
+        })
+    }
+}
+
+/// A filtered frame, tightly packed in the format of the frame it came from.
+pub struct Filtered<'a> {
+    pub pixels: &'a [u8],
+    pub size: (u32, u32),
+}
+
+pub trait FrameRenderer {
+    /// Starts filtering `frame` and returns the newest filtered frame that is ready. The
+    /// CPU renderer finishes `frame` before returning it; the wgpu renderer reads frames
+    /// back while the next one is captured, so it returns an earlier frame, or `None`
+    /// while that is still on the GPU.
+    fn render(
+        &mut self,
+        frame: &Frame,
+        params: &FilterParams,
+        lut: Option<&Arc<LutStage>>,
+    ) -> anyhow::Result<Option<Filtered<'_>>>;
+
+    /// Whether a frame is still being filtered, for [`Self::finished`] to hand out later.
+    fn pending(&self) -> bool {
+        false
+    }
+
+    /// The newest frame that finished filtering since the last call, if any. With `wait`,
+    /// waits for the frames still being filtered first, for when no newer frame arrives
+    /// to push them out.
+    fn finished(&mut self, _wait: bool) -> anyhow::Result<Option<Filtered<'_>>> {
+        Ok(None)
+    }
+
+    /// A renderer of the same kind for another overlay, on the same device.
+    fn another(&self) -> Box<dyn FrameRenderer>;
+
+    /// What the frames are filtered on, for the startup message.
+    fn description(&self) -> String;
+}
+
+/// Opens the renderer `kind` asks for. `Auto` falls back to the CPU when there is no
+/// hardware adapter, since a software one is slower than the reference.
+pub fn create(kind: RendererKind) -> anyhow::Result<Box<dyn FrameRenderer>> {
+    let renderer: Box<dyn FrameRenderer> = match kind {
+        RendererKind::Auto => match GpuRenderer::new(false) {
+            Ok(renderer) => Box::new(renderer),
+            Err(err) => {
+                eprintln!("No GPU renderer ({err:#}); filtering on the CPU");
+                Box::new(CpuRenderer::default())
+            }
+        },
+        RendererKind::Wgpu => Box::new(GpuRenderer::new(true)?),
+        RendererKind::Cpu => Box::new(CpuRenderer::default()),
+        RendererKind::D3d11 => anyhow::bail!("`--renderer d3d11` is only available on Windows"),
+    };
+    eprintln!("Filtering on {}", renderer.description());
+    Ok(renderer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_renderer_names() {
+        for kind in [
+            RendererKind::Auto,
+            RendererKind::D3d11,
+            RendererKind::Wgpu,
+            RendererKind::Cpu,
+        ] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+        assert_eq!("WGPU".parse(), Ok(RendererKind::Wgpu));
+        assert!("vulkan".parse::<RendererKind>().is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use std::array;
+use std::sync::Arc;
+
+use anyhow::bail;
+use rayon::prelude::*;
+use wide::f32x8;
+
+use super::{Filtered, FrameRenderer};
+use crate::filter::{self, PixelFormat};
+use crate::lut::LutStage;
+use crate::params::FilterParams;
+use crate::source::Frame;
+
+/// Pixels each thread filters at a time; large enough to keep scheduling overhead out of
+/// the profile, small enough to balance a 1080p frame across a dozen cores.
+const CHUNK_PIXELS: usize = 16 * 1024;
+const LANES: usize = 8;
+
+/// Filters on every core with [`apply_parallel`].
+#[derive(Default)]
+pub struct CpuRenderer {
+    pixels: Vec<u8>,
+}
+
+impl CpuRenderer {
+    /// Filters `frame` and returns the result, tightly packed in the frame's format.
+    pub fn filter(
+        &mut self,
+        frame: &Frame,
+        params: &FilterParams,
+        lut: Option<&Arc<LutStage>>,
+    ) -> anyhow::Result<&[u8]> {
+        let Some(pixels) = frame.pixels.as_cpu() else {
+            bail!("The CPU renderer needs frames in CPU memory");
+        };
+        self.pixels.clear();
+        self.pixels.extend_from_slice(pixels);
+        apply_parallel(&mut self.pixels, frame.format, params, lut.map(Arc::as_ref))?;
+        Ok(&self.pixels)
+    }
+}
+
+impl FrameRenderer for CpuRenderer {
+    fn render(
+        &mut self,
+        frame: &Frame,
+        params: &FilterParams,
+        lut: Option<&Arc<LutStage>>,
+    ) -> anyhow::Result<Option<Filtered<'_>>> {
+        let pixels = self.filter(frame, params, lut)?;
+        Ok(Some(Filtered {
+            pixels,
+            size: (frame.width, frame.height),
+        }))
+    }
+
+    fn another(&self) -> Box<dyn FrameRenderer> {
+        Box::new(Self::default())
+    }
+
+    fn description(&self) -> String {
+        format!("the CPU ({} threads)", rayon::current_num_threads())
+    }
+}
+
+/// [`filter::apply_graded`] split across every core, giving the same bytes.
+///
+/// 8-bit frames without a LUT take a SIMD path that evaluates the curve eight pixels at
+/// a time; LUTs and `Rgba16F` frames run the reference per pixel.
+pub fn apply_parallel(
+    buffer: &mut [u8],
+    format: PixelFormat,
+    params: &FilterParams,
+    lut: Option<&LutStage>,
+) -> anyhow::Result<()> {
+    let bpp = format.bytes_per_pixel();
+    if !buffer.len().is_multiple_of(bpp) {
+        bail!(
+            "Buffer length {} is not a multiple of {bpp} bytes per pixel",
+            buffer.len()
+        );
+    }
+    let chunks = buffer.par_chunks_mut(CHUNK_PIXELS * bpp);
+    match (format, lut) {
+        (PixelFormat::Rgba8, None) => {
+            let curve = Curve::new(params);
+            chunks.for_each(|chunk| curve.apply(chunk, 0, 2));
+        }
+        (PixelFormat::Bgra8, None) => {
+            let curve = Curve::new(params);
+            chunks.for_each(|chunk| curve.apply(chunk, 2, 0));
+        }
+        _ => chunks.try_for_each(|chunk| filter::apply_graded(chunk, format, params, lut))?,
+    }
+    Ok(())
+}
+
+/// [`filter::shade`] for 8-bit channels. `powf` is the expensive part and only ever sees
+/// 256 inputs, so it is tabulated once per frame; the rest is the same float operations
+/// in the same order, so the output matches the reference exactly.
+struct Curve {
+    /// Each 8-bit value raised to `gamma`.
+    lifted: [f32; 256],
+    weights: [f32x8; 3],
+    protect_low: f32x8,
+    protect_span: f32x8,
+}
+
+impl Curve {
+    fn new(params: &FilterParams) -> Self {
+        Self {
+            lifted: array::from_fn(|value| filter::unorm_to_f32(value as u8).powf(params.gamma)),
+            weights: params.luma_weights.map(f32x8::splat),
+            protect_low: f32x8::splat(params.protect_low),
+            protect_span: f32x8::splat(params.protect_high - params.protect_low),
+        }
+    }
+
+    /// Filters packed pixels with red at byte `r` and blue at byte `b` of each.
+    fn apply(&self, pixels: &mut [u8], r: usize, b: usize) {
+        let mut blocks = pixels.chunks_exact_mut(4 * LANES);
+        for block in &mut blocks {
+            self.shade_block(block, r, b);
+        }
+        let rest = blocks.into_remainder();
+        if !rest.is_empty() {
+            let mut block = [0; 4 * LANES];
+            block[..rest.len()].copy_from_slice(rest);
+            self.shade_block(&mut block, r, b);
+            rest.copy_from_slice(&block[..rest.len()]);
+        }
+    }
+
+    fn shade_block(&self, block: &mut [u8], r: usize, b: usize) {
+        let channels = [r, 1, b];
+        let rgb = channels.map(|offset| {
+            f32x8::new(array::from_fn(|i| {
+                filter::unorm_to_f32(block[i * 4 + offset])
+            }))
+        });
+        let lifted = channels.map(|offset| {
+            f32x8::new(array::from_fn(|i| {
+                self.lifted[usize::from(block[i * 4 + offset])]
+            }))
+        });
+        let luma = rgb[0] * self.weights[0] + rgb[1] * self.weights[1] + rgb[2] * self.weights[2];
+        let t = ((luma - self.protect_low) / self.protect_span)
+            .max(f32x8::ZERO)
+            .min(f32x8::ONE);
+        let protect = t * t * (f32x8::splat(3.0) - f32x8::splat(2.0) * t);
+        for ((offset, c), lifted) in channels.into_iter().zip(rgb).zip(lifted) {
+            let out = (lifted + (c - lifted) * protect).to_array();
+            for (i, value) in out.into_iter().enumerate() {
+                block[i * 4 + offset] = filter::f32_to_unorm(value);
+            }
+        }
+        for i in 0..LANES {
+            block[i * 4 + 3] = u8::MAX;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use half::f16;
+
+    use super::*;
+    use crate::lut::{Interpolation, Lut3d};
+
+    /// Every 8-bit colour on a coarse grid plus a run that does not fill the last block.
+    fn pixels(format: PixelFormat) -> Vec<u8> {
+        let steps = (0..=255).step_by(5);
+        let mut channels: Vec<u8> = steps
+            .clone()
+            .flat_map(|r| steps.clone().map(move |g| (r, g)))
+            .flat_map(|(r, g)| steps.clone().flat_map(move |b| [r, g, b, 7]))
+            .collect();
+        channels.extend_from_slice(&[3; 4 * 5]);
+        match format {
+            PixelFormat::Rgba16F => channels
+                .into_iter()
+                .flat_map(|c| f16::from_f32(filter::unorm_to_f32(c)).to_le_bytes())
+                .collect(),
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => channels,
+        }
+    }
+
+    #[test]
+    fn matches_the_reference_exactly() {
+        let params = FilterParams {
+            gamma: 0.45,
+            protect_low: 0.1,
+            protect_high: 0.6,
+            ..FilterParams::default()
+        };
+        let lut = LutStage {
+            lut: Lut3d::identity(9),
+            interpolation: Interpolation::Tetrahedral,
+            replaces_curve: false,
+        };
+        for (format, lut) in [
+            (PixelFormat::Rgba8, None),
+            (PixelFormat::Bgra8, None),
+            (PixelFormat::Bgra8, Some(&lut)),
+            (PixelFormat::Rgba16F, None),
+        ] {
+            let mut expected = pixels(format);
+            let mut actual = expected.clone();
+            filter::apply_graded(&mut expected, format, &params, lut).unwrap();
+            apply_parallel(&mut actual, format, &params, lut).unwrap();
+            assert!(expected == actual, "{format:?} with LUT {}", lut.is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_partial_pixels() {
+        let params = FilterParams::default();
+        assert!(apply_parallel(&mut [0; 6], PixelFormat::Bgra8, &params, None).is_err());
+    }
+}
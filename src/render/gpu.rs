@@ -0,0 +1,667 @@
+//! The filter on wgpu: each frame is uploaded, drawn through `shader.wgsl` into a target
+//! of the same size and format, and read back.
+//!
+//! Read-backs are double-buffered: a frame is copied into one buffer while the previous
+//! one is still being mapped, and the device is only polled, so the overlay never waits
+//! for the GPU unless it falls two frames behind or there is no newer frame.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+
+use anyhow::Context;
+use half::f16;
+
+use super::{Filtered, FrameRenderer};
+use crate::filter::PixelFormat;
+use crate::lut::LutStage;
+use crate::params::{FilterParams, ShaderParams};
+use crate::source::Frame;
+
+pub struct GpuRenderer {
+    adapter: wgpu::AdapterInfo,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    /// Bound while no LUT is, since the layout always has one.
+    blank_lut: wgpu::TextureView,
+    lut: Option<(Arc<LutStage>, wgpu::TextureView)>,
+    targets: Option<Targets>,
+    /// Refers to the current LUT and targets; rebuilt when either changes.
+    bind_group: Option<wgpu::BindGroup>,
+    /// The last frame read back, without the row padding copies need.
+    pixels: Vec<u8>,
+    /// The size of `pixels` while they have not been handed out yet.
+    unread: Option<(u32, u32)>,
+}
+
+/// Read-back buffers per frame size; more means more frames on the GPU at once.
+const READBACK_COUNT: usize = 2;
+
+/// Everything sized for one frame size and format.
+struct Targets {
+    size: (u32, u32),
+    format: PixelFormat,
+    pipeline: wgpu::RenderPipeline,
+    frame: wgpu::Texture,
+    output: wgpu::Texture,
+    /// Read-back buffers nothing is copied into.
+    free: Vec<wgpu::Buffer>,
+    /// Buffers holding a copy, oldest first, each with the result of its mapping.
+    in_flight: VecDeque<(wgpu::Buffer, Receiver<Result<(), wgpu::BufferAsyncError>>)>,
+    /// Row pitch of the read-back buffers, a multiple of
+    /// [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+    padded_row: u32,
+}
+
+impl GpuRenderer {
+    /// Opens the best adapter there is: a discrete GPU over an integrated one, and a
+    /// software one such as lavapipe or llvmpipe only if `allow_software`.
+    /// `WGPU_BACKEND` narrows the backends considered, e.g. to `vulkan`.
+    pub fn new(allow_software: bool) -> anyhow::Result<Self> {
+        let desc = wgpu::InstanceDescriptor::new_without_display_handle_from_env();
+        let backends = desc.backends;
+        let instance = wgpu::Instance::new(desc);
+        let adapter = pollster::block_on(instance.enumerate_adapters(backends))
+            .into_iter()
+            .filter(|adapter| {
+                allow_software || adapter.get_info().device_type != wgpu::DeviceType::Cpu
+            })
+            .min_by_key(|adapter| match adapter.get_info().device_type {
+                wgpu::DeviceType::DiscreteGpu => 0,
+                wgpu::DeviceType::IntegratedGpu => 1,
+                wgpu::DeviceType::VirtualGpu => 2,
+                wgpu::DeviceType::Other => 3,
+                wgpu::DeviceType::Cpu => 4,
+            })
+            .context(if allow_software {
+                "No Vulkan, GL or Metal adapter found"
+            } else {
+                "No hardware Vulkan, GL or Metal adapter found"
+            })?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("ban-shadow"),
+            // Frames can be larger than the downlevel defaults allow.
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .context("Failed to open the wgpu device")?;
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shader.wgsl"));
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("filter"),
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(2, wgpu::TextureViewDimension::D3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<ShaderParams>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("filter"),
+            bind_group_layouts: &[Some(&layout)],
+            immediate_size: 0,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("filter"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let params = create_params_buffer(&device);
+        let blank_lut = create_lut_texture(&device, &queue, 1, &[0; 8]);
+        Ok(Self {
+            adapter: adapter.get_info(),
+            device,
+            queue,
+            shader,
+            layout,
+            pipeline_layout,
+            sampler,
+            params,
+            blank_lut,
+            lut: None,
+            targets: None,
+            bind_group: None,
+            pixels: Vec::new(),
+            unread: None,
+        })
+    }
+
+    /// Makes sure the targets fit `frame` and `lut` is uploaded, dropping the bind group
+    /// if either changed.
+    fn prepare(&mut self, frame: &Frame, lut: Option<&Arc<LutStage>>) {
+        let size = (frame.width, frame.height);
+        if self
+            .targets
+            .as_ref()
+            .is_none_or(|targets| targets.size != size || targets.format != frame.format)
+        {
+            self.targets = Some(self.create_targets(size, frame.format));
+            self.bind_group = None;
+            self.unread = None;
+        }
+        let current = self.lut.as_ref().map(|(stage, _)| stage);
+        let unchanged = match (current, lut) {
+            (Some(current), Some(lut)) => Arc::ptr_eq(current, lut),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            self.lut = lut.map(|stage| {
+                let texels: Vec<u8> = stage
+                    .lut
+                    .table()
+                    .iter()
+                    .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(f16::from_f32))
+                    .flat_map(f16::to_le_bytes)
+                    .collect();
+                let size = stage.lut.size() as u32;
+                let view = create_lut_texture(&self.device, &self.queue, size, &texels);
+                (stage.clone(), view)
+            });
+            self.bind_group = None;
+        }
+    }
+
+    fn create_targets(&self, size: (u32, u32), format: PixelFormat) -> Targets {
+        let texture_format = texture_format(format);
+        let pipeline = self
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("filter"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("ps_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(texture_format.into())],
+                }),
+                multiview_mask: None,
+                cache: None,
+            });
+        let texture = |label, usage| {
+            self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture_format,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let frame = texture(
+            "frame",
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        );
+        let output = texture(
+            "filtered frame",
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
+        let row = size.0 * format.bytes_per_pixel() as u32;
+        let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let free = (0..READBACK_COUNT)
+            .map(|_| {
+                self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("readback"),
+                    size: u64::from(padded_row) * u64::from(size.1),
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        Targets {
+            size,
+            format,
+            pipeline,
+            frame,
+            output,
+            free,
+            in_flight: VecDeque::new(),
+            padded_row,
+        }
+    }
+
+    /// Copies every read-back whose mapping finished, oldest first, into `pixels` and
+    /// frees its buffer, so `pixels` ends up with the newest. Waits for all of them if
+    /// `wait`, and otherwise only polls.
+    fn read_back(&mut self, wait: bool) -> anyhow::Result<()> {
+        let Some(targets) = &mut self.targets else {
+            return Ok(());
+        };
+        if targets.in_flight.is_empty() {
+            return Ok(());
+        }
+        let poll = if wait {
+            wgpu::PollType::wait_indefinitely()
+        } else {
+            wgpu::PollType::Poll
+        };
+        self.device
+            .poll(poll)
+            .context("The GPU stopped responding")?;
+        let row = targets.size.0 as usize * targets.format.bytes_per_pixel();
+        while let Some((buffer, mapped)) = targets.in_flight.front() {
+            let Ok(result) = mapped.try_recv() else {
+                break;
+            };
+            result.context("Failed to read the filtered frame back")?;
+            {
+                let mapped = buffer.get_mapped_range(..)?;
+                self.pixels.clear();
+                for padded in mapped.chunks_exact(targets.padded_row as usize) {
+                    self.pixels.extend_from_slice(&padded[..row]);
+                }
+            }
+            buffer.unmap();
+            let (buffer, _) = targets.in_flight.pop_front().expect("checked above");
+            targets.free.push(buffer);
+            self.unread = Some(targets.size);
+        }
+        Ok(())
+    }
+
+    /// The newest frame read back, unless it was handed out already.
+    fn take_unread(&mut self) -> Option<Filtered<'_>> {
+        let size = self.unread.take()?;
+        Some(Filtered {
+            pixels: &self.pixels,
+            size,
+        })
+    }
+
+    fn bind_group(&mut self) -> &wgpu::BindGroup {
+        let targets = self.targets.as_ref().expect("prepared before binding");
+        let lut = self.lut.as_ref().map_or(&self.blank_lut, |(_, view)| view);
+        self.bind_group.get_or_insert_with(|| {
+            let frame = targets.frame.create_view(&Default::default());
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("filter"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&frame),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(lut),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.params.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+}
+
+impl FrameRenderer for GpuRenderer {
+    fn render(
+        &mut self,
+        frame: &Frame,
+        params: &FilterParams,
+        lut: Option<&Arc<LutStage>>,
+    ) -> anyhow::Result<Option<Filtered<'_>>> {
+        let Some(pixels) = frame.pixels.as_cpu() else {
+            anyhow::bail!("The wgpu renderer needs frames in CPU memory");
+        };
+        let row = frame.width as usize * frame.format.bytes_per_pixel();
+        if pixels.len() != row * frame.height as usize {
+            anyhow::bail!(
+                "A {}x{} frame cannot hold {} bytes",
+                frame.width,
+                frame.height,
+                pixels.len()
+            );
+        }
+        self.prepare(frame, lut);
+        // Every buffer still holds a frame, so the GPU is behind; catch up first.
+        if self
+            .targets
+            .as_ref()
+            .is_some_and(|targets| targets.free.is_empty())
+        {
+            self.read_back(true)?;
+        }
+        let shader_params = match lut {
+            Some(stage) => params.to_shader().with_lut(stage),
+            None => params.to_shader(),
+        };
+        self.queue
+            .write_buffer(&self.params, 0, shader_params.as_bytes());
+        let bind_group = self.bind_group().clone();
+        let targets = self.targets.as_mut().expect("prepared above");
+        let buffer = targets.free.pop().expect("read back above");
+        let extent = wgpu::Extent3d {
+            width: frame.width,
+            height: frame.height,
+            depth_or_array_layers: 1,
+        };
+        self.queue.write_texture(
+            targets.frame.as_image_copy(),
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(row as u32),
+                rows_per_image: None,
+            },
+            extent,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let output = targets.output.create_view(&Default::default());
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("filter"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(&targets.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            targets.output.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(targets.padded_row),
+                    rows_per_image: None,
+                },
+            },
+            extent,
+        );
+        self.queue.submit([encoder.finish()]);
+        let (sender, receiver) = mpsc::channel();
+        buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        targets.in_flight.push_back((buffer, receiver));
+        self.read_back(false)?;
+        Ok(self.take_unread())
+    }
+
+    fn pending(&self) -> bool {
+        self.targets
+            .as_ref()
+            .is_some_and(|targets| !targets.in_flight.is_empty())
+    }
+
+    fn finished(&mut self, wait: bool) -> anyhow::Result<Option<Filtered<'_>>> {
+        self.read_back(wait)?;
+        Ok(self.take_unread())
+    }
+
+    fn another(&self) -> Box<dyn FrameRenderer> {
+        Box::new(Self {
+            adapter: self.adapter.clone(),
+            device: self.device.clone(),
+            queue: self.queue.clone(),
+            shader: self.shader.clone(),
+            layout: self.layout.clone(),
+            pipeline_layout: self.pipeline_layout.clone(),
+            sampler: self.sampler.clone(),
+            params: create_params_buffer(&self.device),
+            blank_lut: self.blank_lut.clone(),
+            lut: None,
+            targets: None,
+            bind_group: None,
+            pixels: Vec::new(),
+            unread: None,
+        })
+    }
+
+    fn description(&self) -> String {
+        format!("{} ({:?}, wgpu)", self.adapter.name, self.adapter.backend)
+    }
+}
+
+fn texture_format(format: PixelFormat) -> wgpu::TextureFormat {
+    match format {
+        PixelFormat::Rgba8 => wgpu::TextureFormat::Rgba8Unorm,
+        PixelFormat::Bgra8 => wgpu::TextureFormat::Bgra8Unorm,
+        PixelFormat::Rgba16F => wgpu::TextureFormat::Rgba16Float,
+    }
+}
+
+fn create_params_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("filter params"),
+        size: size_of::<ShaderParams>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Uploads a `size`³ table of half-float RGBA texels, red changing fastest.
+fn create_lut_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    size: u32,
+    texels: &[u8],
+) -> wgpu::TextureView {
+    let texel_bytes = size_of::<[f16; 4]>() as u32;
+    let extent = wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: size,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("lut"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        texels,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size * texel_bytes),
+            rows_per_image: Some(size),
+        },
+        extent,
+    );
+    texture.create_view(&Default::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter;
+    use crate::lut::{Interpolation, Lut3d};
+    use crate::source::FramePixels;
+
+    /// Any adapter will do, a software one such as lavapipe from `mesa-vulkan-drivers`
+    /// included; the tests using it are ignored by default since CI images may have none.
+    fn renderer() -> GpuRenderer {
+        GpuRenderer::new(true).unwrap()
+    }
+
+    /// Every 8-bit grey plus a spread of colours, in BGRA.
+    fn pattern() -> Frame {
+        let (width, height) = (64, 16);
+        let pixels = (0..width * height)
+            .flat_map(|i| {
+                let v = (i % 256) as u8;
+                let row = (i / width) as u8;
+                [v, v.wrapping_mul(row | 1), v.wrapping_add(row * 16), 0]
+            })
+            .collect();
+        Frame {
+            id: 1,
+            width,
+            height,
+            format: PixelFormat::Bgra8,
+            pixels: FramePixels::Cpu(pixels),
+        }
+    }
+
+    /// Checks the GPU output against [`filter::apply_graded`], allowing for the last bit
+    /// of rounding and the GPU's lower-precision `pow`.
+    fn assert_matches_reference(
+        renderer: &mut GpuRenderer,
+        frame: &Frame,
+        params: &FilterParams,
+        lut: Option<&Arc<LutStage>>,
+    ) {
+        let mut expected = frame.pixels.as_cpu().unwrap().to_vec();
+        filter::apply_graded(&mut expected, frame.format, params, lut.map(Arc::as_ref)).unwrap();
+        let actual = match renderer.render(frame, params, lut).unwrap() {
+            Some(filtered) => filtered.pixels.to_vec(),
+            None => renderer.finished(true).unwrap().unwrap().pixels.to_vec(),
+        };
+        assert_eq!(actual.len(), expected.len());
+        for (i, (&actual, &expected)) in actual.iter().zip(&expected).enumerate() {
+            assert!(
+                actual.abs_diff(expected) <= 2,
+                "byte {i}: {actual} on the GPU, {expected} on the CPU"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter (lavapipe/llvmpipe)"]
+    fn matches_the_cpu_reference() {
+        let mut renderer = renderer();
+        let frame = pattern();
+        assert_matches_reference(&mut renderer, &frame, &FilterParams::DEFAULT, None);
+        let strong = FilterParams {
+            gamma: 0.4,
+            protect_high: 0.6,
+            ..FilterParams::DEFAULT
+        };
+        assert_matches_reference(&mut renderer, &frame, &strong, None);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter (lavapipe/llvmpipe)"]
+    fn matches_the_cpu_reference_with_luts() {
+        let mut renderer = renderer();
+        let frame = pattern();
+        // Inverts red and halves blue, so a missed lookup shows.
+        let table: String = Lut3d::identity(9)
+            .table()
+            .iter()
+            .map(|[r, g, b]| format!("{} {g} {}\n", 1.0 - r, b * 0.5))
+            .collect();
+        let lut: Lut3d = format!("LUT_3D_SIZE 9\n{table}").parse().unwrap();
+        for interpolation in [Interpolation::Trilinear, Interpolation::Tetrahedral] {
+            for replaces_curve in [false, true] {
+                let stage = Arc::new(LutStage {
+                    lut: lut.clone(),
+                    interpolation,
+                    replaces_curve,
+                });
+                assert_matches_reference(
+                    &mut renderer,
+                    &frame,
+                    &FilterParams::DEFAULT,
+                    Some(&stage),
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter (lavapipe/llvmpipe)"]
+    fn waits_for_the_gpu_when_every_readback_is_busy() {
+        let mut renderer = renderer();
+        let frame = pattern();
+        let mut handed_out = 0;
+        for _ in 0..READBACK_COUNT + 1 {
+            let filtered = renderer
+                .render(&frame, &FilterParams::DEFAULT, None)
+                .unwrap();
+            handed_out += usize::from(filtered.is_some());
+        }
+        assert!(handed_out >= 1);
+        if let Some(last) = renderer.finished(true).unwrap() {
+            assert_eq!(last.size, (frame.width, frame.height));
+        }
+        assert!(!renderer.pending());
+        assert!(renderer.finished(true).unwrap().is_none());
+    }
+
+    #[test]
+    fn shader_matches_the_uniform_layout() {
+        let module = naga::front::wgsl::parse_str(include_str!("../shader.wgsl")).unwrap();
+        naga::valid::Validator::new(Default::default(), Default::default())
+            .validate(&module)
+            .unwrap();
+        let params = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some("FilterParams"))
+            .unwrap()
+            .1;
+        let naga::TypeInner::Struct { span, .. } = params.inner else {
+            panic!("FilterParams is not a struct");
+        };
+        assert_eq!(span as usize, size_of::<ShaderParams>());
+    }
+}
@@ -1,10 +1,8 @@
-Texture2D t_diffuse : register(t0);
-SamplerState s_diffuse : register(s0);
-
-struct VSOut {
-    float4 pos : SV_POSITION;
-    float2 uv : TEXCOORD0;
-};
+// Mirror `FLASH_KNEE` and `FLASH_CUT` in flash.rs.
+static const float FLASH_KNEE = 0.5;
+static const float FLASH_CUT = 0.6;
+// Mirrors `SAFETY_CUT` in flash_safety.rs.
+static const float SAFETY_CUT = 0.75;
 
 VSOut vs_main(uint id : SV_VertexID) {
     float2 uv = float2((id << 1) & 2, id & 2);
@@ -14,11 +12,79 @@ VSOut vs_main(uint id : SV_VertexID) {
     return o;
 }
 
+float3 lut_at(int3 base, int3 offset) {
+    return t_lut.Load(int4(base + offset, 0)).rgb;
+}
+
+// Mirrors `Lut3d::sample` in lut.rs.
+float3 apply_lut(float3 rgb) {
+    float last = lut_size - 1.0;
+    float3 coords = saturate((rgb - lut_domain_min) / (lut_domain_max - lut_domain_min)) * last;
+    if (lut_mode == LUT_TRILINEAR) {
+        // Texel centres sit half a texel in from the edges.
+        return t_lut.SampleLevel(s_diffuse, (coords + 0.5) / lut_size, 0).rgb;
+    }
+
+    float3 base = min(floor(coords), last - 1.0);
+    float3 f = coords - base;
+    int3 b = int3(base);
+    float3 c1;
+    float3 c2;
+    float4 weights;
+    if (f.r > f.g) {
+        if (f.g > f.b) {
+            c1 = lut_at(b, int3(1, 0, 0));
+            c2 = lut_at(b, int3(1, 1, 0));
+            weights = float4(1.0 - f.r, f.r - f.g, f.g - f.b, f.b);
+        } else if (f.r > f.b) {
+            c1 = lut_at(b, int3(1, 0, 0));
+            c2 = lut_at(b, int3(1, 0, 1));
+            weights = float4(1.0 - f.r, f.r - f.b, f.b - f.g, f.g);
+        } else {
+            c1 = lut_at(b, int3(0, 0, 1));
+            c2 = lut_at(b, int3(1, 0, 1));
+            weights = float4(1.0 - f.b, f.b - f.r, f.r - f.g, f.g);
+        }
+    } else if (f.b > f.g) {
+        c1 = lut_at(b, int3(0, 0, 1));
+        c2 = lut_at(b, int3(0, 1, 1));
+        weights = float4(1.0 - f.b, f.b - f.g, f.g - f.r, f.r);
+    } else if (f.b > f.r) {
+        c1 = lut_at(b, int3(0, 1, 0));
+        c2 = lut_at(b, int3(0, 1, 1));
+        weights = float4(1.0 - f.g, f.g - f.b, f.b - f.r, f.r);
+    } else {
+        c1 = lut_at(b, int3(0, 1, 0));
+        c2 = lut_at(b, int3(1, 1, 0));
+        weights = float4(1.0 - f.g, f.g - f.r, f.r - f.b, f.b);
+    }
+    return weights.x * lut_at(b, int3(0, 0, 0)) + weights.y * c1 + weights.z * c2
+        + weights.w * lut_at(b, int3(1, 1, 1));
+}
+
 float4 ps_main(VSOut input) : SV_Target {
     float4 color = t_diffuse.Sample(s_diffuse, input.uv);
-    float luma = dot(color.rgb, float3(0.2126, 0.7152, 0.0722));
-    float3 lifted = pow(color.rgb, 0.75);
-    float protect = smoothstep(0.05, 0.3, luma);
+    float luma = dot(color.rgb, luma_weights);
+    // A flash fades the lift out and darkens the highlights.
+    float3 lifted = pow(color.rgb, lerp(gamma, 1.0, flash_damping));
+    float protect = smoothstep(protect_low, protect_high, luma);
     float3 finalRgb = lerp(lifted, color.rgb, protect);
+    if (lut_mode != LUT_OFF) {
+        finalRgb = apply_lut(lut_replaces_curve != 0 ? color.rgb : finalRgb);
+    }
+    finalRgb *= 1.0 - flash_damping * FLASH_CUT * smoothstep(FLASH_KNEE, 1.0, luma);
+    // Photosensitive safety pulls everything towards the recent average brightness.
+    finalRgb = lerp(finalRgb, safety_level, safety * SAFETY_CUT);
     return float4(finalRgb, 1.0);
 }
+
+// Luma only, drawn into the small R8 target adaptive exposure measures.
+float4 ps_luma(VSOut input) : SV_Target {
+    float4 color = t_diffuse.Sample(s_diffuse, input.uv);
+    return float4(dot(color.rgb, luma_weights), 0.0, 0.0, 1.0);
+}
+
+// Frames the CPU renderer already filtered, drawn into the viewport as they are.
+float4 ps_copy(VSOut input) : SV_Target {
+    return float4(t_diffuse.Sample(s_diffuse, input.uv).rgb, 1.0);
+}
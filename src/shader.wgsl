@@ -0,0 +1,119 @@
+// WGSL port of `vs_main` and `ps_main` in shader.hlsl for the wgpu renderer; keep the
+// two in step.
+
+@group(0) @binding(0) var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1) var s_diffuse: sampler;
+// Red along x, green along y, blue along z.
+@group(0) @binding(2) var t_lut: texture_3d<f32>;
+@group(0) @binding(3) var<uniform> params: FilterParams;
+
+// Mirrors `ShaderParams` in params.rs.
+struct FilterParams {
+    luma_weights: vec3<f32>,
+    gamma: f32,
+    protect_low: f32,
+    protect_high: f32,
+    flash_damping: f32,
+    safety: f32,
+    safety_level: f32,
+    lut_mode: u32,
+    lut_size: f32,
+    lut_replaces_curve: u32,
+    lut_domain_min: vec3<f32>,
+    _padding0: f32,
+    lut_domain_max: vec3<f32>,
+    _padding1: f32,
+}
+
+// Mirror `LUT_OFF`, `LUT_TRILINEAR` and `LUT_TETRAHEDRAL` in params.rs.
+const LUT_OFF: u32 = 0u;
+const LUT_TRILINEAR: u32 = 1u;
+const LUT_TETRAHEDRAL: u32 = 2u;
+
+// Mirror `FLASH_KNEE` and `FLASH_CUT` in flash.rs.
+const FLASH_KNEE: f32 = 0.5;
+const FLASH_CUT: f32 = 0.6;
+// Mirrors `SAFETY_CUT` in flash_safety.rs.
+const SAFETY_CUT: f32 = 0.75;
+
+struct VSOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VSOut {
+    let uv = vec2<f32>(f32((id << 1u) & 2u), f32(id & 2u));
+    var o: VSOut;
+    o.uv = uv;
+    o.pos = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return o;
+}
+
+fn lut_at(base: vec3<i32>, offset: vec3<i32>) -> vec3<f32> {
+    return textureLoad(t_lut, base + offset, 0).rgb;
+}
+
+// Mirrors `Lut3d::sample` in lut.rs.
+fn apply_lut(rgb: vec3<f32>) -> vec3<f32> {
+    let last = params.lut_size - 1.0;
+    let span = params.lut_domain_max - params.lut_domain_min;
+    let coords = saturate((rgb - params.lut_domain_min) / span) * last;
+    if params.lut_mode == LUT_TRILINEAR {
+        // Texel centres sit half a texel in from the edges.
+        return textureSampleLevel(t_lut, s_diffuse, (coords + 0.5) / params.lut_size, 0.0).rgb;
+    }
+
+    let base = min(floor(coords), vec3<f32>(last - 1.0));
+    let f = coords - base;
+    let b = vec3<i32>(base);
+    var c1: vec3<f32>;
+    var c2: vec3<f32>;
+    var weights: vec4<f32>;
+    if f.r > f.g {
+        if f.g > f.b {
+            c1 = lut_at(b, vec3<i32>(1, 0, 0));
+            c2 = lut_at(b, vec3<i32>(1, 1, 0));
+            weights = vec4<f32>(1.0 - f.r, f.r - f.g, f.g - f.b, f.b);
+        } else if f.r > f.b {
+            c1 = lut_at(b, vec3<i32>(1, 0, 0));
+            c2 = lut_at(b, vec3<i32>(1, 0, 1));
+            weights = vec4<f32>(1.0 - f.r, f.r - f.b, f.b - f.g, f.g);
+        } else {
+            c1 = lut_at(b, vec3<i32>(0, 0, 1));
+            c2 = lut_at(b, vec3<i32>(1, 0, 1));
+            weights = vec4<f32>(1.0 - f.b, f.b - f.r, f.r - f.g, f.g);
+        }
+    } else if f.b > f.g {
+        c1 = lut_at(b, vec3<i32>(0, 0, 1));
+        c2 = lut_at(b, vec3<i32>(0, 1, 1));
+        weights = vec4<f32>(1.0 - f.b, f.b - f.g, f.g - f.r, f.r);
+    } else if f.b > f.r {
+        c1 = lut_at(b, vec3<i32>(0, 1, 0));
+        c2 = lut_at(b, vec3<i32>(0, 1, 1));
+        weights = vec4<f32>(1.0 - f.g, f.g - f.b, f.b - f.r, f.r);
+    } else {
+        c1 = lut_at(b, vec3<i32>(0, 1, 0));
+        c2 = lut_at(b, vec3<i32>(1, 1, 0));
+        weights = vec4<f32>(1.0 - f.g, f.g - f.r, f.r - f.b, f.b);
+    }
+    return weights.x * lut_at(b, vec3<i32>(0, 0, 0)) + weights.y * c1 + weights.z * c2
+        + weights.w * lut_at(b, vec3<i32>(1, 1, 1));
+}
+
+@fragment
+fn ps_main(input: VSOut) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, input.uv);
+    let luma = dot(color.rgb, params.luma_weights);
+    // A flash fades the lift out and darkens the highlights.
+    let lifted = pow(color.rgb, vec3<f32>(mix(params.gamma, 1.0, params.flash_damping)));
+    let protect = smoothstep(params.protect_low, params.protect_high, luma);
+    var final_rgb = mix(lifted, color.rgb, protect);
+    if params.lut_mode != LUT_OFF {
+        final_rgb = apply_lut(select(final_rgb, color.rgb, params.lut_replaces_curve != 0u));
+    }
+    final_rgb *= 1.0 - params.flash_damping * FLASH_CUT * smoothstep(FLASH_KNEE, 1.0, luma);
+    // Photosensitive safety pulls everything towards the recent average brightness.
+    final_rgb = mix(final_rgb, vec3<f32>(params.safety_level), params.safety * SAFETY_CUT);
+    return vec4<f32>(final_rgb, 1.0);
+}
@@ -0,0 +1,38 @@
+// What the renderer binds for a pixel shader; custom shaders are compiled after this
+// file and define `float4 ps_main(VSOut input) : SV_Target`.
+
+Texture2D t_diffuse : register(t0);
+SamplerState s_diffuse : register(s0);
+// Red along x, green along y, blue along z.
+Texture3D t_lut : register(t1);
+// The captured frame, unfiltered; the same as `t_diffuse` in the first pass.
+Texture2D t_frame : register(t2);
+
+// Mirrors `ShaderParams` in params.rs.
+cbuffer FilterParams : register(b0) {
+    float3 luma_weights;
+    float gamma;
+    float protect_low;
+    float protect_high;
+    float flash_damping;
+    float safety;
+    float safety_level;
+    uint lut_mode;
+    float lut_size;
+    uint lut_replaces_curve;
+    float3 lut_domain_min;
+    float _padding0;
+    float3 lut_domain_max;
+    float _padding1;
+};
+
+// Mirror `LUT_OFF`, `LUT_TRILINEAR` and `LUT_TETRAHEDRAL` in params.rs.
+static const uint LUT_OFF = 0;
+static const uint LUT_TRILINEAR = 1;
+static const uint LUT_TETRAHEDRAL = 2;
+
+struct VSOut {
+    float4 pos : SV_POSITION;
+    float2 uv : TEXCOORD0;
+};
+
@@ -0,0 +1,94 @@
+//! Frame sources that feed the filter pipeline.
+//!
+//! The overlay pulls frames through [`FrameSource`], so the live Windows capture can be
+//! swapped for a generated pattern or a folder of screenshots on any platform.
+
+mod cpu_frames;
+mod sequence;
+mod synthetic;
+
+pub use cpu_frames::{CpuFrame, CpuFrameSource, CpuFrames};
+pub use sequence::ImageSequenceSource;
+pub use synthetic::{Pattern, SyntheticSource};
+
+use half::f16;
+
+#[cfg(target_os = "windows")]
+use crate::capture::SharedFrame;
+use crate::filter::{self, PixelFormat};
+
+pub struct Frame {
+    /// Increases by one for every frame a source yields, starting at 1.
+    pub id: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub pixels: FramePixels,
+}
+
+pub enum FramePixels {
+    /// Tightly packed rows in `Frame::format`.
+    Cpu(Vec<u8>),
+    /// A slot of a [`CpuFrames`] mailbox, tightly packed BGRA, reserved until dropped.
+    CpuSlot(CpuFrame),
+    /// A slot of the capture thread's keyed-mutex texture ring, reserved until dropped.
+    #[cfg(target_os = "windows")]
+    Shared(SharedFrame),
+}
+
+impl FramePixels {
+    /// The pixel bytes, if the frame lives in CPU memory.
+    pub fn as_cpu(&self) -> Option<&[u8]> {
+        match self {
+            Self::Cpu(pixels) => Some(pixels),
+            Self::CpuSlot(frame) => Some(frame.pixels()),
+            #[cfg(target_os = "windows")]
+            Self::Shared(_) => None,
+        }
+    }
+}
+
+pub trait FrameSource {
+    /// Returns the next frame, or `None` when nothing new is available.
+    ///
+    /// Live sources return `None` until another frame arrives; finite sources return
+    /// `None` for good once they are exhausted.
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>>;
+
+    /// Returns the most recent frame again, e.g. to repaint after a resize.
+    ///
+    /// Sources that cannot hand out a frame twice return `None`.
+    fn repeat_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        Ok(None)
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        (**self).next_frame()
+    }
+
+    fn repeat_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        (**self).repeat_frame()
+    }
+}
+
+/// Writes one linear RGB value into `out` using the layout of `format`, alpha opaque.
+fn encode_pixel(format: PixelFormat, rgb: [f32; 3], out: &mut [u8]) {
+    match format {
+        PixelFormat::Rgba8 | PixelFormat::Bgra8 => {
+            let [r, g, b] = rgb.map(filter::f32_to_unorm);
+            let rgba = if format == PixelFormat::Rgba8 {
+                [r, g, b, u8::MAX]
+            } else {
+                [b, g, r, u8::MAX]
+            };
+            out.copy_from_slice(&rgba);
+        }
+        PixelFormat::Rgba16F => {
+            for (i, value) in rgb.into_iter().chain([1.0]).enumerate() {
+                out[i * 2..i * 2 + 2].copy_from_slice(&f16::from_f32(value).to_le_bytes());
+            }
+        }
+    }
+}
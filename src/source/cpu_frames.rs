@@ -0,0 +1,176 @@
+use std::sync::{Arc, Mutex};
+
+use super::{Frame, FramePixels, FrameSource};
+use crate::filter::PixelFormat;
+use crate::mailbox::{Mailbox, MailboxStats, ReadSlot};
+
+struct Slot {
+    pixels: Vec<u8>,
+    size: (u32, u32),
+}
+
+struct Shared {
+    mailbox: Mailbox,
+    slots: Vec<Slot>,
+}
+
+/// BGRA frames in flight between a capture thread and the renderer, for frontends that
+/// capture into CPU memory. Clones share the same slots.
+#[derive(Clone)]
+pub struct CpuFrames {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Default for CpuFrames {
+    fn default() -> Self {
+        let mailbox = Mailbox::default();
+        let slots = (0..mailbox.slot_count())
+            .map(|_| Slot {
+                pixels: Vec::new(),
+                size: (0, 0),
+            })
+            .collect();
+        Self {
+            shared: Arc::new(Mutex::new(Shared { mailbox, slots })),
+        }
+    }
+}
+
+impl CpuFrames {
+    /// Swaps `pixels` into a free slot and publishes it, handing the slot's old buffer
+    /// back to reuse for the next capture.
+    pub fn publish(&self, pixels: &mut Vec<u8>, size: (u32, u32)) {
+        let mut shared = self.shared.lock().unwrap();
+        let Some(slot) = shared.mailbox.begin_write() else {
+            return;
+        };
+        std::mem::swap(&mut shared.slots[slot].pixels, pixels);
+        shared.slots[slot].size = size;
+        shared.mailbox.publish(slot);
+    }
+
+    pub fn stats(&self) -> MailboxStats {
+        self.shared.lock().unwrap().mailbox.stats()
+    }
+
+    /// The renderer's end.
+    pub fn source(&self) -> CpuFrameSource {
+        CpuFrameSource {
+            frames: self.clone(),
+        }
+    }
+}
+
+/// Yields the newest frame published to a [`CpuFrames`].
+pub struct CpuFrameSource {
+    frames: CpuFrames,
+}
+
+impl CpuFrameSource {
+    pub fn stats(&self) -> MailboxStats {
+        self.frames.stats()
+    }
+
+    fn read(&mut self, reread: bool) -> Option<Frame> {
+        let mut shared = self.frames.shared.lock().unwrap();
+        let read = if reread {
+            shared.mailbox.begin_reread()?
+        } else {
+            shared.mailbox.begin_read()?
+        };
+        let slot = &mut shared.slots[read.slot];
+        Some(Frame {
+            id: read.frame_id,
+            width: slot.size.0,
+            height: slot.size.1,
+            format: PixelFormat::Bgra8,
+            pixels: FramePixels::CpuSlot(CpuFrame {
+                pixels: std::mem::take(&mut slot.pixels),
+                frames: self.frames.clone(),
+                read,
+            }),
+        })
+    }
+}
+
+/// The pixels of a published slot, taken out of [`CpuFrames`] while the renderer reads
+/// them. Dropping it puts them back and frees the slot.
+pub struct CpuFrame {
+    pixels: Vec<u8>,
+    frames: CpuFrames,
+    read: ReadSlot,
+}
+
+impl CpuFrame {
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl Drop for CpuFrame {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.frames.shared.lock() {
+            shared.slots[self.read.slot].pixels = std::mem::take(&mut self.pixels);
+            shared.mailbox.end_read(self.read);
+        }
+    }
+}
+
+impl FrameSource for CpuFrameSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        Ok(self.read(false))
+    }
+
+    fn repeat_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        Ok(self.read(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_over_the_newest_frame_once() {
+        let frames = CpuFrames::default();
+        let mut source = frames.source();
+        assert!(source.next_frame().unwrap().is_none());
+
+        frames.publish(&mut vec![1; 8], (2, 1));
+        frames.publish(&mut vec![2; 8], (2, 1));
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.pixels.as_cpu().unwrap(), [2; 8]);
+        assert!(source.next_frame().unwrap().is_none());
+        let id = frame.id;
+        drop(frame);
+        let repeated = source.repeat_frame().unwrap().unwrap();
+        assert_eq!(repeated.id, id);
+        assert_eq!(repeated.pixels.as_cpu().unwrap(), [2; 8]);
+        assert_eq!(source.stats().dropped, 1);
+    }
+
+    #[test]
+    fn keeps_the_slot_until_the_frame_is_dropped() {
+        let frames = CpuFrames::default();
+        let mut source = frames.source();
+        frames.publish(&mut vec![1; 4], (1, 1));
+        let frame = source.next_frame().unwrap().unwrap();
+        for value in 2..6 {
+            frames.publish(&mut vec![value; 4], (1, 1));
+        }
+        assert_eq!(frame.pixels.as_cpu().unwrap(), [1; 4]);
+        assert!(source.next_frame().unwrap().is_none());
+        drop(frame);
+        assert_eq!(
+            source
+                .next_frame()
+                .unwrap()
+                .unwrap()
+                .pixels
+                .as_cpu()
+                .unwrap(),
+            [5; 4]
+        );
+    }
+}
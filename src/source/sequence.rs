@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::filter::PixelFormat;
+use crate::source::{Frame, FramePixels, FrameSource};
+
+/// Plays back image files from disk as RGBA8 frames.
+pub struct ImageSequenceSource {
+    paths: Vec<PathBuf>,
+    next_index: usize,
+    looping: bool,
+    frame_id: u64,
+}
+
+impl ImageSequenceSource {
+    pub fn new(paths: Vec<PathBuf>, looping: bool) -> Self {
+        Self {
+            paths,
+            next_index: 0,
+            looping,
+            frame_id: 0,
+        }
+    }
+
+    /// Collects the PNG files in `dir`, ordered by file name.
+    pub fn from_dir(dir: &Path, looping: bool) -> anyhow::Result<Self> {
+        let mut paths = Vec::new();
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let is_png = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+            if is_png {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            anyhow::bail!("No PNG files in {}", dir.display());
+        }
+        paths.sort();
+        Ok(Self::new(paths, looping))
+    }
+}
+
+impl FrameSource for ImageSequenceSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        if self.next_index == self.paths.len() {
+            if !self.looping || self.paths.is_empty() {
+                return Ok(None);
+            }
+            self.next_index = 0;
+        }
+        let path = &self.paths[self.next_index];
+        let image = image::open(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .into_rgba8();
+        self.next_index += 1;
+        self.frame_id += 1;
+        Ok(Some(Frame {
+            id: self.frame_id,
+            width: image.width(),
+            height: image.height(),
+            format: PixelFormat::Rgba8,
+            pixels: FramePixels::Cpu(image.into_raw()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ban-shadow-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_png(dir: &Path, name: &str, width: u32, height: u32) {
+        image::RgbaImage::new(width, height)
+            .save(dir.join(name))
+            .unwrap();
+    }
+
+    #[test]
+    fn plays_files_in_name_order() {
+        let dir = temp_dir("sequence-order");
+        write_png(&dir, "b.png", 2, 1);
+        write_png(&dir, "a.png", 1, 1);
+        std::fs::write(dir.join("notes.txt"), "skip me").unwrap();
+
+        let mut source = ImageSequenceSource::from_dir(&dir, false).unwrap();
+        let first = source.next_frame().unwrap().unwrap();
+        let second = source.next_frame().unwrap().unwrap();
+        let done = source.next_frame().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((first.id, first.width), (1, 1));
+        assert_eq!((second.id, second.width), (2, 2));
+        assert!(done.is_none());
+    }
+
+    #[test]
+    fn looping_restarts_with_new_ids() {
+        let dir = temp_dir("sequence-loop");
+        write_png(&dir, "only.png", 1, 1);
+
+        let mut source = ImageSequenceSource::from_dir(&dir, true).unwrap();
+        let ids: Vec<u64> = (0..3)
+            .map(|_| source.next_frame().unwrap().unwrap().id)
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn empty_dir_is_an_error() {
+        let dir = temp_dir("sequence-empty");
+        let result = ImageSequenceSource::from_dir(&dir, false);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::filter::PixelFormat;
+use crate::source::{Frame, FramePixels, FrameSource, encode_pixel};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Gray ramp from black on the left to white on the right.
+    Gradient,
+    /// Gray ramp over the shadow range the filter lifts, `0.0..=0.25`.
+    DarkRamp,
+    /// Near-black frames with a white frame every `period` frames.
+    Flash { period: u64 },
+}
+
+/// Generates test patterns, one new frame per call.
+pub struct SyntheticSource {
+    pattern: Pattern,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    frame_id: u64,
+}
+
+impl SyntheticSource {
+    pub fn new(pattern: Pattern, width: u32, height: u32, format: PixelFormat) -> Self {
+        Self {
+            pattern,
+            width,
+            height,
+            format,
+            frame_id: 0,
+        }
+    }
+
+    fn sample(&self, x: u32, frame_id: u64) -> [f32; 3] {
+        let t = if self.width > 1 {
+            x as f32 / (self.width - 1) as f32
+        } else {
+            0.0
+        };
+        let value = match self.pattern {
+            Pattern::Gradient => t,
+            Pattern::DarkRamp => t * 0.25,
+            Pattern::Flash { period } if frame_id.is_multiple_of(period) => 1.0,
+            Pattern::Flash { .. } => 0.02,
+        };
+        [value; 3]
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        self.frame_id += 1;
+        let bpp = self.format.bytes_per_pixel();
+        let row: Vec<u8> = (0..self.width)
+            .flat_map(|x| {
+                let mut px = [0; 8];
+                encode_pixel(self.format, self.sample(x, self.frame_id), &mut px[..bpp]);
+                px.into_iter().take(bpp)
+            })
+            .collect();
+        Ok(Some(Frame {
+            id: self.frame_id,
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            pixels: FramePixels::Cpu(row.repeat(self.height as usize)),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter;
+    use crate::params::FilterParams;
+
+    fn cpu_pixels(frame: Frame) -> Vec<u8> {
+        frame.pixels.as_cpu().expect("Expected CPU pixels").to_vec()
+    }
+
+    #[test]
+    fn frames_have_requested_shape_and_increasing_ids() {
+        for format in [PixelFormat::Rgba8, PixelFormat::Bgra8, PixelFormat::Rgba16F] {
+            let mut source = SyntheticSource::new(Pattern::Gradient, 16, 9, format);
+            let first = source.next_frame().unwrap().unwrap();
+            let second = source.next_frame().unwrap().unwrap();
+            assert_eq!((first.id, second.id), (1, 2));
+            assert_eq!(
+                (second.width, second.height, second.format),
+                (16, 9, format)
+            );
+            assert_eq!(cpu_pixels(second).len(), 16 * 9 * format.bytes_per_pixel());
+        }
+    }
+
+    #[test]
+    fn gradient_spans_black_to_white() {
+        let mut source = SyntheticSource::new(Pattern::Gradient, 4, 1, PixelFormat::Rgba8);
+        let pixels = cpu_pixels(source.next_frame().unwrap().unwrap());
+        assert_eq!(&pixels[..4], &[0, 0, 0, 255]);
+        assert_eq!(&pixels[12..], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn flash_fires_on_period() {
+        let mut source =
+            SyntheticSource::new(Pattern::Flash { period: 3 }, 1, 1, PixelFormat::Rgba8);
+        let reds: Vec<u8> = (0..6)
+            .map(|_| cpu_pixels(source.next_frame().unwrap().unwrap())[0])
+            .collect();
+        assert_eq!(reds, [5, 5, 255, 5, 5, 255]);
+    }
+
+    #[test]
+    fn filter_lifts_dark_ramp() {
+        let mut source = SyntheticSource::new(Pattern::DarkRamp, 64, 1, PixelFormat::Bgra8);
+        let frame = source.next_frame().unwrap().unwrap();
+        let before = cpu_pixels(frame);
+        let mut after = before.clone();
+        filter::apply(&mut after, PixelFormat::Bgra8, &FilterParams::default()).unwrap();
+        assert!(before.iter().zip(&after).all(|(b, a)| a >= b));
+        assert!(before.iter().zip(&after).any(|(b, a)| a > b));
+    }
+}
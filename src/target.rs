@@ -0,0 +1,503 @@
+//! What to capture and where the overlay goes.
+//!
+//! Matching and geometry are kept free of Win32 calls so they can be tested anywhere;
+//! the Windows frontend only feeds in the enumerated windows and their rectangles.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// Whole monitors, chosen with [`MonitorSelection`].
+    #[default]
+    Monitors,
+    /// Exact title match, falling back to the first title containing the text.
+    WindowTitle(String),
+    /// Executable name, with or without the `.exe` suffix.
+    Process(String),
+    Hwnd(isize),
+}
+
+impl FromStr for CaptureTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("monitor") {
+            return Ok(Self::Monitors);
+        }
+        let Some((kind, arg)) = value.split_once(':') else {
+            return Err(format!(
+                "unknown target `{value}`; expected `monitor`, `title:<text>`, `process:<name>` or `hwnd:<handle>`"
+            ));
+        };
+        if arg.is_empty() {
+            return Err(format!("missing value after `{kind}:`"));
+        }
+        match kind.to_ascii_lowercase().as_str() {
+            "title" => Ok(Self::WindowTitle(arg.to_string())),
+            "process" => Ok(Self::Process(arg.to_string())),
+            "hwnd" => parse_hwnd(arg).map(Self::Hwnd),
+            _ => Err(format!(
+                "unknown target kind `{kind}`; expected `title`, `process` or `hwnd`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CaptureTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Monitors => write!(f, "monitor"),
+            Self::WindowTitle(title) => write!(f, "title:{title}"),
+            Self::Process(name) => write!(f, "process:{name}"),
+            Self::Hwnd(hwnd) => write!(f, "hwnd:{hwnd:#x}"),
+        }
+    }
+}
+
+fn parse_hwnd(value: &str) -> Result<isize, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => isize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|err| format!("invalid window handle `{value}`: {err}"))
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MonitorSelection {
+    #[default]
+    Primary,
+    All,
+    /// One-based position in the monitor enumeration.
+    Index(usize),
+    /// Friendly name, or device name with or without the `\\.\` prefix.
+    Name(String),
+}
+
+impl FromStr for MonitorSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err("monitor name must not be empty".to_string());
+        }
+        if value.eq_ignore_ascii_case("primary") {
+            return Ok(Self::Primary);
+        }
+        if value.eq_ignore_ascii_case("all") {
+            return Ok(Self::All);
+        }
+        match value.parse::<usize>() {
+            Ok(0) => Err("monitor indices start at 1".to_string()),
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) => Ok(Self::Name(value.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for MonitorSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Primary => write!(f, "primary"),
+            Self::All => write!(f, "all"),
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// One-based position in the monitor enumeration.
+    pub index: usize,
+    pub name: String,
+    /// e.g. `\\.\DISPLAY1`.
+    pub device_name: String,
+    pub primary: bool,
+}
+
+impl MonitorInfo {
+    fn matches(&self, selection: &MonitorSelection) -> bool {
+        match selection {
+            MonitorSelection::Primary => self.primary,
+            MonitorSelection::All => true,
+            MonitorSelection::Index(index) => self.index == *index,
+            MonitorSelection::Name(name) => {
+                self.name.eq_ignore_ascii_case(name)
+                    || self.device_name.eq_ignore_ascii_case(name)
+                    || self
+                        .device_name
+                        .strip_prefix("\\\\.\\")
+                        .is_some_and(|device| device.eq_ignore_ascii_case(name))
+            }
+        }
+    }
+}
+
+/// Resolves `--monitor` values against the connected monitors, in enumeration order.
+///
+/// No selections means the primary monitor. Every selection has to match at least one
+/// monitor, so a typo fails loudly instead of silently enhancing nothing.
+pub fn select_monitors<'a>(
+    selections: &[MonitorSelection],
+    monitors: &'a [MonitorInfo],
+) -> Result<Vec<&'a MonitorInfo>, String> {
+    let default = [MonitorSelection::Primary];
+    let selections = if selections.is_empty() {
+        &default[..]
+    } else {
+        selections
+    };
+    if let Some(unmatched) = selections
+        .iter()
+        .find(|selection| !monitors.iter().any(|monitor| monitor.matches(selection)))
+    {
+        return Err(format!("No monitor matches `{unmatched}`"));
+    }
+    Ok(monitors
+        .iter()
+        .filter(|monitor| {
+            selections
+                .iter()
+                .any(|selection| monitor.matches(selection))
+        })
+        .collect())
+}
+
+/// A top-level window as seen by the capture frontend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub hwnd: isize,
+    pub title: String,
+    pub process_name: String,
+}
+
+impl CaptureTarget {
+    pub fn is_window(&self) -> bool {
+        !matches!(self, Self::Monitors)
+    }
+
+    /// Picks the window this target refers to out of an enumeration.
+    pub fn select<'a>(&self, windows: &'a [WindowInfo]) -> Option<&'a WindowInfo> {
+        match self {
+            Self::Monitors => None,
+            Self::WindowTitle(title) => {
+                let needle = title.to_lowercase();
+                windows
+                    .iter()
+                    .find(|window| window.title.to_lowercase() == needle)
+                    .or_else(|| {
+                        windows
+                            .iter()
+                            .find(|window| window.title.to_lowercase().contains(&needle))
+                    })
+            }
+            Self::Process(name) => {
+                let name = name.to_ascii_lowercase();
+                windows.iter().find(|window| {
+                    let process = window.process_name.to_ascii_lowercase();
+                    process == name || process.strip_suffix(".exe") == Some(name.as_str())
+                })
+            }
+            Self::Hwnd(hwnd) => windows.iter().find(|window| window.hwnd == *hwnd),
+        }
+    }
+}
+
+/// Screen-space rectangle, right and bottom exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn width(&self) -> u32 {
+        self.right.saturating_sub(self.left).max(0) as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bottom.saturating_sub(self.top).max(0) as u32
+    }
+
+    /// The area both rectangles cover, if any.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let rect = Rect {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.min(other.right),
+            bottom: self.bottom.min(other.bottom),
+        };
+        (rect.width() > 0 && rect.height() > 0).then_some(rect)
+    }
+}
+
+/// Where the overlay sits and how the captured window is drawn into it.
+///
+/// Window capture includes the frame and title bar, so the overlay covers only the
+/// client area and the viewport is shifted to push the decorations off its edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverlayLayout {
+    pub position: (i32, i32),
+    pub size: (u32, u32),
+    pub viewport_origin: (f32, f32),
+    pub viewport_size: (f32, f32),
+}
+
+impl OverlayLayout {
+    /// `frame` is the captured window bounds and `client` its client area, both in
+    /// screen coordinates. Returns `None` for a minimized or zero-sized window.
+    pub fn new(frame: Rect, client: Rect) -> Option<Self> {
+        if client.width() == 0 || client.height() == 0 || frame.width() == 0 {
+            return None;
+        }
+        Some(Self {
+            position: (client.left, client.top),
+            size: (client.width(), client.height()),
+            viewport_origin: (
+                (frame.left - client.left) as f32,
+                (frame.top - client.top) as f32,
+            ),
+            viewport_size: (frame.width() as f32, frame.height() as f32),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows() -> Vec<WindowInfo> {
+        [
+            (1, "Minecraft Launcher", "MinecraftLauncher.exe"),
+            (2, "Minecraft 1.21", "javaw.exe"),
+            (3, "notes.txt - Notepad", "notepad.exe"),
+        ]
+        .into_iter()
+        .map(|(hwnd, title, process)| WindowInfo {
+            hwnd,
+            title: title.to_string(),
+            process_name: process.to_string(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn parses_target_kinds() {
+        assert_eq!("monitor".parse(), Ok(CaptureTarget::Monitors));
+        assert_eq!(
+            "title:Minecraft".parse(),
+            Ok(CaptureTarget::WindowTitle("Minecraft".to_string()))
+        );
+        assert_eq!(
+            "process:javaw.exe".parse(),
+            Ok(CaptureTarget::Process("javaw.exe".to_string()))
+        );
+        assert_eq!("hwnd:0x1A2b".parse(), Ok(CaptureTarget::Hwnd(0x1a2b)));
+        assert_eq!("hwnd:4242".parse(), Ok(CaptureTarget::Hwnd(4242)));
+    }
+
+    #[test]
+    fn rejects_malformed_targets() {
+        assert!("window".parse::<CaptureTarget>().is_err());
+        assert!("title:".parse::<CaptureTarget>().is_err());
+        assert!("pid:12".parse::<CaptureTarget>().is_err());
+        assert!("hwnd:zz".parse::<CaptureTarget>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for target in ["monitor", "title:Game: Part 2", "process:game", "hwnd:0x10"] {
+            let parsed: CaptureTarget = target.parse().unwrap();
+            assert_eq!(parsed.to_string(), target);
+        }
+    }
+
+    #[test]
+    fn title_prefers_exact_match() {
+        let windows = windows();
+        let target = CaptureTarget::WindowTitle("minecraft 1.21".to_string());
+        assert_eq!(target.select(&windows).unwrap().hwnd, 2);
+
+        let target = CaptureTarget::WindowTitle("Minecraft".to_string());
+        assert_eq!(target.select(&windows).unwrap().hwnd, 1);
+
+        let target = CaptureTarget::WindowTitle("1.21".to_string());
+        assert_eq!(target.select(&windows).unwrap().hwnd, 2);
+    }
+
+    #[test]
+    fn process_matches_with_or_without_extension() {
+        let windows = windows();
+        for name in ["javaw.exe", "JAVAW", "javaw"] {
+            let target = CaptureTarget::Process(name.to_string());
+            assert_eq!(target.select(&windows).unwrap().hwnd, 2, "{name}");
+        }
+        assert!(
+            CaptureTarget::Process("java".to_string())
+                .select(&windows)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn hwnd_and_monitor_selection() {
+        let windows = windows();
+        assert_eq!(CaptureTarget::Hwnd(3).select(&windows).unwrap().hwnd, 3);
+        assert!(CaptureTarget::Hwnd(9).select(&windows).is_none());
+        assert!(CaptureTarget::Monitors.select(&windows).is_none());
+    }
+
+    fn monitors() -> Vec<MonitorInfo> {
+        [
+            (1, "DELL U2720Q", false),
+            (2, "LG ULTRAGEAR", true),
+            (3, "DELL U2720Q", false),
+        ]
+        .into_iter()
+        .map(|(index, name, primary)| MonitorInfo {
+            index,
+            name: name.to_string(),
+            device_name: format!("\\\\.\\DISPLAY{index}"),
+            primary,
+        })
+        .collect()
+    }
+
+    fn selected_indices(selections: &[MonitorSelection]) -> Result<Vec<usize>, String> {
+        let monitors = monitors();
+        select_monitors(selections, &monitors)
+            .map(|selected| selected.iter().map(|monitor| monitor.index).collect())
+    }
+
+    #[test]
+    fn parses_monitor_selections() {
+        assert_eq!("primary".parse(), Ok(MonitorSelection::Primary));
+        assert_eq!("ALL".parse(), Ok(MonitorSelection::All));
+        assert_eq!("2".parse(), Ok(MonitorSelection::Index(2)));
+        assert_eq!(
+            "DISPLAY3".parse(),
+            Ok(MonitorSelection::Name("DISPLAY3".to_string()))
+        );
+        assert!("0".parse::<MonitorSelection>().is_err());
+        assert!("".parse::<MonitorSelection>().is_err());
+    }
+
+    #[test]
+    fn no_selection_means_primary() {
+        assert_eq!(selected_indices(&[]), Ok(vec![2]));
+    }
+
+    #[test]
+    fn selects_by_index_name_and_device() {
+        assert_eq!(selected_indices(&[MonitorSelection::Index(3)]), Ok(vec![3]));
+        assert_eq!(
+            selected_indices(&[MonitorSelection::Name("dell u2720q".to_string())]),
+            Ok(vec![1, 3])
+        );
+        assert_eq!(
+            selected_indices(&[MonitorSelection::Name("display1".to_string())]),
+            Ok(vec![1])
+        );
+        assert_eq!(
+            selected_indices(&[MonitorSelection::Name("\\\\.\\DISPLAY2".to_string())]),
+            Ok(vec![2])
+        );
+    }
+
+    #[test]
+    fn combined_selections_are_deduplicated_in_order() {
+        let selections = [
+            MonitorSelection::Index(3),
+            MonitorSelection::Primary,
+            MonitorSelection::Index(2),
+        ];
+        assert_eq!(selected_indices(&selections), Ok(vec![2, 3]));
+        assert_eq!(
+            selected_indices(&[MonitorSelection::All]),
+            Ok(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn unmatched_selection_is_an_error() {
+        let err = selected_indices(&[MonitorSelection::Index(1), MonitorSelection::Index(4)])
+            .unwrap_err();
+        assert!(err.contains('4'), "{err}");
+    }
+
+    #[test]
+    fn layout_covers_client_and_offsets_decorations() {
+        let frame = Rect {
+            left: 100,
+            top: 50,
+            right: 900,
+            bottom: 690,
+        };
+        let client = Rect {
+            left: 108,
+            top: 81,
+            right: 892,
+            bottom: 682,
+        };
+        let layout = OverlayLayout::new(frame, client).unwrap();
+        assert_eq!(layout.position, (108, 81));
+        assert_eq!(layout.size, (784, 601));
+        assert_eq!(layout.viewport_origin, (-8.0, -31.0));
+        assert_eq!(layout.viewport_size, (800.0, 640.0));
+    }
+
+    #[test]
+    fn minimized_window_has_no_layout() {
+        let frame = Rect {
+            left: -32000,
+            top: -32000,
+            right: -31840,
+            bottom: -31972,
+        };
+        let client = Rect {
+            left: -32000,
+            top: -32000,
+            right: -32000,
+            bottom: -32000,
+        };
+        assert!(OverlayLayout::new(frame, client).is_none());
+    }
+
+    #[test]
+    fn intersection_clips_to_both_rectangles() {
+        let monitor = Rect {
+            left: 1920,
+            top: 0,
+            right: 3840,
+            bottom: 1080,
+        };
+        let window = Rect {
+            left: 1800,
+            top: 900,
+            right: 2200,
+            bottom: 1300,
+        };
+        let expected = Rect {
+            left: 1920,
+            top: 900,
+            right: 2200,
+            bottom: 1080,
+        };
+        assert_eq!(monitor.intersection(&window), Some(expected));
+        assert_eq!(window.intersection(&monitor), Some(expected));
+
+        let elsewhere = Rect {
+            left: 0,
+            top: 0,
+            right: 1920,
+            bottom: 1080,
+        };
+        assert_eq!(monitor.intersection(&elsewhere), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Wayland frontend: captures the target window with ext-image-copy-capture, or the
+//! output with wlr-screencopy, and shows the filtered frames on a click-through
+//! wlr-layer-shell surface, mirroring what `app` does on Windows.
+//!
+//! Frames are filtered by a [`crate::render`] renderer like on X11. Output copies would
+//! include the overlay itself, so the overlay hides for each one; window targets are
+//! captured on their own and have to be fullscreen, since Wayland does not say where a
+//! window is.
+
+mod capture;
+mod outputs;
+mod overlay;
+mod shm;
+
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::pipe::{PipeFlags, pipe_with};
+use wayland_client::EventQueue;
+
+use crate::config_watch::ConfigWatcher;
+use crate::error::OverlayError;
+use crate::hotkeys::Enhancement;
+use crate::linux::{self, CaptureThread, FLUSH_DELAY, OverlayEvent, ReloadConfig};
+use crate::options::{OverlayOptions, Settings};
+use crate::render::{self, FrameRenderer};
+use crate::source::{CpuFrameSource, CpuFrames, FrameSource};
+use crate::target::{CaptureTarget, MonitorSelection};
+use overlay::Overlay;
+
+pub use outputs::monitor_infos;
+
+/// How often `--frame-stats` prints the capture mailbox counters.
+const FRAME_STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// Capture rate without an `fps_cap`.
+const DEFAULT_FPS: u32 = 60;
+/// Longest the event loop sleeps when nothing happens.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether the session looks like Wayland rather than X11.
+pub fn is_session() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// Sends [`OverlayEvent`]s and makes the event loop's `poll` return for them.
+#[derive(Clone)]
+struct Waker {
+    events: Sender<OverlayEvent>,
+    pipe: Arc<OwnedFd>,
+}
+
+impl Waker {
+    /// The waker and the end the event loop reads and polls.
+    fn new() -> anyhow::Result<(Self, Receiver<OverlayEvent>, OwnedFd)> {
+        let (reader, writer) = pipe_with(PipeFlags::CLOEXEC | PipeFlags::NONBLOCK)?;
+        let (events, receiver) = mpsc::channel();
+        let waker = Self {
+            events,
+            pipe: Arc::new(writer),
+        };
+        Ok((waker, receiver, reader))
+    }
+
+    fn send(&self, event: OverlayEvent) {
+        if self.events.send(event).is_ok() {
+            // A full pipe already wakes the loop.
+            let _ = rustix::io::write(&*self.pipe, &[0]);
+        }
+    }
+}
+
+/// Runs the overlay until the target window closes or capturing fails.
+pub fn run(options: OverlayOptions) -> anyhow::Result<()> {
+    linux::check_options(Frontend::NAME, &options)?;
+    check_target(&options.settings.target).map_err(OverlayError::Config)?;
+    let (waker, events, wake) = Waker::new()?;
+    let mut frontend = Frontend {
+        renderer: render::create(options.renderer).map_err(OverlayError::Device)?,
+        enhancement: options.settings.enhancement(),
+        next_stats_report: options
+            .frame_stats
+            .then(|| Instant::now() + FRAME_STATS_INTERVAL),
+        overlay: Overlay::new(output_selection(&options.settings))
+            .map_err(OverlayError::Capture)
+            .context("Failed to start the overlay")?,
+        options,
+        capture: None,
+        waker: waker.clone(),
+        last_frame: Instant::now(),
+        copy: Vec::new(),
+    };
+    frontend
+        .start_capture()
+        .map_err(OverlayError::Capture)
+        .context("Failed to start the overlay")?;
+    let _config_watcher = match &frontend.options.config_path {
+        Some(path) => ConfigWatcher::spawn(path, move |config| {
+            waker.send(OverlayEvent::ConfigChanged(Box::new(config)));
+        })
+        .inspect_err(|err| eprintln!("Config reload unavailable: {err:?}"))
+        .ok(),
+        None => None,
+    };
+
+    loop {
+        let mut timeout = frontend
+            .next_stats_report
+            .map_or(IDLE_TIMEOUT, |report_at| {
+                report_at.saturating_duration_since(Instant::now())
+            });
+        if frontend.renderer.pending() {
+            timeout = timeout.min(FLUSH_DELAY);
+        }
+        if let Some(Capture {
+            copies: Some(copies),
+            ..
+        }) = &frontend.capture
+        {
+            timeout = timeout.min(copies.next.saturating_duration_since(Instant::now()));
+        }
+        frontend.overlay.dispatch(wake.as_fd(), timeout)?;
+        while rustix::io::read(&wake, &mut [0; 64]).is_ok_and(|read| read > 0) {}
+        loop {
+            match events.try_recv() {
+                Ok(OverlayEvent::FrameReady) => {}
+                Ok(OverlayEvent::CaptureFailed(err)) => {
+                    return Err(OverlayError::Capture(err).into());
+                }
+                Ok(OverlayEvent::ConfigChanged(config)) => frontend.reload_config(&config)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        frontend.take_copy()?;
+        frontend.present()?;
+        frontend.start_copy()?;
+        frontend.report_frame_stats();
+    }
+}
+
+/// The mailbox frames are read from, with what fills it.
+struct Capture {
+    // Declared first so the thread stops before the source goes away.
+    /// The thread capturing the target window; `None` for outputs.
+    _thread: Option<CaptureThread>,
+    frames: CpuFrames,
+    source: CpuFrameSource,
+    /// When to copy the output next, for output targets.
+    copies: Option<OutputCopies>,
+}
+
+/// The pace of output copies, which the event loop makes itself since the overlay has to
+/// hide for each of them.
+struct OutputCopies {
+    interval: Duration,
+    next: Instant,
+}
+
+struct Frontend {
+    options: OverlayOptions,
+    enhancement: Enhancement,
+    overlay: Overlay,
+    renderer: Box<dyn FrameRenderer>,
+    capture: Option<Capture>,
+    waker: Waker,
+    /// When the last frame was handed to the renderer.
+    last_frame: Instant,
+    /// The pixels of the last output copy, on their way into the mailbox.
+    copy: Vec<u8>,
+    /// When to print the next `--frame-stats` report; `None` when disabled.
+    next_stats_report: Option<Instant>,
+}
+
+impl Frontend {
+    /// Starts capturing the target, moving the overlay to another output first if the
+    /// target asks for one.
+    fn start_capture(&mut self) -> anyhow::Result<()> {
+        let settings = &self.options.settings;
+        check_target(&settings.target)?;
+        let monitors = output_selection(settings);
+        if self.overlay.monitors() != monitors {
+            self.overlay = Overlay::new(monitors)?;
+        }
+        let fps = settings.fps_cap.unwrap_or(DEFAULT_FPS);
+        let frames = CpuFrames::default();
+        let (thread, copies) = if settings.target.is_window() {
+            let ready = self.waker.clone();
+            let failures = self.waker.clone();
+            let thread = capture::spawn(
+                &settings.target,
+                fps,
+                frames.clone(),
+                move || ready.send(OverlayEvent::FrameReady),
+                move |err| failures.send(OverlayEvent::CaptureFailed(err)),
+            )?;
+            (Some(thread), None)
+        } else {
+            let copies = OutputCopies {
+                interval: Duration::from_secs(1) / fps.max(1),
+                next: Instant::now(),
+            };
+            (None, Some(copies))
+        };
+        self.capture = Some(Capture {
+            _thread: thread,
+            source: frames.source(),
+            frames,
+            copies,
+        });
+        Ok(())
+    }
+
+    /// Hands a finished output copy to the renderer's mailbox.
+    fn take_copy(&mut self) -> anyhow::Result<()> {
+        if let Some(capture) = &self.capture
+            && let Some(size) = self.overlay.take_copy(&mut self.copy)?
+        {
+            capture.frames.publish(&mut self.copy, size);
+        }
+        Ok(())
+    }
+
+    /// Copies the output again once it is time to and the last copy has been shown, so
+    /// the overlay is visible between copies.
+    fn start_copy(&mut self) -> anyhow::Result<()> {
+        let Some(Capture {
+            copies: Some(copies),
+            ..
+        }) = &mut self.capture
+        else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now < copies.next || self.overlay.is_copying() || self.renderer.pending() {
+            return Ok(());
+        }
+        copies.next = (copies.next + copies.interval).max(now);
+        self.overlay.start_copy()
+    }
+
+    /// Filters the newest frame, if one arrived since the last call, and shows the
+    /// newest one that is done.
+    fn present(&mut self) -> anyhow::Result<()> {
+        let Some(capture) = &mut self.capture else {
+            return Ok(());
+        };
+        let filtered = match capture.source.next_frame()? {
+            Some(frame) if self.overlay.fits((frame.width, frame.height)) => {
+                self.last_frame = Instant::now();
+                let params = self.enhancement.params();
+                self.renderer
+                    .render(&frame, &params, self.enhancement.lut())?
+            }
+            Some(_) => return self.overlay.show_blank(),
+            None => {
+                let wait = self.last_frame.elapsed() >= FLUSH_DELAY;
+                self.renderer.finished(wait)?
+            }
+        };
+        match filtered {
+            Some(filtered) if self.overlay.fits(filtered.size) => {
+                self.overlay.present(filtered.pixels, filtered.size)
+            }
+            Some(_) => self.overlay.show_blank(),
+            None => Ok(()),
+        }
+    }
+
+    fn report_frame_stats(&mut self) {
+        let (Some(report_at), Some(capture)) = (self.next_stats_report, &self.capture) else {
+            return;
+        };
+        if Instant::now() < report_at {
+            return;
+        }
+        let stats = capture.source.stats();
+        eprintln!(
+            "{} captured, {} rendered, {} dropped, {} reused",
+            stats.published, stats.consumed, stats.dropped, stats.reused
+        );
+        self.next_stats_report = Some(Instant::now() + FRAME_STATS_INTERVAL);
+    }
+}
+
+impl ReloadConfig for Frontend {
+    const NAME: &'static str = "The Wayland overlay";
+
+    fn options(&mut self) -> &mut OverlayOptions {
+        &mut self.options
+    }
+
+    fn enhancement(&mut self) -> &mut Enhancement {
+        &mut self.enhancement
+    }
+
+    fn stop_capture(&mut self) {
+        self.capture = None;
+    }
+
+    fn start_capture(&mut self) -> anyhow::Result<()> {
+        Frontend::start_capture(self)
+    }
+}
+
+/// Fails for targets the Wayland overlay cannot enhance.
+fn check_target(target: &CaptureTarget) -> anyhow::Result<()> {
+    match target {
+        CaptureTarget::Hwnd(_) => anyhow::bail!(
+            "Wayland windows have no handles; pass `--target title:<text>` or \
+             `--target process:<app id>` instead"
+        ),
+        CaptureTarget::Monitors | CaptureTarget::WindowTitle(_) | CaptureTarget::Process(_) => {
+            Ok(())
+        }
+    }
+}
+
+/// The `--monitor` values to open the overlay for, `None` when it enhances a window.
+fn output_selection(settings: &Settings) -> Option<&[MonitorSelection]> {
+    (!settings.target.is_window()).then_some(&settings.monitors[..])
+}
+
+/// Dispatches queued events, then waits up to `timeout` for more from the compositor, or
+/// for `wake` to become readable, and dispatches whatever arrived.
+fn dispatch_for<D>(
+    queue: &mut EventQueue<D>,
+    state: &mut D,
+    wake: Option<BorrowedFd<'_>>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    queue.dispatch_pending(state)?;
+    queue.flush()?;
+    if let Some(guard) = queue.prepare_read() {
+        let readable = {
+            let connection = guard.connection_fd();
+            let mut fds = vec![PollFd::new(&connection, PollFlags::IN)];
+            if let Some(wake) = &wake {
+                fds.push(PollFd::new(wake, PollFlags::IN));
+            }
+            let timeout = Timespec::try_from(timeout)?;
+            match poll(&mut fds, Some(&timeout)) {
+                Ok(_) | Err(rustix::io::Errno::INTR) => {}
+                Err(err) => return Err(err.into()),
+            }
+            !fds[0].revents().is_empty()
+        };
+        if readable {
+            guard.read()?;
+        }
+    }
+    queue.dispatch_pending(state)?;
+    Ok(())
+}
@@ -0,0 +1,514 @@
+//! Captures the target window with ext-image-copy-capture on a thread of its own and
+//! hands the newest frame to the renderer through [`CpuFrames`].
+//!
+//! Only toplevel sources are used here. Outputs are copied by the overlay itself, which
+//! has to hide for each copy so it does not filter its own output over and over.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::Context;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, delegate_noop, event_created_child,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::{
+    ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+    ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+};
+use wayland_protocols::ext::image_capture_source::v1::client::{
+    ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+    ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::{
+    ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1, FailureReason},
+    ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
+    ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+};
+
+use super::shm::{self, BufferBusy, ShmBuffer};
+use crate::linux::CaptureThread;
+use crate::source::CpuFrames;
+use crate::target::{CaptureTarget, WindowInfo};
+
+/// How long the capture thread waits for the compositor before checking whether it
+/// should stop; frames of a window that does not change never arrive.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Finds the window `target` refers to and captures it on a thread and connection of
+/// its own up to `fps` times a second.
+pub(super) fn spawn(
+    target: &CaptureTarget,
+    fps: u32,
+    frames: CpuFrames,
+    on_frame: impl Fn() + Send + 'static,
+    on_error: impl FnOnce(anyhow::Error) + Send + 'static,
+) -> anyhow::Result<CaptureThread> {
+    let mut capturer = Capturer::new(target)?;
+    CaptureThread::spawn(
+        "wayland-capture",
+        fps,
+        frames,
+        move |stop, pixels| capturer.capture(stop, pixels),
+        on_frame,
+        on_error,
+    )
+}
+
+/// A capture session for one window.
+struct Capturer {
+    queue: EventQueue<CaptureState>,
+    state: CaptureState,
+    shm: WlShm,
+    session: ExtImageCopyCaptureSessionV1,
+    buffer: Option<ShmBuffer>,
+}
+
+impl Capturer {
+    fn new(target: &CaptureTarget) -> anyhow::Result<Self> {
+        let conn =
+            Connection::connect_to_env().context("Failed to connect to the Wayland compositor")?;
+        let (globals, mut queue) = registry_queue_init::<CaptureState>(&conn)?;
+        let qh = queue.handle();
+        let missing = |name: &str| format!("The compositor does not support {name}");
+        let list: ExtForeignToplevelListV1 = globals
+            .bind(&qh, 1..=1, ())
+            .with_context(|| missing("ext-foreign-toplevel-list"))?;
+        let sources: ExtForeignToplevelImageCaptureSourceManagerV1 =
+            globals
+                .bind(&qh, 1..=1, ())
+                .with_context(|| missing("capturing windows with ext-image-capture-source"))?;
+        let copy: ExtImageCopyCaptureManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .with_context(|| missing("ext-image-copy-capture"))?;
+        let shm: WlShm = globals.bind(&qh, 1..=1, ())?;
+
+        // The first roundtrip announces the toplevels, the second their titles.
+        let mut state = CaptureState::default();
+        queue.roundtrip(&mut state)?;
+        queue.roundtrip(&mut state)?;
+        let windows = state.windows();
+        let window = target
+            .select(&windows)
+            .ok_or_else(|| anyhow::anyhow!("No window matches {target}"))?;
+        let handle = state.toplevels[window.hwnd as usize].handle.clone();
+        list.stop();
+
+        let source = sources.create_source(&handle, &qh, ());
+        let session = copy.create_session(&source, Options::empty(), &qh, ());
+        while state.constraints.is_none() {
+            if let Some(err) = state.session_error() {
+                anyhow::bail!(err);
+            }
+            queue.blocking_dispatch(&mut state)?;
+        }
+        Ok(Self {
+            queue,
+            state,
+            shm,
+            session,
+            buffer: None,
+        })
+    }
+
+    /// Copies the window's next frame into `out` and returns its size, or `None` when
+    /// the compositor could not provide one this time. Fails once the window is gone.
+    fn capture(
+        &mut self,
+        stop: &AtomicBool,
+        out: &mut Vec<u8>,
+    ) -> anyhow::Result<Option<(u32, u32)>> {
+        if let Some(err) = self.state.session_error() {
+            anyhow::bail!(err);
+        }
+        let Some(constraints) = self.state.constraints else {
+            return Ok(None);
+        };
+        let qh = self.queue.handle();
+        if self.buffer.as_ref().is_none_or(|buffer| {
+            buffer.size() != constraints.size || buffer.format() != constraints.format
+        }) {
+            let (width, height) = constraints.size;
+            self.buffer = Some(ShmBuffer::new(
+                &self.shm,
+                &qh,
+                width,
+                height,
+                constraints.format,
+            )?);
+        }
+        let buffer = self.buffer.as_ref().unwrap();
+
+        let (width, height) = constraints.size;
+        let frame = self.session.create_frame(&qh, ());
+        frame.attach_buffer(buffer.buffer());
+        frame.damage_buffer(0, 0, width as i32, height as i32);
+        frame.capture();
+        self.state.outcome = None;
+        while self.state.outcome.is_none() && !self.state.stopped && !stop.load(Ordering::Relaxed) {
+            super::dispatch_for(&mut self.queue, &mut self.state, None, STOP_POLL_INTERVAL)?;
+        }
+        frame.destroy();
+
+        match self.state.outcome.take() {
+            Some(Outcome::Ready) => {
+                out.clear();
+                out.extend_from_slice(&buffer.as_slice()[..width as usize * height as usize * 4]);
+                Ok(Some(constraints.size))
+            }
+            Some(Outcome::Failed(WEnum::Value(FailureReason::Stopped))) => {
+                anyhow::bail!("Target window closed")
+            }
+            // New constraints follow a failure caused by them, e.g. a resize.
+            Some(Outcome::Failed(_)) | None => Ok(None),
+        }
+    }
+}
+
+impl Drop for Capturer {
+    fn drop(&mut self) {
+        self.buffer = None;
+        self.session.destroy();
+        let _ = self.queue.flush();
+    }
+}
+
+struct Toplevel {
+    handle: ExtForeignToplevelHandleV1,
+    title: String,
+    app_id: String,
+}
+
+/// The buffer the session asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Constraints {
+    size: (u32, u32),
+    format: wl_shm::Format,
+}
+
+enum Outcome {
+    Ready,
+    Failed(WEnum<FailureReason>),
+}
+
+#[derive(Default)]
+struct CaptureState {
+    toplevels: Vec<Toplevel>,
+    /// Collected until the session's `done`.
+    pending_size: Option<(u32, u32)>,
+    pending_formats: Vec<wl_shm::Format>,
+    constraints: Option<Constraints>,
+    no_format: bool,
+    stopped: bool,
+    outcome: Option<Outcome>,
+}
+
+impl CaptureState {
+    /// The toplevels as the target matching sees them: the app id stands in for the
+    /// process name and `hwnd` is the index in `toplevels`.
+    fn windows(&self) -> Vec<WindowInfo> {
+        self.toplevels
+            .iter()
+            .enumerate()
+            .map(|(i, toplevel)| WindowInfo {
+                hwnd: i as isize,
+                title: toplevel.title.clone(),
+                process_name: toplevel.app_id.clone(),
+            })
+            .collect()
+    }
+
+    fn session_error(&self) -> Option<&'static str> {
+        if self.stopped {
+            Some("Target window closed")
+        } else if self.no_format {
+            Some("The compositor offers no 32-bit shared-memory format for capturing")
+        } else {
+            None
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for CaptureState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for CaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtForeignToplevelListV1,
+        event: ext_foreign_toplevel_list_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } = event {
+            state.toplevels.push(Toplevel {
+                handle: toplevel,
+                title: String::new(),
+                app_id: String::new(),
+            });
+        }
+    }
+
+    event_created_child!(CaptureState, ExtForeignToplevelListV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for CaptureState {
+    fn event(
+        state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: ext_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(toplevel) = state.toplevels.iter_mut().find(|t| &t.handle == handle) else {
+            return;
+        };
+        match event {
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureSessionV1,
+        event: ext_image_copy_capture_session_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                state.pending_size = Some((width, height));
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat {
+                format: WEnum::Value(format),
+            } => state.pending_formats.push(format),
+            ext_image_copy_capture_session_v1::Event::Done => {
+                // Both are blue, green, red in memory, like every other frame source.
+                let format = [wl_shm::Format::Xrgb8888, wl_shm::Format::Argb8888]
+                    .into_iter()
+                    .find(|format| state.pending_formats.contains(format));
+                state.pending_formats.clear();
+                match (state.pending_size, format) {
+                    (Some(size), Some(format)) => {
+                        state.constraints = Some(Constraints { size, format });
+                    }
+                    _ => state.no_format = true,
+                }
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => state.stopped = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for CaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureFrameV1,
+        event: ext_image_copy_capture_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Ready => state.outcome = Some(Outcome::Ready),
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                state.outcome = Some(Outcome::Failed(reason));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, BufferBusy> for CaptureState {
+    fn event(
+        _: &mut Self,
+        _: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        busy: &BufferBusy,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        shm::buffer_event(event, busy);
+    }
+}
+
+delegate_noop!(CaptureState: ignore WlShm);
+delegate_noop!(CaptureState: WlShmPool);
+delegate_noop!(CaptureState: ExtForeignToplevelImageCaptureSourceManagerV1);
+delegate_noop!(CaptureState: ExtImageCaptureSourceV1);
+delegate_noop!(CaptureState: ExtImageCopyCaptureManagerV1);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use wayland_client::protocol::wl_compositor::WlCompositor;
+    use wayland_client::protocol::wl_surface::WlSurface;
+    use wayland_protocols::xdg::shell::client::{
+        xdg_surface::{self, XdgSurface},
+        xdg_toplevel::XdgToplevel,
+        xdg_wm_base::{self, XdgWmBase},
+    };
+
+    use super::*;
+    use crate::filter::PixelFormat;
+    use crate::source::{FrameSource, Pattern, SyntheticSource};
+
+    const TITLE: &str = "ban-shadow capture test";
+
+    /// The "game": a toplevel showing a pattern from the synthetic source.
+    #[derive(Default)]
+    struct Game {
+        configured: bool,
+    }
+
+    impl Dispatch<XdgWmBase, ()> for Game {
+        fn event(
+            _: &mut Self,
+            wm_base: &XdgWmBase,
+            event: xdg_wm_base::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let xdg_wm_base::Event::Ping { serial } = event {
+                wm_base.pong(serial);
+            }
+        }
+    }
+
+    impl Dispatch<XdgSurface, ()> for Game {
+        fn event(
+            game: &mut Self,
+            xdg_surface: &XdgSurface,
+            event: xdg_surface::Event,
+            _: &(),
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            if let xdg_surface::Event::Configure { serial } = event {
+                xdg_surface.ack_configure(serial);
+                game.configured = true;
+            }
+        }
+    }
+
+    impl Dispatch<WlRegistry, GlobalListContents> for Game {
+        fn event(
+            _: &mut Self,
+            _: &WlRegistry,
+            _: <WlRegistry as Proxy>::Event,
+            _: &GlobalListContents,
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+        }
+    }
+
+    impl Dispatch<WlBuffer, BufferBusy> for Game {
+        fn event(
+            _: &mut Self,
+            _: &WlBuffer,
+            event: <WlBuffer as Proxy>::Event,
+            busy: &BufferBusy,
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+            shm::buffer_event(event, busy);
+        }
+    }
+
+    delegate_noop!(Game: WlCompositor);
+    delegate_noop!(Game: ignore WlSurface);
+    delegate_noop!(Game: ignore WlShm);
+    delegate_noop!(Game: WlShmPool);
+    delegate_noop!(Game: ignore XdgToplevel);
+
+    /// Runs against a compositor with ext-image-copy-capture, e.g. from a terminal in a
+    /// headless sway (`WLR_BACKENDS=headless sway`): `cargo test wayland -- --ignored`.
+    #[test]
+    #[ignore = "needs a Wayland compositor"]
+    fn captures_a_pattern_window() {
+        let (width, height) = (64, 32);
+        let conn = Connection::connect_to_env().unwrap();
+        let (globals, mut queue) = registry_queue_init::<Game>(&conn).unwrap();
+        let qh = queue.handle();
+        let compositor: WlCompositor = globals.bind(&qh, 4..=6, ()).unwrap();
+        let shm: WlShm = globals.bind(&qh, 1..=1, ()).unwrap();
+        let wm_base: XdgWmBase = globals.bind(&qh, 1..=6, ()).unwrap();
+
+        let surface = compositor.create_surface(&qh, ());
+        let xdg_surface = wm_base.get_xdg_surface(&surface, &qh, ());
+        let toplevel = xdg_surface.get_toplevel(&qh, ());
+        toplevel.set_title(TITLE.to_string());
+        surface.commit();
+        let mut game = Game::default();
+        while !game.configured {
+            queue.blocking_dispatch(&mut game).unwrap();
+        }
+
+        let mut source = SyntheticSource::new(Pattern::DarkRamp, width, height, PixelFormat::Bgra8);
+        let pattern = source.next_frame().unwrap().unwrap();
+        let pattern = pattern.pixels.as_cpu().unwrap().to_vec();
+        let mut buffer =
+            ShmBuffer::new(&shm, &qh, width, height, wl_shm::Format::Xrgb8888).unwrap();
+        buffer.as_mut_slice().copy_from_slice(&pattern);
+        surface.attach(Some(buffer.buffer()), 0, 0);
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        surface.commit();
+        queue.roundtrip(&mut game).unwrap();
+
+        let frames = CpuFrames::default();
+        let (sender, results) = mpsc::channel();
+        let failures = sender.clone();
+        let _capture = spawn(
+            &CaptureTarget::WindowTitle(TITLE.to_string()),
+            30,
+            frames.clone(),
+            move || {
+                let _ = sender.send(Ok(()));
+            },
+            move |err| {
+                let _ = failures.send(Err(format!("{err:?}")));
+            },
+        )
+        .unwrap();
+        results
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+
+        let frame = frames.source().next_frame().unwrap().unwrap();
+        assert_eq!((frame.width, frame.height), (width, height));
+        let rgb = |pixels: &[u8]| -> Vec<u8> {
+            pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| pixel[..3].to_vec())
+                .collect()
+        };
+        assert_eq!(rgb(frame.pixels.as_cpu().unwrap()), rgb(&pattern));
+    }
+}
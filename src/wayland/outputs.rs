@@ -0,0 +1,160 @@
+//! The compositor's outputs, described the way `--monitor` and `--list-monitors` expect.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use wayland_client::globals::{GlobalList, GlobalListContents, registry_queue_init};
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+
+use crate::target::MonitorInfo;
+
+/// What an output's user data collects from its events.
+pub(super) type OutputDetails = Arc<Mutex<Details>>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(super) struct Details {
+    /// The connector, e.g. `DP-1`; from wl_output version 4.
+    name: Option<String>,
+    /// e.g. `Dell Inc. DELL U2720Q`; from wl_output version 4.
+    description: Option<String>,
+    /// Make and model, for compositors with older outputs.
+    model: Option<String>,
+}
+
+/// Binds every output the registry announced, in its order. Their details arrive with
+/// the next roundtrip.
+pub(super) fn bind<D>(globals: &GlobalList, qh: &QueueHandle<D>) -> Vec<(WlOutput, OutputDetails)>
+where
+    D: Dispatch<WlOutput, OutputDetails> + 'static,
+{
+    globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == WlOutput::interface().name)
+            .map(|global| {
+                let details = OutputDetails::default();
+                let output = globals.registry().bind::<WlOutput, _, _>(
+                    global.name,
+                    global.version.min(4),
+                    qh,
+                    details.clone(),
+                );
+                (output, details)
+            })
+            .collect()
+    })
+}
+
+/// Describes the bound `outputs`, in the same order.
+pub(super) fn infos(outputs: &[(WlOutput, OutputDetails)]) -> Vec<MonitorInfo> {
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(i, (_, details))| info(i + 1, &details.lock().unwrap()))
+        .collect()
+}
+
+/// The connector is the device name and the description the friendly one. Wayland has
+/// no primary output, so the first one stands in for it.
+fn info(index: usize, details: &Details) -> MonitorInfo {
+    let device_name = details
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("output-{index}"));
+    MonitorInfo {
+        index,
+        name: details
+            .description
+            .clone()
+            .or_else(|| details.model.clone())
+            .unwrap_or_else(|| device_name.clone()),
+        device_name,
+        primary: index == 1,
+    }
+}
+
+/// The output half of the [`Dispatch`] impls a queue binding outputs needs.
+pub(super) fn output_event(event: wl_output::Event, details: &OutputDetails) {
+    let mut details = details.lock().unwrap();
+    match event {
+        wl_output::Event::Name { name } => details.name = Some(name),
+        wl_output::Event::Description { description } => details.description = Some(description),
+        wl_output::Event::Geometry { make, model, .. } => {
+            details.model = Some(format!("{make} {model}"));
+        }
+        _ => {}
+    }
+}
+
+/// Describes the outputs for `--monitor` and `--list-monitors`.
+pub fn monitor_infos() -> anyhow::Result<Vec<MonitorInfo>> {
+    let conn =
+        Connection::connect_to_env().context("Failed to connect to the Wayland compositor")?;
+    let (globals, mut queue) = registry_queue_init::<Listing>(&conn)?;
+    let outputs = bind(&globals, &queue.handle());
+    queue.roundtrip(&mut Listing)?;
+    let infos = infos(&outputs);
+    for (output, _) in outputs {
+        if output.version() >= 3 {
+            output.release();
+        }
+    }
+    Ok(infos)
+}
+
+struct Listing;
+
+impl Dispatch<WlRegistry, GlobalListContents> for Listing {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlOutput, OutputDetails> for Listing {
+    fn event(
+        _: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        details: &OutputDetails,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        output_event(event, details);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_outputs_by_connector_and_description() {
+        let details = Details {
+            name: Some("DP-1".to_string()),
+            description: Some("Dell Inc. U2720Q".to_string()),
+            model: Some("Dell Inc. U2720Q".to_string()),
+        };
+        let first = info(1, &details);
+        assert_eq!(first.device_name, "DP-1");
+        assert_eq!(first.name, "Dell Inc. U2720Q");
+        assert!(first.primary);
+
+        let old = Details {
+            model: Some("Acme Panel".to_string()),
+            ..Details::default()
+        };
+        let second = info(2, &old);
+        assert_eq!(
+            (second.device_name.as_str(), second.name.as_str()),
+            ("output-2", "Acme Panel")
+        );
+        assert!(!second.primary);
+    }
+}
@@ -0,0 +1,557 @@
+//! The overlay surface: a layer-shell surface on the overlay layer, covering the output
+//! and with an empty input region so every click goes through to the game underneath.
+//!
+//! For output targets the overlay also copies its output with wlr-screencopy. The copy
+//! is requested on the overlay's own connection right after the commit that hides the
+//! overlay, so the compositor applies that commit first and the overlay is never part
+//! of what it copies.
+
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+use anyhow::Context;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_region::WlRegion;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{Format, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::protocol::wl_surface::{self, WlSurface};
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, delegate_noop};
+use wayland_protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+};
+use wayland_protocols::wp::viewporter::client::{
+    wp_viewport::WpViewport, wp_viewporter::WpViewporter,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, Anchor, ZwlrLayerSurfaceV1},
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, Flags, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use super::outputs::{self, OutputDetails};
+use super::shm::{self, BufferBusy, ShmBuffer};
+use crate::target::{MonitorSelection, select_monitors};
+
+const NAMESPACE: &str = "ban-shadow";
+/// Frames in flight at once: one on screen, one queued and one being filled.
+const BUFFER_COUNT: usize = 3;
+
+pub(super) struct Overlay {
+    queue: EventQueue<OverlayState>,
+    state: OverlayState,
+    shm: WlShm,
+    surface: WlSurface,
+    layer_surface: ZwlrLayerSurfaceV1,
+    /// Stretches whatever buffer is attached over the whole surface.
+    viewport: WpViewport,
+    fractional_scale: Option<WpFractionalScaleV1>,
+    buffers: Vec<ShmBuffer>,
+    /// A transparent pixel, shown while there is no frame that fits the output.
+    blank: ShmBuffer,
+    showing_frame: bool,
+    /// What the overlay was opened for: `None` for windows, else the `--monitor` values.
+    monitors: Option<Vec<MonitorSelection>>,
+    /// For output targets, the output the overlay covers and copies.
+    output: Option<OutputCopier>,
+}
+
+/// Copies the overlay's output.
+struct OutputCopier {
+    manager: ZwlrScreencopyManagerV1,
+    output: WlOutput,
+    /// The copy under way, if any.
+    frame: Option<ZwlrScreencopyFrameV1>,
+    buffer: Option<ShmBuffer>,
+}
+
+impl Overlay {
+    /// Opens the overlay on the output `monitors` select, which has to be exactly one, and
+    /// copies that output; without `monitors`, opens it on the output the compositor
+    /// picks, normally the focused one, and copies nothing.
+    pub fn new(monitors: Option<&[MonitorSelection]>) -> anyhow::Result<Self> {
+        let conn =
+            Connection::connect_to_env().context("Failed to connect to the Wayland compositor")?;
+        let (globals, mut queue) = registry_queue_init::<OverlayState>(&conn)?;
+        let qh = queue.handle();
+        let compositor: WlCompositor = globals.bind(&qh, 4..=6, ())?;
+        let shm: WlShm = globals.bind(&qh, 1..=1, ())?;
+        let layer_shell: ZwlrLayerShellV1 = globals
+            .bind(&qh, 1..=4, ())
+            .context("The compositor does not support wlr-layer-shell")?;
+        let viewporter: WpViewporter = globals
+            .bind(&qh, 1..=1, ())
+            .context("The compositor does not support wp-viewporter")?;
+        let fractional_scales: Option<WpFractionalScaleManagerV1> =
+            globals.bind(&qh, 1..=1, ()).ok();
+        let mut state = OverlayState::default();
+        let output = match monitors {
+            Some(selections) => {
+                let manager: ZwlrScreencopyManagerV1 = globals
+                    .bind(&qh, 1..=3, ())
+                    .context("The compositor does not support wlr-screencopy")?;
+                let outputs = outputs::bind(&globals, &qh);
+                queue.roundtrip(&mut state)?;
+                Some(OutputCopier {
+                    manager,
+                    output: select_output(&outputs, selections)?,
+                    frame: None,
+                    buffer: None,
+                })
+            }
+            None => None,
+        };
+
+        let surface = compositor.create_surface(&qh, ());
+        // An empty input region: the pointer never lands on the overlay.
+        let region = compositor.create_region(&qh, ());
+        surface.set_input_region(Some(&region));
+        region.destroy();
+        let layer_surface = layer_shell.get_layer_surface(
+            &surface,
+            output.as_ref().map(|copier| &copier.output),
+            Layer::Overlay,
+            NAMESPACE.to_string(),
+            &qh,
+            (),
+        );
+        layer_surface.set_anchor(Anchor::Top | Anchor::Bottom | Anchor::Left | Anchor::Right);
+        // Cover panels too, rather than the area they leave free.
+        layer_surface.set_exclusive_zone(-1);
+        let viewport = viewporter.get_viewport(&surface, &qh, ());
+        let fractional_scale = fractional_scales
+            .as_ref()
+            .map(|manager| manager.get_fractional_scale(&surface, &qh, ()));
+        surface.commit();
+
+        while state.size.is_none() {
+            if state.closed {
+                anyhow::bail!("The compositor refused the overlay surface");
+            }
+            queue.blocking_dispatch(&mut state)?;
+        }
+        let blank = ShmBuffer::new(&shm, &qh, 1, 1, Format::Argb8888)?;
+        let mut overlay = Self {
+            queue,
+            state,
+            shm,
+            surface,
+            layer_surface,
+            viewport,
+            fractional_scale,
+            buffers: Vec::new(),
+            blank,
+            showing_frame: true,
+            monitors: monitors.map(<[_]>::to_vec),
+            output,
+        };
+        overlay.show_blank()?;
+        Ok(overlay)
+    }
+
+    /// Dispatches what the compositor sent, waiting up to `timeout` for it or for `wake`
+    /// to become readable. Fails once the compositor closed the overlay, e.g. because its
+    /// output went away.
+    pub fn dispatch(&mut self, wake: BorrowedFd<'_>, timeout: Duration) -> anyhow::Result<()> {
+        super::dispatch_for(&mut self.queue, &mut self.state, Some(wake), timeout)?;
+        if self.state.closed {
+            anyhow::bail!("The compositor closed the overlay");
+        }
+        Ok(())
+    }
+
+    /// The `--monitor` values the overlay was opened for, `None` for window targets.
+    pub fn monitors(&self) -> Option<&[MonitorSelection]> {
+        self.monitors.as_deref()
+    }
+
+    /// Whether a `size` frame covers the output pixel for pixel. Anything else, e.g. a
+    /// window that is not fullscreen, is not shown, since there is no telling where on
+    /// the output it is. Copies of the output always cover it, whatever its scale rounds
+    /// to.
+    pub fn fits(&self, size: (u32, u32)) -> bool {
+        if self.output.is_some() {
+            return size != (0, 0);
+        }
+        let logical = self.state.size.unwrap_or_default();
+        size != (0, 0) && size == buffer_size(logical, self.state.scale())
+    }
+
+    /// Whether an output copy is under way, during which the overlay stays hidden.
+    pub fn is_copying(&self) -> bool {
+        self.output
+            .as_ref()
+            .is_some_and(|copier| copier.frame.is_some())
+    }
+
+    /// Hides the overlay and asks for a copy of the output without it, for
+    /// [`Self::take_copy`] to hand over once it is done.
+    pub fn start_copy(&mut self) -> anyhow::Result<()> {
+        if self.output.is_none() || self.is_copying() {
+            return Ok(());
+        }
+        self.show_blank()?;
+        let qh = self.queue.handle();
+        let copier = self.output.as_mut().expect("checked above");
+        self.state.copy = CopyEvents::default();
+        copier.frame = Some(copier.manager.capture_output(0, &copier.output, &qh, ()));
+        self.queue.flush()?;
+        Ok(())
+    }
+
+    /// Moves the finished output copy into `out` and returns its size. `None` while the
+    /// copy is under way, or when the compositor could not make it, e.g. because the
+    /// output just changed mode; the next copy is then started as usual.
+    pub fn take_copy(&mut self, out: &mut Vec<u8>) -> anyhow::Result<Option<(u32, u32)>> {
+        let Some(copier) = &mut self.output else {
+            return Ok(None);
+        };
+        let Some(frame) = &copier.frame else {
+            return Ok(None);
+        };
+        let events = &mut self.state.copy;
+        match events.outcome.take() {
+            Some(ready) => {
+                frame.destroy();
+                copier.frame = None;
+                if !ready {
+                    return Ok(None);
+                }
+            }
+            None if events.copying => return Ok(None),
+            None => {
+                // Version 3 lists every buffer type before `buffer_done`; older ones send one.
+                if !events.buffer_done && (frame.version() >= 3 || events.offers.is_empty()) {
+                    return Ok(None);
+                }
+                // Both are blue, green, red in memory, like every other frame source.
+                let Some(&offer) = events.offers.iter().find(|offer| {
+                    matches!(offer.format, Format::Xrgb8888 | Format::Argb8888)
+                        && offer.stride == offer.size.0 * 4
+                }) else {
+                    anyhow::bail!(
+                        "The compositor offers no packed 32-bit shared-memory buffer for output copies"
+                    );
+                };
+                if copier.buffer.as_ref().is_none_or(|buffer| {
+                    buffer.size() != offer.size || buffer.format() != offer.format
+                }) {
+                    let qh = self.queue.handle();
+                    let (width, height) = offer.size;
+                    copier.buffer =
+                        Some(ShmBuffer::new(&self.shm, &qh, width, height, offer.format)?);
+                }
+                let buffer = copier.buffer.as_ref().expect("created above");
+                frame.copy(buffer.buffer());
+                self.queue.flush()?;
+                events.copying = true;
+                return Ok(None);
+            }
+        }
+        let buffer = copier.buffer.as_ref().expect("copied into it");
+        let (width, height) = buffer.size();
+        let row = width as usize * 4;
+        let pixels = &buffer.as_slice()[..row * height as usize];
+        out.clear();
+        if events.y_invert {
+            for line in pixels.chunks_exact(row).rev() {
+                out.extend_from_slice(line);
+            }
+        } else {
+            out.extend_from_slice(pixels);
+        }
+        Ok(Some((width, height)))
+    }
+
+    /// Shows a filtered `size` frame over the output. Skipped during an output copy,
+    /// which must not contain the overlay.
+    pub fn present(&mut self, pixels: &[u8], size: (u32, u32)) -> anyhow::Result<()> {
+        if self.is_copying() {
+            return Ok(());
+        }
+        let output = self.state.size.unwrap_or_default();
+        // Drop frames of the old size, e.g. after the game changed resolution.
+        self.buffers.retain(|buffer| buffer.size() == size);
+        let index = match self.buffers.iter().position(|buffer| !buffer.is_busy()) {
+            Some(index) => index,
+            None if self.buffers.len() < BUFFER_COUNT => {
+                let qh = self.queue.handle();
+                let buffer = ShmBuffer::new(&self.shm, &qh, size.0, size.1, Format::Xrgb8888)?;
+                self.buffers.push(buffer);
+                self.buffers.len() - 1
+            }
+            // The compositor still holds every buffer; this frame is skipped.
+            None => return Ok(()),
+        };
+        let buffer = &mut self.buffers[index];
+        buffer.as_mut_slice()[..pixels.len()].copy_from_slice(pixels);
+
+        buffer.mark_busy();
+        self.surface.attach(Some(buffer.buffer()), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, size.0 as i32, size.1 as i32);
+        self.viewport
+            .set_destination(output.0 as i32, output.1 as i32);
+        self.surface.commit();
+        self.queue.flush()?;
+        self.showing_frame = true;
+        Ok(())
+    }
+
+    /// Hides the last frame until the next one that fits.
+    pub fn show_blank(&mut self) -> anyhow::Result<()> {
+        if !self.showing_frame {
+            return Ok(());
+        }
+        let (width, height) = self.state.size.unwrap_or((1, 1));
+        self.surface.attach(Some(self.blank.buffer()), 0, 0);
+        self.surface.damage_buffer(0, 0, 1, 1);
+        self.viewport
+            .set_destination(width.max(1) as i32, height.max(1) as i32);
+        self.surface.commit();
+        self.queue.flush()?;
+        self.showing_frame = false;
+        Ok(())
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        if let Some(copier) = self.output.take() {
+            if let Some(frame) = copier.frame {
+                frame.destroy();
+            }
+            copier.manager.destroy();
+            if copier.output.version() >= 3 {
+                copier.output.release();
+            }
+        }
+        self.buffers.clear();
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        self.viewport.destroy();
+        self.layer_surface.destroy();
+        self.surface.destroy();
+        let _ = self.queue.flush();
+    }
+}
+
+/// The buffer size that covers a `logical` surface 1:1 at `scale`, in 120ths, rounded
+/// halfway away from zero like fractional-scale-v1 does for toplevels.
+fn buffer_size(logical: (u32, u32), scale: u32) -> (u32, u32) {
+    let scaled = |length: u32| ((u64::from(length) * u64::from(scale) + 60) / 120) as u32;
+    (scaled(logical.0), scaled(logical.1))
+}
+
+/// Picks the one output `selections` name.
+fn select_output(
+    outputs: &[(WlOutput, OutputDetails)],
+    selections: &[MonitorSelection],
+) -> anyhow::Result<WlOutput> {
+    let infos = outputs::infos(outputs);
+    let selected = select_monitors(selections, &infos).map_err(anyhow::Error::msg)?;
+    match selected[..] {
+        [info] => Ok(outputs[info.index - 1].0.clone()),
+        [] => anyhow::bail!("The compositor has no outputs"),
+        _ => anyhow::bail!(
+            "The Wayland overlay enhances one output at a time; pick it with `--monitor`"
+        ),
+    }
+}
+
+/// A buffer type the compositor accepts for an output copy.
+#[derive(Clone, Copy, Debug)]
+struct BufferOffer {
+    format: Format,
+    size: (u32, u32),
+    stride: u32,
+}
+
+/// What the compositor said about the output copy under way.
+#[derive(Default)]
+struct CopyEvents {
+    offers: Vec<BufferOffer>,
+    buffer_done: bool,
+    /// Set once the buffer was handed over.
+    copying: bool,
+    y_invert: bool,
+    /// `true` once the copy is done, `false` if it failed.
+    outcome: Option<bool>,
+}
+
+#[derive(Default)]
+struct OverlayState {
+    /// The surface size in logical pixels, once configured.
+    size: Option<(u32, u32)>,
+    /// From wp-fractional-scale, in 120ths.
+    fractional_scale: Option<u32>,
+    /// From `wl_surface.preferred_buffer_scale`, for compositors without fractional scales.
+    buffer_scale: Option<u32>,
+    closed: bool,
+    copy: CopyEvents,
+}
+
+impl OverlayState {
+    /// Output pixels per logical pixel, in 120ths.
+    fn scale(&self) -> u32 {
+        self.fractional_scale
+            .or(self.buffer_scale.map(|scale| scale * 120))
+            .unwrap_or(120)
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, ()> for OverlayState {
+    fn event(
+        state: &mut Self,
+        layer_surface: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                layer_surface.ack_configure(serial);
+                state.size = Some((width, height));
+            }
+            zwlr_layer_surface_v1::Event::Closed => state.closed = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlSurface, ()> for OverlayState {
+    fn event(
+        state: &mut Self,
+        _: &WlSurface,
+        event: wl_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_surface::Event::PreferredBufferScale { factor } = event {
+            state.buffer_scale = u32::try_from(factor).ok().filter(|&factor| factor > 0);
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for OverlayState {
+    fn event(
+        state: &mut Self,
+        _: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.fractional_scale = Some(scale);
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for OverlayState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let copy = &mut state.copy;
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format: WEnum::Value(format),
+                width,
+                height,
+                stride,
+            } => copy.offers.push(BufferOffer {
+                format,
+                size: (width, height),
+                stride,
+            }),
+            zwlr_screencopy_frame_v1::Event::BufferDone => copy.buffer_done = true,
+            zwlr_screencopy_frame_v1::Event::Flags {
+                flags: WEnum::Value(flags),
+            } => copy.y_invert = flags.contains(Flags::YInvert),
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => copy.outcome = Some(true),
+            zwlr_screencopy_frame_v1::Event::Failed => copy.outcome = Some(false),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, OutputDetails> for OverlayState {
+    fn event(
+        _: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        details: &OutputDetails,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        outputs::output_event(event, details);
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for OverlayState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlBuffer, BufferBusy> for OverlayState {
+    fn event(
+        _: &mut Self,
+        _: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        busy: &BufferBusy,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        shm::buffer_event(event, busy);
+    }
+}
+
+delegate_noop!(OverlayState: WlCompositor);
+delegate_noop!(OverlayState: WlRegion);
+delegate_noop!(OverlayState: ignore WlShm);
+delegate_noop!(OverlayState: WlShmPool);
+delegate_noop!(OverlayState: ZwlrLayerShellV1);
+delegate_noop!(OverlayState: WpViewporter);
+delegate_noop!(OverlayState: WpViewport);
+delegate_noop!(OverlayState: WpFractionalScaleManagerV1);
+delegate_noop!(OverlayState: ZwlrScreencopyManagerV1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_the_surface_to_output_pixels() {
+        assert_eq!(buffer_size((1920, 1080), 120), (1920, 1080));
+        assert_eq!(buffer_size((1280, 720), 180), (1920, 1080));
+        assert_eq!(buffer_size((1280, 720), 240), (2560, 1440));
+        // 1.25 times 2 is 2.5, which rounds up; 1.25 alone rounds down.
+        assert_eq!(buffer_size((2, 1), 150), (3, 1));
+    }
+}
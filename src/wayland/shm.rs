@@ -0,0 +1,107 @@
+//! `wl_shm` buffers backed by a memfd, shared with the compositor.
+
+use std::os::fd::AsFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use wayland_client::protocol::wl_buffer::{self, WlBuffer};
+use wayland_client::protocol::wl_shm::{Format, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{Dispatch, QueueHandle};
+
+use crate::linux::SharedMemory;
+
+/// What a buffer's user data says about it: whether the compositor still reads it.
+pub(super) type BufferBusy = Arc<AtomicBool>;
+
+/// A single buffer in a pool of its own; destroyed and unmapped when dropped.
+pub(super) struct ShmBuffer {
+    buffer: WlBuffer,
+    busy: BufferBusy,
+    memory: SharedMemory,
+    width: u32,
+    height: u32,
+    format: Format,
+}
+
+impl ShmBuffer {
+    /// Allocates a `width` x `height` buffer of 32-bit pixels in `format`.
+    pub fn new<D>(
+        shm: &WlShm,
+        qh: &QueueHandle<D>,
+        width: u32,
+        height: u32,
+        format: Format,
+    ) -> anyhow::Result<Self>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, BufferBusy> + 'static,
+    {
+        let stride = width * 4;
+        let memory = SharedMemory::new((stride * height) as usize)?;
+        let pool = shm.create_pool(memory.as_fd(), memory.len() as i32, qh, ());
+        let busy = BufferBusy::default();
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+            qh,
+            busy.clone(),
+        );
+        // The buffer keeps the memory alive on the compositor's side.
+        pool.destroy();
+        Ok(Self {
+            buffer,
+            busy,
+            memory,
+            width,
+            height,
+            format,
+        })
+    }
+
+    pub fn buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Whether the compositor may still read the buffer, after [`Self::mark_busy`] and
+    /// until it releases it.
+    pub fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+
+    /// Records that the buffer was just attached to a surface.
+    pub fn mark_busy(&self) {
+        self.busy.store(true, Ordering::Release);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.memory.as_mut_slice()
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+/// The `wl_buffer` half of the [`Dispatch`] impls a queue owning [`ShmBuffer`]s needs.
+pub(super) fn buffer_event(event: wl_buffer::Event, busy: &BufferBusy) {
+    if let wl_buffer::Event::Release = event {
+        busy.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,314 @@
+//! X11 frontend: captures with Composite and MIT-SHM and shows the filtered frames in
+//! click-through, override-redirect windows, mirroring what `app` does on Windows.
+//!
+//! Frames are filtered by a [`crate::render`] renderer, on wgpu or the CPU, and shown
+//! from CPU memory. The curve and LUTs apply, but the features only the Direct3D
+//! renderer has (adaptive exposure, flash handling, custom shaders and passes) do not yet.
+
+mod capture;
+mod overlay;
+mod screen;
+mod shm;
+
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use x11rb::connection::Connection;
+use x11rb::protocol::composite::ConnectionExt as _;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::shm::ConnectionExt as _;
+use x11rb::protocol::xfixes::ConnectionExt as _;
+use x11rb::protocol::xproto::{Screen, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::config_watch::ConfigWatcher;
+use crate::error::OverlayError;
+use crate::hotkeys::Enhancement;
+use crate::linux::{self, CaptureThread, FLUSH_DELAY, OverlayEvent, ReloadConfig};
+use crate::options::OverlayOptions;
+use crate::render::{self, FrameRenderer};
+use crate::source::{CpuFrameSource, CpuFrames, FrameSource};
+use crate::target::{CaptureTarget, select_monitors};
+use capture::Grab;
+use overlay::Overlay;
+
+pub use screen::monitor_infos;
+
+/// How often the overlay checks whether a target window moved, resized or closed.
+const TARGET_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often `--frame-stats` prints the capture mailbox counters.
+const FRAME_STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// Capture rate without an `fps_cap`; X11 has no portable way to ask for the refresh rate.
+const DEFAULT_FPS: u32 = 60;
+
+/// Opens a connection and checks it has every extension the overlay relies on.
+pub(crate) fn connect() -> anyhow::Result<(Arc<RustConnection>, usize)> {
+    let (conn, screen) = RustConnection::connect(None).context("Failed to open the X display")?;
+    let shm = conn.shm_query_version()?.reply().ok();
+    if shm.is_none_or(|shm| (shm.major_version, shm.minor_version) < (1, 2)) {
+        anyhow::bail!("The X server does not support MIT-SHM 1.2");
+    }
+    conn.composite_query_version(0, 4)?
+        .reply()
+        .context("The X server does not support Composite")?;
+    conn.xfixes_query_version(5, 0)?
+        .reply()
+        .context("The X server does not support XFixes")?;
+    conn.randr_query_version(1, 5)?
+        .reply()
+        .context("The X server does not support RandR")?;
+    Ok((Arc::new(conn), screen))
+}
+
+/// Runs the overlay until the target window closes or capturing fails.
+pub fn run(options: OverlayOptions) -> anyhow::Result<()> {
+    linux::check_options(Frontend::NAME, &options)?;
+    let (conn, screen) = connect().map_err(OverlayError::Swapchain)?;
+    let renderer = render::create(options.renderer).map_err(OverlayError::Device)?;
+    let (events, receiver) = mpsc::channel();
+    let mut frontend = Frontend {
+        screen: conn.setup().roots[screen].clone(),
+        conn,
+        enhancement: options.settings.enhancement(),
+        next_stats_report: options
+            .frame_stats
+            .then(|| Instant::now() + FRAME_STATS_INTERVAL),
+        options,
+        surfaces: Vec::new(),
+        renderer,
+        events: events.clone(),
+    };
+    frontend
+        .open_surfaces()
+        .map_err(OverlayError::Capture)
+        .context("Failed to start the overlay")?;
+    let _config_watcher = match &frontend.options.config_path {
+        Some(path) => ConfigWatcher::spawn(path, move |config| {
+            let _ = events.send(OverlayEvent::ConfigChanged(Box::new(config)));
+        })
+        .inspect_err(|err| eprintln!("Config reload unavailable: {err:?}"))
+        .ok(),
+        None => None,
+    };
+
+    let mut next_poll = Instant::now();
+    loop {
+        let mut timeout = next_poll.saturating_duration_since(Instant::now());
+        if frontend
+            .surfaces
+            .iter()
+            .any(|surface| surface.renderer.pending())
+        {
+            timeout = timeout.min(FLUSH_DELAY);
+        }
+        match receiver.recv_timeout(timeout) {
+            Ok(OverlayEvent::FrameReady) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(OverlayEvent::CaptureFailed(err)) => return Err(OverlayError::Capture(err).into()),
+            Ok(OverlayEvent::ConfigChanged(config)) => frontend.reload_config(&config)?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        frontend.present()?;
+        let now = Instant::now();
+        if now >= next_poll {
+            frontend.poll()?;
+            next_poll = now + TARGET_POLL_INTERVAL;
+        }
+    }
+}
+
+/// An overlay with the capture feeding it.
+struct Surface {
+    // Declared first so the thread stops before the overlay goes away.
+    _capture: CaptureThread,
+    source: CpuFrameSource,
+    /// One per overlay, since the wgpu renderer hands frames back later.
+    renderer: Box<dyn FrameRenderer>,
+    overlay: Overlay,
+    /// The window being enhanced, for window targets.
+    target: Option<Window>,
+    last_frame: Instant,
+}
+
+struct Frontend {
+    conn: Arc<RustConnection>,
+    screen: Screen,
+    options: OverlayOptions,
+    enhancement: Enhancement,
+    surfaces: Vec<Surface>,
+    /// What each overlay's renderer is made from.
+    renderer: Box<dyn FrameRenderer>,
+    events: Sender<OverlayEvent>,
+    /// When to print the next `--frame-stats` report; `None` when disabled.
+    next_stats_report: Option<Instant>,
+}
+
+impl Frontend {
+    /// Opens an overlay for the window target, or one per selected monitor.
+    fn open_surfaces(&mut self) -> anyhow::Result<()> {
+        let settings = &self.options.settings;
+        let fps = settings.fps_cap.unwrap_or(DEFAULT_FPS);
+        let root = self.screen.root;
+        if settings.target.is_window() {
+            let window = find_target_window(&self.conn, root, &settings.target)?;
+            let rect = screen::client_rect(&self.conn, root, window)?.unwrap_or_default();
+            let overlay = Overlay::new(&self.conn, &self.screen, rect)?;
+            self.spawn_surface(overlay, Grab::Window(window), fps, Some(window))?;
+            return Ok(());
+        }
+
+        let monitors = screen::monitors(&self.conn, root)?;
+        let infos: Vec<_> = monitors.iter().map(|(info, _)| info.clone()).collect();
+        let selected = select_monitors(&settings.monitors, &infos).map_err(anyhow::Error::msg)?;
+        let overlays = selected
+            .iter()
+            .map(|info| Overlay::new(&self.conn, &self.screen, monitors[info.index - 1].1))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Every capture leaves out every overlay, not just its own.
+        let skip: Vec<Window> = overlays.iter().map(Overlay::window).collect();
+        for overlay in overlays {
+            let grab = Grab::Region {
+                rect: overlay.rect(),
+                skip: skip.clone(),
+            };
+            self.spawn_surface(overlay, grab, fps, None)?;
+        }
+        Ok(())
+    }
+
+    fn spawn_surface(
+        &mut self,
+        overlay: Overlay,
+        grab: Grab,
+        fps: u32,
+        target: Option<Window>,
+    ) -> anyhow::Result<()> {
+        let frames = CpuFrames::default();
+        let ready = self.events.clone();
+        let failures = self.events.clone();
+        let capture = capture::spawn(
+            grab,
+            fps,
+            frames.clone(),
+            move || {
+                let _ = ready.send(OverlayEvent::FrameReady);
+            },
+            move |err| {
+                let _ = failures.send(OverlayEvent::CaptureFailed(err));
+            },
+        )?;
+        self.surfaces.push(Surface {
+            _capture: capture,
+            source: frames.source(),
+            renderer: self.renderer.another(),
+            overlay,
+            target,
+            last_frame: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Filters whatever frames arrived since the last call and shows those that are done.
+    fn present(&mut self) -> anyhow::Result<()> {
+        let params = self.enhancement.params();
+        let lut = self.enhancement.lut();
+        for surface in &mut self.surfaces {
+            let filtered = match surface.source.next_frame()? {
+                Some(frame) if surface.overlay.is_visible() => {
+                    surface.last_frame = Instant::now();
+                    surface.renderer.render(&frame, &params, lut)?
+                }
+                Some(_) => None,
+                None => {
+                    let wait = surface.last_frame.elapsed() >= FLUSH_DELAY;
+                    surface.renderer.finished(wait)?
+                }
+            };
+            if let Some(filtered) = filtered
+                && surface.overlay.is_visible()
+            {
+                surface.overlay.present(filtered.pixels, filtered.size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows target windows, keeps the overlays on top and reports frame stats.
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let root = self.screen.root;
+        for surface in &mut self.surfaces {
+            if let Some(window) = surface.target {
+                let rect = screen::client_rect(&self.conn, root, window)
+                    .map_err(|_| anyhow::anyhow!("Target window closed"))?;
+                match rect {
+                    Some(rect) => {
+                        surface.overlay.move_to(rect)?;
+                        surface.overlay.set_visible(true)?;
+                    }
+                    None => surface.overlay.set_visible(false)?,
+                }
+            }
+            surface.overlay.raise()?;
+        }
+        // Nothing is selected on the overlays but exposures, and frames repaint anyway.
+        while self.conn.poll_for_event()?.is_some() {}
+        self.conn.flush()?;
+
+        if let Some(report_at) = self.next_stats_report
+            && Instant::now() >= report_at
+        {
+            for (i, surface) in self.surfaces.iter().enumerate() {
+                let stats = surface.source.stats();
+                eprintln!(
+                    "Overlay {}: {} captured, {} rendered, {} dropped, {} reused",
+                    i + 1,
+                    stats.published,
+                    stats.consumed,
+                    stats.dropped,
+                    stats.reused
+                );
+            }
+            self.next_stats_report = Some(Instant::now() + FRAME_STATS_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+impl ReloadConfig for Frontend {
+    const NAME: &'static str = "The X11 overlay";
+
+    fn options(&mut self) -> &mut OverlayOptions {
+        &mut self.options
+    }
+
+    fn enhancement(&mut self) -> &mut Enhancement {
+        &mut self.enhancement
+    }
+
+    fn stop_capture(&mut self) {
+        self.surfaces.clear();
+    }
+
+    fn start_capture(&mut self) -> anyhow::Result<()> {
+        self.open_surfaces()
+    }
+}
+
+fn find_target_window(
+    conn: &RustConnection,
+    root: Window,
+    target: &CaptureTarget,
+) -> anyhow::Result<Window> {
+    if let CaptureTarget::Hwnd(hwnd) = target {
+        let not_a_window = || anyhow::anyhow!("{target} is not a window");
+        let window = Window::try_from(*hwnd).map_err(|_| not_a_window())?;
+        screen::client_rect(conn, root, window).map_err(|_| not_a_window())?;
+        return Ok(window);
+    }
+    let windows = screen::windows(conn, root)?;
+    let window = target
+        .select(&windows)
+        .ok_or_else(|| anyhow::anyhow!("No window matches {target}"))?;
+    Ok(window.hwnd as Window)
+}
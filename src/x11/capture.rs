@@ -0,0 +1,447 @@
+//! Grabs frames on a thread of its own and hands the newest one to the renderer through
+//! [`CpuFrames`], like the Windows capture thread does with its texture ring.
+//!
+//! Everything is read from Composite's offscreen pixmaps rather than the screen, so the
+//! overlay, which sits on top of what it captures, never ends up in its own input.
+
+use std::sync::Arc;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::composite::{ConnectionExt as _, Redirect};
+use x11rb::protocol::shm::ConnectionExt as _;
+use x11rb::protocol::xproto::{
+    ChangeWindowAttributesAux, ConnectionExt as _, EventMask, ImageFormat, MapState, Pixmap,
+    Window, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+use super::shm::ShmSegment;
+use crate::linux::CaptureThread;
+use crate::source::CpuFrames;
+use crate::target::Rect;
+
+/// Every pixmap the overlay reads is 32 bits per pixel, blue first.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// What a capture thread grabs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Grab {
+    /// A monitor, composed from the top-level windows on it other than `skip`.
+    Region { rect: Rect, skip: Vec<Window> },
+    /// The contents of one window.
+    Window(Window),
+}
+
+/// Reads window pixmaps through a shared-memory segment.
+///
+/// The pixmaps and the geometry they are read with are kept between frames and only
+/// looked up again after the structure events selected in [`Grabber::new`] say a window
+/// was mapped, unmapped, moved, resized, restacked or destroyed.
+pub(super) struct Grabber {
+    conn: Arc<RustConnection>,
+    root: Window,
+    shm: ShmSegment,
+    /// Bottom to top, so later reads paint over earlier ones.
+    named: Vec<NamedPixmap>,
+    /// Set by structure events; `named` is rebuilt before the next read.
+    stale: bool,
+}
+
+/// A window's pixmap, named once per configuration, and the part of it a frame reads.
+struct NamedPixmap {
+    pixmap: Pixmap,
+    /// Where the read starts within the pixmap.
+    origin: (i32, i32),
+    /// What is read; in root coordinates for regions.
+    area: Rect,
+    /// Where the read lands in the shared segment.
+    offset: usize,
+}
+
+impl Grabber {
+    /// Redirects what `grab` covers, so its pixmaps can be named, and listens for the
+    /// changes that invalidate them.
+    pub fn new(conn: &Arc<RustConnection>, root: Window, grab: &Grab) -> anyhow::Result<Self> {
+        let (watched, mask) = match grab {
+            Grab::Region { .. } => {
+                conn.composite_redirect_subwindows(root, Redirect::AUTOMATIC)?
+                    .check()?;
+                (root, EventMask::SUBSTRUCTURE_NOTIFY)
+            }
+            Grab::Window(window) => {
+                conn.composite_redirect_window(*window, Redirect::AUTOMATIC)?
+                    .check()?;
+                (*window, EventMask::STRUCTURE_NOTIFY)
+            }
+        };
+        conn.change_window_attributes(watched, &ChangeWindowAttributesAux::new().event_mask(mask))?
+            .check()?;
+        Ok(Self {
+            conn: conn.clone(),
+            root,
+            shm: ShmSegment::new(conn, 0)?,
+            named: Vec::new(),
+            stale: true,
+        })
+    }
+
+    /// Fills `out` with the current contents of `grab` and returns their size, or `None`
+    /// while there is nothing to show, e.g. a minimized window. Fails once a grabbed
+    /// window is gone.
+    pub fn grab(&mut self, grab: &Grab, out: &mut Vec<u8>) -> anyhow::Result<Option<(u32, u32)>> {
+        while let Some(event) = self.conn.poll_for_event()? {
+            let window = match event {
+                Event::ConfigureNotify(event) => event.window,
+                Event::MapNotify(event) => event.window,
+                Event::UnmapNotify(event) => event.window,
+                Event::DestroyNotify(event) if *grab == Grab::Window(event.window) => {
+                    anyhow::bail!("Target window closed");
+                }
+                Event::DestroyNotify(event) => event.window,
+                Event::ReparentNotify(event) => event.window,
+                Event::CirculateNotify(event) => event.window,
+                _ => continue,
+            };
+            // The overlays are raised all the time, but never read.
+            if !matches!(grab, Grab::Region { skip, .. } if skip.contains(&window)) {
+                self.stale = true;
+            }
+        }
+        match grab {
+            Grab::Region { rect, skip } => self.grab_region(*rect, skip, out).map(Some),
+            Grab::Window(window) => self.grab_window(*window, out),
+        }
+    }
+
+    fn grab_region(
+        &mut self,
+        rect: Rect,
+        skip: &[Window],
+        out: &mut Vec<u8>,
+    ) -> anyhow::Result<(u32, u32)> {
+        if self.stale {
+            self.name_region(rect, skip)?;
+        }
+        let size = (rect.width(), rect.height());
+        out.clear();
+        out.resize(size.0 as usize * size.1 as usize * BYTES_PER_PIXEL, 0);
+        let read = self.read_pixmaps()?;
+        for (named, _) in self.named.iter().zip(read).filter(|(_, read)| *read) {
+            blit(
+                &self.shm.as_slice()[named.offset..],
+                (named.area.width(), named.area.height()),
+                out,
+                size.0,
+                (named.area.left - rect.left, named.area.top - rect.top),
+            );
+        }
+        Ok(size)
+    }
+
+    /// Names the pixmap of every viewable top-level window overlapping `rect`, other than
+    /// `skip`, and gives each its own part of the segment so all of them can be read at
+    /// once.
+    fn name_region(&mut self, rect: Rect, skip: &[Window]) -> anyhow::Result<()> {
+        self.release();
+        // Children come bottom to top, so later windows paint over earlier ones.
+        let children = self.conn.query_tree(self.root)?.reply()?.children;
+        let queries = children
+            .iter()
+            .filter(|window| !skip.contains(window))
+            .map(|&window| {
+                let attributes = self.conn.get_window_attributes(window)?;
+                let geometry = self.conn.get_geometry(window)?;
+                Ok((window, attributes, geometry))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut len = 0;
+        for (window, attributes, geometry) in queries {
+            // Windows may disappear at any point; they are simply left out.
+            let (Ok(attributes), Ok(geometry)) = (attributes.reply(), geometry.reply()) else {
+                continue;
+            };
+            if attributes.map_state != MapState::VIEWABLE
+                || attributes.class == WindowClass::INPUT_ONLY
+            {
+                continue;
+            }
+            let border = 2 * i32::from(geometry.border_width);
+            let outer = Rect {
+                left: geometry.x.into(),
+                top: geometry.y.into(),
+                right: i32::from(geometry.x) + i32::from(geometry.width) + border,
+                bottom: i32::from(geometry.y) + i32::from(geometry.height) + border,
+            };
+            let Some(visible) = outer.intersection(&rect) else {
+                continue;
+            };
+            // Left unchecked: a window unmapped since is caught when reading fails.
+            let pixmap = self.conn.generate_id()?;
+            self.conn.composite_name_window_pixmap(window, pixmap)?;
+            self.named.push(NamedPixmap {
+                pixmap,
+                origin: (visible.left - outer.left, visible.top - outer.top),
+                area: visible,
+                offset: len,
+            });
+            len += visible.width() as usize * visible.height() as usize * BYTES_PER_PIXEL;
+        }
+        self.reserve(len)
+    }
+
+    fn grab_window(
+        &mut self,
+        window: Window,
+        out: &mut Vec<u8>,
+    ) -> anyhow::Result<Option<(u32, u32)>> {
+        if self.stale {
+            self.name_window(window)?;
+        }
+        let Some(named) = self.named.first() else {
+            return Ok(None);
+        };
+        let size = (named.area.width(), named.area.height());
+        if !self.read_pixmaps()?[0] {
+            return Ok(None);
+        }
+        out.clear();
+        let len = size.0 as usize * size.1 as usize * BYTES_PER_PIXEL;
+        out.extend_from_slice(&self.shm.as_slice()[..len]);
+        Ok(Some(size))
+    }
+
+    fn name_window(&mut self, window: Window) -> anyhow::Result<()> {
+        self.release();
+        let attributes = self.conn.get_window_attributes(window)?;
+        let geometry = self.conn.get_geometry(window)?;
+        let closed = |_| anyhow::anyhow!("Target window closed");
+        let (attributes, geometry) = (
+            attributes.reply().map_err(closed)?,
+            geometry.reply().map_err(closed)?,
+        );
+        // Unmapped windows have no pixmap; the map brings a structure event.
+        if attributes.map_state != MapState::VIEWABLE {
+            return Ok(());
+        }
+        let border = i32::from(geometry.border_width);
+        let area = Rect {
+            left: 0,
+            top: 0,
+            right: geometry.width.into(),
+            bottom: geometry.height.into(),
+        };
+        let pixmap = self.conn.generate_id()?;
+        self.conn.composite_name_window_pixmap(window, pixmap)?;
+        self.named.push(NamedPixmap {
+            pixmap,
+            origin: (border, border),
+            area,
+            offset: 0,
+        });
+        self.reserve(area.width() as usize * area.height() as usize * BYTES_PER_PIXEL)
+    }
+
+    /// Grows the shared segment to at least `len` bytes.
+    fn reserve(&mut self, len: usize) -> anyhow::Result<()> {
+        if self.shm.len() < len {
+            self.shm = ShmSegment::new(&self.conn, len)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a read for every named pixmap before waiting for any of them, and says which
+    /// succeeded. A failed read, from a window that went away in between, marks the
+    /// pixmaps stale.
+    fn read_pixmaps(&mut self) -> anyhow::Result<Vec<bool>> {
+        let requests = self
+            .named
+            .iter()
+            .map(|named| {
+                self.conn.shm_get_image(
+                    named.pixmap,
+                    named.origin.0 as i16,
+                    named.origin.1 as i16,
+                    named.area.width() as u16,
+                    named.area.height() as u16,
+                    u32::MAX,
+                    ImageFormat::Z_PIXMAP.into(),
+                    self.shm.id(),
+                    named.offset as u32,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let read: Vec<bool> = requests
+            .into_iter()
+            .map(|request| request.reply().is_ok())
+            .collect();
+        if read.contains(&false) {
+            self.stale = true;
+        }
+        Ok(read)
+    }
+
+    /// Frees the named pixmaps before they are named again.
+    fn release(&mut self) {
+        for named in self.named.drain(..) {
+            let _ = self.conn.free_pixmap(named.pixmap);
+        }
+        self.stale = false;
+    }
+}
+
+impl Drop for Grabber {
+    fn drop(&mut self) {
+        self.release();
+        let _ = self.conn.flush();
+    }
+}
+
+/// Copies a tightly packed `size` image from `src` into the `dst_width` wide `dst` at
+/// `at`, forcing alpha opaque.
+fn blit(src: &[u8], size: (u32, u32), dst: &mut [u8], dst_width: u32, at: (i32, i32)) {
+    let row_len = size.0 as usize * BYTES_PER_PIXEL;
+    let dst_pitch = dst_width as usize * BYTES_PER_PIXEL;
+    for (y, row) in src.chunks_exact(row_len).take(size.1 as usize).enumerate() {
+        let start = (at.1 as usize + y) * dst_pitch + at.0 as usize * BYTES_PER_PIXEL;
+        let target = &mut dst[start..start + row_len];
+        target.copy_from_slice(row);
+        for pixel in target.chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel[3] = u8::MAX;
+        }
+    }
+}
+
+/// Grabs `grab` on a thread and connection of its own up to `fps` times a second.
+pub(super) fn spawn(
+    grab: Grab,
+    fps: u32,
+    frames: CpuFrames,
+    on_frame: impl Fn() + Send + 'static,
+    on_error: impl FnOnce(anyhow::Error) + Send + 'static,
+) -> anyhow::Result<CaptureThread> {
+    let (conn, screen) = super::connect()?;
+    let root = conn.setup().roots[screen].root;
+    let mut grabber = Grabber::new(&conn, root, &grab)?;
+    CaptureThread::spawn(
+        "x11-capture",
+        fps,
+        frames,
+        move |_, pixels| grabber.grab(&grab, pixels),
+        on_frame,
+        on_error,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::PixelFormat;
+    use crate::source::{FrameSource, Pattern, SyntheticSource};
+    use crate::x11::overlay::Overlay;
+    use x11rb::protocol::shape::{self, ConnectionExt as _};
+    use x11rb::protocol::xproto::{CreateGCAux, CreateWindowAux};
+
+    #[test]
+    fn blit_places_rows_and_makes_alpha_opaque() {
+        let src = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0, 10, 11, 12, 0];
+        let mut dst = vec![0; 3 * 3 * BYTES_PER_PIXEL];
+        blit(&src, (2, 2), &mut dst, 3, (1, 1));
+        let pixel = |x: usize, y: usize| {
+            let start = (y * 3 + x) * BYTES_PER_PIXEL;
+            dst[start..start + BYTES_PER_PIXEL].to_vec()
+        };
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(1, 1), [1, 2, 3, 255]);
+        assert_eq!(pixel(2, 1), [4, 5, 6, 255]);
+        assert_eq!(pixel(1, 2), [7, 8, 9, 255]);
+        assert_eq!(pixel(2, 2), [10, 11, 12, 255]);
+        assert_eq!(pixel(0, 2), [0, 0, 0, 0]);
+    }
+
+    /// Runs against a real X server: `xvfb-run cargo test x11 -- --ignored`.
+    #[test]
+    #[ignore = "needs an X server"]
+    fn captures_a_pattern_window_without_the_overlay() {
+        let (conn, screen) = crate::x11::connect().unwrap();
+        let screen = &conn.setup().roots[screen];
+        let (width, height) = (64, 32);
+
+        // The "game": a window showing a dark ramp from the synthetic source.
+        let game = conn.generate_id().unwrap();
+        conn.create_window(
+            screen.root_depth,
+            game,
+            screen.root,
+            0,
+            0,
+            width,
+            height,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &CreateWindowAux::new().override_redirect(1),
+        )
+        .unwrap();
+        conn.map_window(game).unwrap();
+
+        // The overlay covers the game but must neither end up in the capture nor take
+        // its clicks.
+        let rect = Rect {
+            left: 0,
+            top: 0,
+            right: width.into(),
+            bottom: height.into(),
+        };
+        let overlay = Overlay::new(&conn, screen, rect).unwrap();
+        let grab = Grab::Region {
+            rect,
+            skip: vec![overlay.window()],
+        };
+        let mut grabber = Grabber::new(&conn, screen.root, &grab).unwrap();
+
+        // Painted once redirected, so the game's pixmap holds the pattern.
+        let mut source = SyntheticSource::new(
+            Pattern::DarkRamp,
+            width.into(),
+            height.into(),
+            PixelFormat::Bgra8,
+        );
+        let pattern = source.next_frame().unwrap().unwrap();
+        let pattern = pattern.pixels.as_cpu().unwrap().to_vec();
+        let gc = conn.generate_id().unwrap();
+        conn.create_gc(gc, game, &CreateGCAux::new()).unwrap();
+        conn.put_image(
+            ImageFormat::Z_PIXMAP,
+            game,
+            gc,
+            width,
+            height,
+            0,
+            0,
+            0,
+            screen.root_depth,
+            &pattern,
+        )
+        .unwrap();
+        conn.get_input_focus().unwrap().reply().unwrap();
+
+        let mut pixels = Vec::new();
+        let size = grabber.grab(&grab, &mut pixels).unwrap();
+        assert_eq!(size, Some((width.into(), height.into())));
+        let rgb = |pixels: &[u8]| -> Vec<u8> {
+            pixels
+                .chunks_exact(BYTES_PER_PIXEL)
+                .flat_map(|pixel| pixel[..3].to_vec())
+                .collect()
+        };
+        assert_eq!(rgb(&pixels), rgb(&pattern));
+
+        let input = conn
+            .shape_get_rectangles(overlay.window(), shape::SK::INPUT)
+            .unwrap()
+            .reply()
+            .unwrap();
+        assert!(input.rectangles.is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! The overlay window: override-redirect, kept on top, and with an empty input shape so
+//! every click goes through to the game underneath.
+
+use std::sync::Arc;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::shape::SK;
+use x11rb::protocol::shm::ConnectionExt as _;
+use x11rb::protocol::xfixes::ConnectionExt as _;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConfigureWindowAux, ConnectionExt as _, CreateGCAux, CreateWindowAux, EventMask,
+    Gcontext, ImageFormat, PropMode, Screen, StackMode, Window, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use super::shm::ShmSegment;
+use crate::filter::PixelFormat;
+use crate::target::Rect;
+
+const TITLE: &[u8] = b"Ban-Shadow Overlay";
+
+pub(super) struct Overlay {
+    conn: Arc<RustConnection>,
+    window: Window,
+    gc: Gcontext,
+    depth: u8,
+    rect: Rect,
+    /// What the next frame is filtered in and presented from.
+    shm: ShmSegment,
+    visible: bool,
+}
+
+impl Overlay {
+    /// Opens and shows an overlay covering `rect`.
+    pub fn new(conn: &Arc<RustConnection>, screen: &Screen, rect: Rect) -> anyhow::Result<Self> {
+        let window = conn.generate_id()?;
+        conn.create_window(
+            screen.root_depth,
+            window,
+            screen.root,
+            rect.left as i16,
+            rect.top as i16,
+            rect.width().max(1) as u16,
+            rect.height().max(1) as u16,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &CreateWindowAux::new()
+                .override_redirect(1)
+                .event_mask(EventMask::EXPOSURE),
+        )?
+        .check()?;
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_NAME,
+            AtomEnum::STRING,
+            TITLE,
+        )?;
+
+        // An empty input region: the pointer never lands on the overlay.
+        let region = conn.generate_id()?;
+        conn.xfixes_create_region(region, &[])?;
+        conn.xfixes_set_window_shape_region(window, SK::INPUT, 0, 0, region)?;
+        conn.xfixes_destroy_region(region)?;
+
+        let gc = conn.generate_id()?;
+        conn.create_gc(gc, window, &CreateGCAux::new().graphics_exposures(0))?;
+        let shm = ShmSegment::new(conn, frame_len(rect))?;
+        let mut overlay = Self {
+            conn: conn.clone(),
+            window,
+            gc,
+            depth: screen.root_depth,
+            rect,
+            shm,
+            visible: false,
+        };
+        overlay.set_visible(true)?;
+        Ok(overlay)
+    }
+
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Moves the overlay over `rect`, e.g. after the target window moved or resized.
+    pub fn move_to(&mut self, rect: Rect) -> anyhow::Result<()> {
+        if rect == self.rect {
+            return Ok(());
+        }
+        self.conn.configure_window(
+            self.window,
+            &ConfigureWindowAux::new()
+                .x(rect.left)
+                .y(rect.top)
+                .width(rect.width().max(1))
+                .height(rect.height().max(1)),
+        )?;
+        if self.shm.len() < frame_len(rect) {
+            self.shm = ShmSegment::new(&self.conn, frame_len(rect))?;
+        }
+        self.rect = rect;
+        Ok(())
+    }
+
+    pub fn set_visible(&mut self, visible: bool) -> anyhow::Result<()> {
+        if visible == self.visible {
+            return Ok(());
+        }
+        if visible {
+            self.conn.map_window(self.window)?;
+            self.raise()?;
+        } else {
+            self.conn.unmap_window(self.window)?;
+        }
+        self.conn.flush()?;
+        self.visible = visible;
+        Ok(())
+    }
+
+    /// Puts the overlay back on top; override-redirect windows are not kept there by the
+    /// window manager, so this is repeated as other windows are raised.
+    pub fn raise(&self) -> anyhow::Result<()> {
+        self.conn.configure_window(
+            self.window,
+            &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+        )?;
+        Ok(())
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Shows a filtered `size` frame. A frame of another size than the overlay, as while
+    /// a window is being resized, is cropped.
+    pub fn present(&mut self, pixels: &[u8], size: (u32, u32)) -> anyhow::Result<()> {
+        if !self.visible {
+            return Ok(());
+        }
+        let width = size.0.min(self.rect.width()) as usize;
+        let height = size.1.min(self.rect.height()) as usize;
+        let bpp = PixelFormat::Bgra8.bytes_per_pixel();
+        let row_len = width * bpp;
+        let out = &mut self.shm.as_mut_slice()[..row_len * height];
+        for (row, target) in pixels
+            .chunks_exact(size.0 as usize * bpp)
+            .zip(out.chunks_exact_mut(row_len))
+        {
+            target.copy_from_slice(&row[..row_len]);
+        }
+
+        self.conn.shm_put_image(
+            self.window,
+            self.gc,
+            width as u16,
+            height as u16,
+            0,
+            0,
+            width as u16,
+            height as u16,
+            0,
+            0,
+            self.depth,
+            ImageFormat::Z_PIXMAP.into(),
+            false,
+            self.shm.id(),
+            0,
+        )?;
+        // The segment is rewritten for the next frame, so wait until the server read it.
+        self.conn.get_input_focus()?.reply()?;
+        Ok(())
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        let _ = self.conn.free_gc(self.gc);
+        let _ = self.conn.destroy_window(self.window);
+        let _ = self.conn.flush();
+    }
+}
+
+fn frame_len(rect: Rect) -> usize {
+    rect.width() as usize * rect.height() as usize * PixelFormat::Bgra8.bytes_per_pixel()
+}
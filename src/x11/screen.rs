@@ -0,0 +1,133 @@
+//! What is on the X screen: RandR monitors and the window manager's client windows.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, MapState, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::target::{MonitorInfo, Rect, WindowInfo};
+
+/// Describes the connected monitors for `--monitor` and `--list-monitors`.
+pub fn monitor_infos() -> anyhow::Result<Vec<MonitorInfo>> {
+    let (conn, screen) = RustConnection::connect(None)?;
+    let root = conn.setup().roots[screen].root;
+    Ok(monitors(&conn, root)?
+        .into_iter()
+        .map(|(info, _)| info)
+        .collect())
+}
+
+/// The active monitors with where they sit on the screen, in RandR order. Without a
+/// monitor marked primary, the first one is.
+pub(super) fn monitors(
+    conn: &RustConnection,
+    root: Window,
+) -> anyhow::Result<Vec<(MonitorInfo, Rect)>> {
+    let reply = conn.randr_get_monitors(root, true)?.reply()?;
+    let has_primary = reply.monitors.iter().any(|monitor| monitor.primary);
+    reply
+        .monitors
+        .iter()
+        .enumerate()
+        .map(|(i, monitor)| {
+            let name = conn.get_atom_name(monitor.name)?.reply()?.name;
+            let name = String::from_utf8_lossy(&name).into_owned();
+            let info = MonitorInfo {
+                index: i + 1,
+                name: name.clone(),
+                device_name: name,
+                primary: monitor.primary || (!has_primary && i == 0),
+            };
+            let rect = Rect {
+                left: monitor.x.into(),
+                top: monitor.y.into(),
+                right: i32::from(monitor.x) + i32::from(monitor.width),
+                bottom: i32::from(monitor.y) + i32::from(monitor.height),
+            };
+            Ok((info, rect))
+        })
+        .collect()
+}
+
+/// The windows the window manager lists in `_NET_CLIENT_LIST`; `hwnd` is the window id.
+pub(super) fn windows(conn: &RustConnection, root: Window) -> anyhow::Result<Vec<WindowInfo>> {
+    let client_list = atom(conn, b"_NET_CLIENT_LIST")?;
+    let reply = conn
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?;
+    let Some(windows) = reply.value32() else {
+        anyhow::bail!("The window manager does not publish _NET_CLIENT_LIST");
+    };
+    let net_wm_name = atom(conn, b"_NET_WM_NAME")?;
+    let net_wm_pid = atom(conn, b"_NET_WM_PID")?;
+    Ok(windows
+        .map(|window| WindowInfo {
+            hwnd: window as isize,
+            title: title(conn, window, net_wm_name).unwrap_or_default(),
+            process_name: process_name(conn, window, net_wm_pid).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// The client area of `window` on the screen, or `None` while it is not shown. Fails
+/// once the window is gone.
+pub(super) fn client_rect(
+    conn: &RustConnection,
+    root: Window,
+    window: Window,
+) -> anyhow::Result<Option<Rect>> {
+    let attributes = conn.get_window_attributes(window)?;
+    let geometry = conn.get_geometry(window)?;
+    let origin = conn.translate_coordinates(window, root, 0, 0)?;
+    let (attributes, geometry, origin) = (attributes.reply()?, geometry.reply()?, origin.reply()?);
+    if attributes.map_state != MapState::VIEWABLE {
+        return Ok(None);
+    }
+    let (left, top) = (i32::from(origin.dst_x), i32::from(origin.dst_y));
+    Ok(Some(Rect {
+        left,
+        top,
+        right: left + i32::from(geometry.width),
+        bottom: top + i32::from(geometry.height),
+    }))
+}
+
+pub(super) fn atom(conn: &RustConnection, name: &[u8]) -> anyhow::Result<u32> {
+    Ok(conn.intern_atom(false, name)?.reply()?.atom)
+}
+
+fn title(conn: &RustConnection, window: Window, net_wm_name: u32) -> anyhow::Result<String> {
+    let utf8 = string_property(conn, window, net_wm_name)?;
+    let title = match utf8 {
+        Some(title) => title,
+        None => string_property(conn, window, AtomEnum::WM_NAME.into())?.unwrap_or_default(),
+    };
+    Ok(title)
+}
+
+fn string_property(
+    conn: &RustConnection,
+    window: Window,
+    property: u32,
+) -> anyhow::Result<Option<String>> {
+    let reply = conn
+        .get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX)?
+        .reply()?;
+    Ok((reply.format == 8 && !reply.value.is_empty())
+        .then(|| String::from_utf8_lossy(&reply.value).into_owned()))
+}
+
+/// The executable behind `window`, from `_NET_WM_PID`; only local clients have one.
+fn process_name(conn: &RustConnection, window: Window, net_wm_pid: u32) -> anyhow::Result<String> {
+    let reply = conn
+        .get_property(false, window, net_wm_pid, AtomEnum::CARDINAL, 0, 1)?
+        .reply()?;
+    let Some(pid) = reply.value32().and_then(|mut values| values.next()) else {
+        return Ok(String::new());
+    };
+    let exe = std::fs::read_link(format!("/proc/{pid}/exe"))?;
+    Ok(exe
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default())
+}
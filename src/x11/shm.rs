@@ -0,0 +1,61 @@
+//! MIT-SHM segments backed by a memfd, so images never travel over the X socket.
+
+use std::os::fd::AsFd;
+use std::sync::Arc;
+
+use anyhow::Context;
+use x11rb::connection::Connection;
+use x11rb::protocol::shm::{ConnectionExt as _, Seg};
+use x11rb::rust_connection::RustConnection;
+
+use crate::linux::SharedMemory;
+
+/// Memory shared with the X server; detached and unmapped when dropped.
+pub(super) struct ShmSegment {
+    conn: Arc<RustConnection>,
+    seg: Seg,
+    memory: SharedMemory,
+}
+
+impl ShmSegment {
+    pub fn new(conn: &Arc<RustConnection>, len: usize) -> anyhow::Result<Self> {
+        let memory = SharedMemory::new(len)?;
+        let seg = conn
+            .generate_id()
+            .map_err(anyhow::Error::from)
+            .and_then(|seg| {
+                let fd = memory.as_fd().try_clone_to_owned()?;
+                conn.shm_attach_fd(seg, fd, false)?.check()?;
+                Ok(seg)
+            })
+            .context("Failed to share memory with the X server")?;
+        Ok(Self {
+            conn: conn.clone(),
+            seg,
+            memory,
+        })
+    }
+
+    pub fn id(&self) -> Seg {
+        self.seg
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.memory.as_mut_slice()
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        let _ = self.conn.shm_detach(self.seg);
+        let _ = self.conn.flush();
+    }
+}